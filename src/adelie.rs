@@ -0,0 +1,79 @@
+//! Adélie Linux rootfs support.
+//!
+//! `setup --distro adelie` creates the rootfs from the Adélie "mini"
+//! rootfs archive instead of the Alpine minirootfs. Adélie also uses apk,
+//! so once its `system` and `user` repositories are written the rest of
+//! ALPack (and the `aptree` package tree) works the same way.
+
+use crate::http;
+use crate::utils::is_offline;
+use regex::Regex;
+use sandbox_utils::app_arch;
+use scraper::{Html, Selector};
+use std::error::Error;
+
+/// Base URL of the Adélie distribution files.
+const ADELIE_MIRROR: &str = "https://distfiles.adelielinux.org/adelie/";
+
+/// Adélie release the rootfs is created from.
+pub const ADELIE_RELEASE: &str = "1.0-beta6";
+
+/// Default packages installed into a new Adélie rootfs.
+pub const ADELIE_PACKAGES: &str = "build-tools autoconf automake cmake libtool xz";
+
+/// Distributions `setup --distro` can create.
+pub const DISTROS: [&str; 2] = ["alpine", "adelie"];
+
+/// Returns the Adélie name of the architecture ALPack runs for.
+fn adelie_arch() -> String {
+    match app_arch().as_str() {
+        "x86" => "pmmx".to_string(),
+        "ppc64le" => "ppc64".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Finds the newest mini rootfs archive of the release.
+///
+/// # Returns
+/// - `Ok(String)` with the archive URL.
+/// - `Err` if the listing cannot be fetched or has no archive for this
+///   architecture.
+pub fn rootfs_url() -> Result<String, Box<dyn Error>> {
+    if is_offline() {
+        return Err("--distro adelie needs network access to find the rootfs archive".into());
+    }
+
+    let arch = adelie_arch();
+    let url = format!("{ADELIE_MIRROR}{ADELIE_RELEASE}/iso/");
+    let listing = http::get(&url).call()?.body_mut().read_to_string()?;
+
+    let document = Html::parse_document(&listing);
+    let selector = Selector::parse("a").map_err(|e| e.to_string())?;
+    let re = Regex::new(&format!(
+        r"^adelie-rootfs-mini-{}-{}-(\d+)\.txz$",
+        regex::escape(&arch),
+        regex::escape(ADELIE_RELEASE)
+    ))?;
+
+    document
+        .select(&selector)
+        .filter_map(|element| element.value().attr("href"))
+        .filter_map(|href| {
+            let date: u64 = re.captures(href)?[1].parse().ok()?;
+            Some((date, href))
+        })
+        .max()
+        .map(|(_, href)| format!("{url}{href}"))
+        .ok_or_else(|| {
+            format!("No Adélie {ADELIE_RELEASE} rootfs found for '{arch}' at {url}").into()
+        })
+}
+
+/// Returns the repositories of an Adélie rootfs.
+pub fn repositories() -> Vec<String> {
+    ["system", "user"]
+        .iter()
+        .map(|repo| format!("{ADELIE_MIRROR}{ADELIE_RELEASE}/{repo}"))
+        .collect()
+}
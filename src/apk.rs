@@ -4,11 +4,28 @@
 //! Alpine `apk` manager. It handles command aliasing (e.g., 'install' to 'add')
 //! and ensures commands are executed within the correct rootfs context.
 
+use crate::qemu;
 use crate::settings::settings_rootfs_dir;
-use crate::utils::map_result;
-use sandbox_utils::{missing_arg, SandBox, SandBoxConfig};
+use crate::snapshot;
+use crate::utils::{bind_args, check_exit_status, configured_binds, map_result, with_apk_cache};
+use regex::Regex;
+use sandbox_utils::{get_cmd_box, missing_arg, SandBox, SandBoxConfig, SEPARATOR};
 use std::error::Error;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+/// Location of the apk database lock inside the rootfs.
+const DB_LOCK: &str = "lib/apk/db/lock";
+
+/// Mount point inside the rootfs for directories holding local packages.
+const LOCAL_PKG_DIR: &str = "mnt/alpack-local";
+
+/// Location inside the rootfs where simulated transactions are logged.
+const SIMULATE_LOG: &str = "var/tmp/alpack-simulate.log";
 
 /// Controller for interacting with the Alpine Package Manager.
 pub struct Apk {
@@ -45,45 +62,313 @@ impl Apk {
     /// - `Err` if no command is provided or if execution fails.
     pub fn run(&self) -> Result<(), Box<dyn Error>> {
         match &self.command.as_deref() {
-            Some("add") | Some("install") => self.run_apk("apk add"),
+            Some("add") | Some("install") => self.run_add(),
             Some("del") | Some("remove") => self.run_apk("apk del"),
-            Some("-u") | Some("update") => self.run_apk("apk update && apk upgrade"),
+            Some("-u") | Some("update") => self.run_upgrade(),
             Some("-s") | Some("search") => self.run_apk("apk search"),
+            Some("fix") if self.remaining_args.iter().any(|a| a == "--db-lock") => {
+                self.clear_db_lock()
+            }
             Some("fix") => self.run_apk("apk fix"),
             Some(other) => self.run_apk(&format!("apk {other}")),
             None => missing_arg!("apk"),
         }
     }
 
+    /// Updates the indexes and upgrades the rootfs, rolling back on failure.
+    ///
+    /// A snapshot is taken before the transaction. If `apk upgrade` or the
+    /// post-check (apk still starting with the upgraded libraries) fails,
+    /// the snapshot is restored, so a bad upgrade never leaves the rootfs
+    /// unusable. `--no-rollback` skips the snapshot; simulations never
+    /// take one.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the upgrade succeeds.
+    /// - `Err` with the upgrade error, after restoring the snapshot.
+    fn run_upgrade(&self) -> Result<(), Box<dyn Error>> {
+        let mut args = self.remaining_args.clone();
+        let before = args.len();
+        args.retain(|a| a != "--no-rollback");
+        let rollback = args.len() == before && !args.iter().any(|a| a == "--simulate");
+
+        let rootfs = self.rootfs.clone().unwrap_or_else(settings_rootfs_dir);
+        let cmd = "apk update && apk upgrade";
+
+        if !rollback {
+            return Apk::new(self.command.clone(), args, Some(rootfs)).run_apk(cmd);
+        }
+
+        let snap = snapshot::take(&rootfs, "pre-update")?;
+        let checked = format!("{cmd} {} && apk --version > /dev/null", args.join(" "));
+
+        match Apk::new(self.command.clone(), Vec::new(), Some(rootfs.clone())).run_apk(&checked) {
+            Ok(()) => snapshot::remove(&snap),
+            Err(e) => {
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: Upgrade failed, restoring the previous rootfs"
+                );
+                snapshot::restore(&rootfs, &snap)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Installs packages, accepting `.apk` files from the host.
+    ///
+    /// Arguments naming existing host `.apk` files have their directory
+    /// bound into the sandbox and are replaced by the mapped path. Local
+    /// packages are usually unsigned, so `--allow-untrusted` is added with
+    /// a warning.
+    ///
+    /// # Returns
+    /// - `Ok(())` if apk succeeds.
+    /// - `Err` if a mount point cannot be created or apk fails.
+    fn run_add(&self) -> Result<(), Box<dyn Error>> {
+        let rootfs = self.rootfs.clone().unwrap_or_else(settings_rootfs_dir);
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut args = Vec::new();
+
+        for arg in &self.remaining_args {
+            let path = Path::new(arg);
+            if arg.starts_with('-') || !arg.ends_with(".apk") || !path.is_file() {
+                args.push(arg.clone());
+                continue;
+            }
+
+            let path = path.canonicalize()?;
+            let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+            let index = dirs.iter().position(|d| *d == dir).unwrap_or_else(|| {
+                dirs.push(dir);
+                dirs.len() - 1
+            });
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            args.push(format!("/{LOCAL_PKG_DIR}/{index}/{name}"));
+        }
+
+        if dirs.is_empty() {
+            return self.run_apk("apk add");
+        }
+
+        eprintln!(
+            "\x1b[1;33mWarning\x1b[0m: Installing local packages with --allow-untrusted, their signatures are not verified"
+        );
+        args.insert(0, "--allow-untrusted".to_string());
+
+        let mount_root = rootfs.join("rootfs").join(LOCAL_PKG_DIR);
+        let mut binds = Vec::new();
+        for (index, dir) in dirs.iter().enumerate() {
+            let target = Path::new("/").join(LOCAL_PKG_DIR).join(index.to_string());
+            fs::create_dir_all(mount_root.join(index.to_string()))?;
+            binds.push(bind_args(dir, &target));
+        }
+
+        let apk = Apk::new(self.command.clone(), args, Some(rootfs));
+        let result = apk.run_apk_bound("apk add", &binds);
+        let _ = fs::remove_dir_all(&mount_root);
+        result
+    }
+
     /// Executes an `apk` command inside the root filesystem environment.
     ///
+    /// When `--simulate` is among the arguments, it is passed through to
+    /// `apk` and the transaction output is captured and printed as a plan.
+    ///
     /// # Parameters
     /// - `cmd`: The base `apk` command to execute (e.g., "add", "del", "update").
     ///
     /// # Returns
     /// - `Ok(())` on success.
     /// - `Err(Box<dyn Error>)` if execution fails.
-    fn run_apk(&self, cmd: &str) -> Result<(), Box<dyn Error>> {
+    pub fn run_apk(&self, cmd: &str) -> Result<(), Box<dyn Error>> {
+        self.run_apk_bound(cmd, &[])
+    }
+
+    /// Executes an `apk` command with additional backend bind arguments.
+    ///
+    /// # Parameters
+    /// - `cmd`: The base `apk` command to execute.
+    /// - `binds`: Backend arguments binding host paths into the sandbox.
+    fn run_apk_bound(&self, cmd: &str, binds: &[String]) -> Result<(), Box<dyn Error>> {
         let rootfs = match &self.rootfs {
             Some(path) => path.clone(),
             None => settings_rootfs_dir(),
         };
 
+        Self::check_db_lock(&rootfs.join("rootfs"))?;
+
         let run_cmd = if self.remaining_args.is_empty() {
             cmd.to_string()
         } else {
             format!("{} {}", cmd, self.remaining_args.join(" "))
         };
 
+        let simulate = self.remaining_args.iter().any(|a| a == "--simulate");
+        let run_cmd = if simulate {
+            format!("( {run_cmd} ) > /{SIMULATE_LOG} 2>&1")
+        } else {
+            run_cmd
+        };
+
+        let (run_cmd, mut args_bind) = with_apk_cache(run_cmd, &rootfs)?;
+        let emulation = qemu::emulation_args(&rootfs)?;
+        let extra_binds = configured_binds();
+        for bind in binds.iter().chain([&emulation, &extra_binds]) {
+            args_bind = format!("{args_bind} {bind}").trim().to_string();
+        }
+
         let config = SandBoxConfig {
-            rootfs,
+            rootfs: rootfs.clone(),
             run_cmd,
+            args_bind,
             use_root: true,
             ignore_extra_bind: true,
             ..Default::default()
         };
 
-        map_result(SandBox::run(config))?;
+        let result = map_result(SandBox::run(config)).and_then(check_exit_status);
+
+        if simulate {
+            let log_path = rootfs.join("rootfs").join(SIMULATE_LOG);
+            let output = fs::read_to_string(&log_path).unwrap_or_default();
+            let _ = fs::remove_file(&log_path);
+            Self::print_plan(&output)?;
+        }
+
+        result?;
+        Ok(())
+    }
+
+    /// Fails early if another process holds the apk database lock.
+    ///
+    /// Without this check apk aborts with an opaque "unable to lock
+    /// database" error, typically left behind by a killed session.
+    ///
+    /// # Parameters
+    /// - `root`: The extracted rootfs on the host.
+    fn check_db_lock(root: &Path) -> Result<(), Box<dyn Error>> {
+        let holders = Self::lock_holders(&root.join(DB_LOCK));
+        if holders.is_empty() {
+            return Ok(());
+        }
+
+        Err(format!(
+            "The apk database is locked by process {}.\nIf no other session is running, clear it with 'fix --db-lock'.",
+            holders.join(", ")
+        )
+        .into())
+    }
+
+    /// Clears a stale apk database lock and repairs the database.
+    ///
+    /// Leftover apk processes of killed sessions holding the lock are
+    /// terminated; any other holder is reported and left alone. The lock
+    /// file is then removed and `apk fix` is run.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the lock is cleared and `apk fix` succeeds.
+    /// - `Err` if a non-apk process holds the lock or apk fails.
+    fn clear_db_lock(&self) -> Result<(), Box<dyn Error>> {
+        let rootfs = self.rootfs.clone().unwrap_or_else(settings_rootfs_dir);
+        let lock = rootfs.join("rootfs").join(DB_LOCK);
+
+        for pid in Self::lock_holders(&lock) {
+            let cmdline = fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+            if !String::from_utf8_lossy(&cmdline).contains("apk") {
+                return Err(format!(
+                    "The apk database lock is held by process {pid}, which is not apk. Stop it first."
+                )
+                .into());
+            }
+
+            println!("Terminating stale apk process {pid}");
+            Command::new("kill").args(["-TERM", &pid]).status()?;
+        }
+
+        for _ in 0..10 {
+            if Self::lock_holders(&lock).is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+
+        if lock.exists() {
+            fs::remove_file(&lock)?;
+            println!("Removed {}", lock.display());
+        }
+
+        let args = self
+            .remaining_args
+            .iter()
+            .filter(|a| *a != "--db-lock")
+            .cloned()
+            .collect();
+        Apk::new(self.command.clone(), args, Some(rootfs)).run_apk("apk fix")
+    }
+
+    /// Returns the IDs of the processes holding a lock file.
+    ///
+    /// The lock is probed with a non-blocking `flock`; only when it is held
+    /// are the holders looked up in `/proc/locks` by inode.
+    fn lock_holders(lock: &Path) -> Vec<String> {
+        let Ok(file) = File::open(lock) else {
+            return Vec::new();
+        };
+
+        if file.try_lock().is_ok() {
+            return Vec::new();
+        }
+
+        let inode = file.metadata().map(|m| m.ino()).unwrap_or_default();
+        let holders: Vec<String> = fs::read_to_string("/proc/locks")
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let ino = fields.get(5)?.rsplit(':').next()?.parse::<u64>().ok()?;
+                (ino == inode).then(|| fields[4].to_string())
+            })
+            .collect();
+
+        if holders.is_empty() {
+            vec!["unknown".to_string()]
+        } else {
+            holders
+        }
+    }
+
+    /// Parses the output of a simulated `apk` transaction and prints the plan.
+    ///
+    /// Lines such as `(1/3) Installing curl (8.5.0-r0)` are turned into an
+    /// aligned list of actions. Output without any planned step is printed
+    /// unchanged, since it usually holds apk's own status or error message.
+    ///
+    /// # Parameters
+    /// - `output`: The captured output of `apk --simulate`.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing.
+    /// - `Err` if the result box cannot be generated.
+    fn print_plan(output: &str) -> Result<(), Box<dyn Error>> {
+        let re = Regex::new(r"^\(\d+/\d+\) (\w+) (\S+) \((.+)\)$")?;
+
+        let plan: Vec<String> = output
+            .lines()
+            .filter_map(|line| re.captures(line.trim()))
+            .map(|caps| format!("{:<12} {} {}", &caps[1], &caps[2], &caps[3]))
+            .collect();
+
+        let body = if plan.is_empty() {
+            output.trim().to_string()
+        } else {
+            plan.join("\n")
+        };
+
+        println!(
+            "{u}\n{}\n{body}\n{u}",
+            get_cmd_box("SIMULATED PLAN:", None, Some(19))?,
+            u = SEPARATOR
+        );
         Ok(())
     }
 }
@@ -5,20 +5,27 @@
 //! It supports building from directories (contextual builds) or
 //! standalone APKBUILD files.
 
+use crate::envs;
+use crate::qemu;
 use crate::settings::{
-    settings_overlay_action, settings_overlay_inode_mode, settings_rootfs_dir, settings_use_overlay,
+    settings_builder_ephemeral, settings_cmd, settings_overlay_action, settings_overlay_inode_mode,
+    settings_proot, settings_rootfs_dir, settings_use_overlay,
 };
 use crate::setup::DEF_PACKAGES;
-use crate::utils::map_result;
-use recursive_copy::{copy_recursive, CopyOptions};
-use sandbox_utils::{
-    app_arch, invalid_arg, missing_arg, parse_value, OverlayAction, SandBox, SandBoxConfig,
+use crate::tree;
+use crate::utils::{
+    bind_args, check_exit_status, configured_binds, copy_dir_recursive, in_process_group,
+    map_result, parse_on_failure, parse_size, proot_options, resolve_log_file, run_with_spinner,
+    with_failure_shell, with_log_file,
 };
+use crate::wsl;
+use sandbox_utils::{invalid_arg, missing_arg, parse_value, OverlayAction, SandBox, SandBoxConfig};
 use std::collections::VecDeque;
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::process;
 use std::{env, fs};
 
 /// Controller for automated Alpine Linux package compilation.
@@ -27,6 +34,30 @@ pub struct Builder {
     remaining_args: Vec<String>,
 }
 
+/// Options shared by every build target of a `builder` invocation.
+struct BuildOptions {
+    /// Regenerate the signing keys even if they exist.
+    force_key: bool,
+    /// Leave the base rootfs untouched.
+    ephemeral: bool,
+    /// Run the build in an overlay.
+    use_overlay: bool,
+    /// What to do with the overlay after the build.
+    overlay_action: OverlayAction,
+    /// File receiving a copy of the build output.
+    log_file: Option<PathBuf>,
+    /// Install the produced packages into the rootfs after the build.
+    install: bool,
+    /// Run the `check()` phase, installing `checkdepends` first.
+    check: bool,
+    /// Keep the build work directories in memory.
+    tmpfs_build: bool,
+    /// Size limit of each in-memory work directory in bytes (0 = unlimited).
+    tmpfs_size: u64,
+    /// Open a shell in the build sandbox if the build fails.
+    failure_shell: bool,
+}
+
 impl Builder {
     /// Creates a new `Builder` instance with the given context and arguments.
     pub fn new(remaining_args: Vec<String>) -> Self {
@@ -53,17 +84,55 @@ impl Builder {
         }
 
         let mut build_targets = Vec::new();
+        let mut tree_targets: Option<(String, Vec<String>)> = None;
         let mut rootfs_dir = settings_rootfs_dir();
         let mut force_key = false;
         let mut use_overlay = settings_use_overlay();
         let mut overlay_action = settings_overlay_action();
+        let mut ephemeral = settings_builder_ephemeral();
+        let mut log_file = None;
+        let mut install = true;
+        let mut check = true;
+        let (mut tmpfs_build, mut tmpfs_size) = (false, 0);
+        let mut failure_shell = false;
 
         while let Some(arg) = args.pop_front() {
             match arg {
                 "--force-key" => force_key = true,
-                "-e" | "--ephemeral" => {
-                    use_overlay = true;
-                    overlay_action = OverlayAction::Discard;
+                "-e" | "--ephemeral" => ephemeral = true,
+                "--no-ephemeral" => ephemeral = false,
+                "--install" => install = true,
+                "--no-install" => install = false,
+                "--check" => check = true,
+                "--no-check" => check = false,
+                "--tmpfs-build" => tmpfs_build = true,
+                a if a.starts_with("--size=") => {
+                    let size = parse_value!("builder", "size", arg)?;
+                    tmpfs_size = parse_size(&size).ok_or(format!("Invalid size: {size}"))?;
+                }
+                "--size" => {
+                    let size = parse_value!("builder", "size", arg, args.pop_front())?;
+                    tmpfs_size = parse_size(&size).ok_or(format!("Invalid size: {size}"))?;
+                }
+                a if a.starts_with("--on-failure=") => {
+                    failure_shell = parse_on_failure(&parse_value!("builder", "action", arg)?)?;
+                }
+                "--on-failure" => {
+                    let value = parse_value!("builder", "action", arg, args.pop_front())?;
+                    failure_shell = parse_on_failure(&value)?;
+                }
+                a if a.starts_with("--log-file=") => {
+                    log_file = Some(parse_value!("builder", "file", arg)?.into());
+                }
+                "--log-file" => {
+                    log_file = Some(parse_value!("builder", "file", arg, args.pop_front())?.into());
+                }
+                a if a.starts_with("--name=") => {
+                    rootfs_dir = envs::lookup(&parse_value!("builder", "name", arg)?)?;
+                }
+                "--name" => {
+                    let name = parse_value!("builder", "name", arg, args.pop_front())?;
+                    rootfs_dir = envs::lookup(&name)?;
                 }
                 a if a.starts_with("--rootfs=") => {
                     rootfs_dir = parse_value!("builder", "directory", arg)?.into();
@@ -85,10 +154,56 @@ impl Builder {
                     build_targets.extend(args.drain(..).map(|s| s.to_string()));
                     break;
                 }
+                a if a.starts_with("--tree=") => {
+                    let name = parse_value!("builder", "tree", arg)?;
+                    tree_targets = Some((name, args.drain(..).map(|s| s.to_string()).collect()));
+                    break;
+                }
+                "-t" | "--tree" => {
+                    let name = parse_value!("builder", "tree", arg, args.pop_front())?;
+                    tree_targets = Some((name, args.drain(..).map(|s| s.to_string()).collect()));
+                    break;
+                }
                 _ => return invalid_arg!("builder", arg),
             }
         }
 
+        if let Some((name, pkgs)) = tree_targets {
+            if pkgs.is_empty() {
+                return Err(format!("No package given for the '{name}' tree").into());
+            }
+            let dirs = tree::checkout(&name, rootfs_dir.clone(), &pkgs)?;
+            build_targets.extend(dirs.iter().map(|d| d.display().to_string()));
+        }
+
+        if ephemeral {
+            use_overlay = true;
+            overlay_action = OverlayAction::Discard;
+        }
+
+        let opts = BuildOptions {
+            force_key,
+            ephemeral,
+            use_overlay,
+            overlay_action,
+            log_file: resolve_log_file(log_file, "builder")?,
+            install,
+            check,
+            tmpfs_build,
+            tmpfs_size,
+            failure_shell,
+        };
+
+        // A debug shell needs the terminal, which a separate group cannot read.
+        let group_status = if opts.failure_shell {
+            None
+        } else {
+            in_process_group()?
+        };
+        if let Some(code) = group_status {
+            return check_exit_status(code);
+        }
+
         for p in build_targets {
             let path = Path::new(&p);
             let potential_apkbuild = path.join("APKBUILD");
@@ -124,17 +239,14 @@ impl Builder {
                 fs::create_dir_all(&target_dir)?;
                 fs::copy(source_path, target_dir.join("APKBUILD"))?;
             } else {
-                copy_recursive(source_path, &target_dir, &CopyOptions::default())?;
+                copy_dir_recursive(source_path, &target_dir)?;
+            }
+
+            if !opts.check {
+                Self::disable_check(&target_dir.join("APKBUILD"))?;
             }
 
-            Self::run_abuild(
-                rootfs_dir.clone(),
-                &folder_name,
-                &pkg_name,
-                force_key,
-                use_overlay,
-                overlay_action,
-            )?;
+            Self::run_abuild(rootfs_dir.clone(), &folder_name, &pkg_name, &opts)?;
         }
 
         Ok(())
@@ -166,18 +278,42 @@ impl Builder {
             .unwrap_or_default()
     }
 
+    /// Disables the `check()` phase of the build copy of an APKBUILD.
+    ///
+    /// abuild has no command-line switch for the test suite, so `!check`
+    /// is appended to the `options` of the copy in the build directory,
+    /// which also keeps `checkdepends` from being installed. The user's
+    /// original APKBUILD is never modified.
+    ///
+    /// # Arguments
+    /// * `apkbuild` - The APKBUILD copy inside the build directory.
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the line was appended.
+    /// * `Err` - If the file cannot be written.
+    fn disable_check(apkbuild: &Path) -> Result<(), Box<dyn Error>> {
+        let mut file = OpenOptions::new().append(true).open(apkbuild)?;
+        writeln!(file, "\noptions=\"$options !check\"")?;
+        Ok(())
+    }
+
     /// Orchestrates the `abuild` process inside the rootfs.
     ///
-    /// Handles key generation, environment setup, and automated
-    /// installation of the compiled package.
+    /// Handles key generation, environment setup, and optional
+    /// installation of the compiled packages. The installed files are the
+    /// ones `abuild listpkg` reports for the APKBUILD, i.e. exactly the
+    /// package and subpackages of this build at its current version.
+    ///
+    /// In ephemeral mode every step, including installing the toolchain and
+    /// trusting the signing key, runs in a discarded overlay. The key pair
+    /// is kept in the build directory and its public key is copied into
+    /// `/etc/apk/keys` at the start of each build session instead.
     ///
     /// # Arguments
     /// * `rootfs` - Path to the root filesystem.
     /// * `dir_name` - The subdirectory name for the build context.
-    /// * `pkg` - The package name for final APK installation.
-    /// * `force_key` - If true, regenerates RSA keys even if they exist.
-    /// * `use_overlay` - If true, enable Overlay.
-    /// * `overlay_action` - Set overlay action.
+    /// * `pkg` - The package name, shown in the progress output.
+    /// * `opts` - The build options (signing keys, overlay and logging).
     ///
     /// # Returns
     /// * `Ok(())` - If the `abuild` command executes successfully.
@@ -186,13 +322,19 @@ impl Builder {
         rootfs: PathBuf,
         dir_name: &str,
         pkg: &str,
-        force_key: bool,
-        use_overlay: bool,
-        overlay_action: OverlayAction,
+        opts: &BuildOptions,
     ) -> Result<(), Box<dyn Error>> {
+        let ephemeral = opts.ephemeral;
         let user = env::var("USER").unwrap_or_else(|_| "root".into());
         let build_dir = rootfs.join("build");
-        let keys_dir = rootfs.join("rootfs/etc/apk/keys");
+        let abuild_config = build_dir.join(".abuild");
+        let pub_key = abuild_config.join(format!("{user}*.rsa.pub"));
+
+        let keys_dir = if ephemeral {
+            abuild_config.clone()
+        } else {
+            rootfs.join("rootfs/etc/apk/keys")
+        };
 
         let has_user_key = fs::read_dir(&keys_dir)
             .map(|entries| {
@@ -204,55 +346,161 @@ impl Builder {
             })
             .unwrap_or(false);
 
-        if force_key || !has_user_key {
-            let abuild_config = build_dir.join(".abuild");
+        if opts.force_key || !has_user_key {
             if fs::metadata(&abuild_config).is_ok() {
                 fs::remove_dir_all(&abuild_config)?;
             }
 
+            let trust_key = if ephemeral {
+                String::new()
+            } else {
+                format!(" && cp -v {} /etc/apk/keys", pub_key.display())
+            };
+
             let run_cmd = format!(
                 "type abuild > /dev/null 2>&1 || apk add {DEF_PACKAGES}
                 HOME={b}
-                abuild-keygen -a -n && \
-                cp -v {f} /etc/apk/keys",
+                abuild-keygen -a -n{trust_key}",
                 b = build_dir.display(),
-                f = &abuild_config.join(format!("{user}*.rsa.pub")).display()
             );
 
+            let wsl_binds = wsl::default_binds();
             let config = SandBoxConfig {
                 rootfs: rootfs.clone(),
                 run_cmd,
+                ignore_extra_bind: wsl_binds.is_some(),
+                args_bind: wsl_binds.unwrap_or_default(),
+                use_overlay: ephemeral,
+                action: opts.overlay_action,
+                inode_mode: settings_overlay_inode_mode(),
                 ..Default::default()
             };
 
-            map_result(SandBox::run(config))?;
+            run_with_spinner("Generating abuild signing keys", config)?;
         }
 
-        let run_cmd = format!(
+        let trust_key = if ephemeral {
+            format!("cp {} /etc/apk/keys", pub_key.display())
+        } else {
+            String::new()
+        };
+
+        let install = if opts.install {
+            format!(
+                " && apk add --allow-untrusted $(abuild -F listpkg | sed 's|^|{}/|')",
+                build_dir
+                    .join(format!("packages/build/{}", qemu::env_arch(&rootfs)))
+                    .display()
+            )
+        } else {
+            String::new()
+        };
+
+        let mut run_cmd = format!(
             "type abuild > /dev/null || apk add {DEF_PACKAGES}
+            {trust_key}
             HOME={b}
             cd {d}
-            abuild -r -F && \
-            find \"{f}\" -name \"{pkg}-*.apk\" -exec apk add --allow-untrusted {{}} \\;",
+            abuild -r -F{install}",
             b = build_dir.display(),
             d = build_dir.join(dir_name).display(),
-            f = build_dir
-                .join(format!("packages/build/{}", app_arch()))
-                .display()
         );
 
+        let mut binds = vec![
+            proot_options(&settings_proot(), &rootfs),
+            qemu::emulation_args(&rootfs)?,
+            configured_binds(),
+        ];
+        let wsl_binds = wsl::default_binds();
+        let ignore_extra_bind = wsl_binds.is_some();
+        binds.extend(wsl_binds);
+        let mut shm_dir = None;
+
+        if opts.tmpfs_build {
+            let (args, dir) = Self::tmpfs_args(&build_dir.join(dir_name), opts.tmpfs_size)?;
+            binds.push(args);
+            shm_dir = dir;
+        }
+
+        if let Some(log_file) = &opts.log_file {
+            let (script, bind) = with_log_file(&run_cmd, log_file);
+            run_cmd = script;
+            binds.push(bind);
+        }
+
+        if opts.failure_shell {
+            run_cmd = with_failure_shell(&run_cmd, &build_dir.join(dir_name));
+        }
+
         let config = SandBoxConfig {
             rootfs,
             run_cmd,
+            args_bind: binds.join(" ").trim().to_string(),
             use_root: true,
+            ignore_extra_bind,
             secure_rootfs: true,
-            use_overlay,
-            action: overlay_action,
+            use_overlay: opts.use_overlay,
+            action: opts.overlay_action,
             inode_mode: settings_overlay_inode_mode(),
             ..Default::default()
         };
 
-        map_result(SandBox::run(config))?;
-        Ok(())
+        // abuild streams its own output, which a spinner would garble.
+        println!("Building {pkg}");
+        let result = map_result(SandBox::run(config)).map(|_| ());
+
+        if let Some(dir) = shm_dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+        result
+    }
+
+    /// Builds the backend arguments placing the build work directories in memory.
+    ///
+    /// The `src` and `pkg` directories abuild works in are covered by a
+    /// tmpfs with bwrap. proot cannot mount filesystems, so directories
+    /// under the host's `/dev/shm` are bound instead, without size limit.
+    ///
+    /// # Arguments
+    /// * `startdir` - The directory holding the APKBUILD inside the sandbox.
+    /// * `size` - The size limit of each tmpfs in bytes (0 = unlimited).
+    ///
+    /// # Returns
+    /// * `Ok((args, dir))` - The backend arguments and, for proot, the host
+    ///   directory to remove after the build.
+    /// * `Err` - If the mount points cannot be created.
+    fn tmpfs_args(startdir: &Path, size: u64) -> Result<(String, Option<PathBuf>), Box<dyn Error>> {
+        let work_dirs = ["src", "pkg"].map(|d| startdir.join(d));
+        for dir in &work_dirs {
+            fs::create_dir_all(dir)?;
+        }
+
+        if settings_cmd() == "bwrap" {
+            let size = if size > 0 {
+                format!("--size {size} ")
+            } else {
+                String::new()
+            };
+            let args: Vec<String> = work_dirs
+                .iter()
+                .map(|dir| format!("{size}--tmpfs {}", dir.display()))
+                .collect();
+            return Ok((args.join(" "), None));
+        }
+
+        if size > 0 {
+            eprintln!(
+                "\x1b[1;33mWarning\x1b[0m: proot cannot limit the tmpfs size, ignoring --size."
+            );
+        }
+
+        let shm = PathBuf::from(format!("/dev/shm/alpack-build-{}", process::id()));
+        let mut args = Vec::new();
+        for dir in &work_dirs {
+            let host_dir = shm.join(dir.file_name().unwrap_or_default());
+            fs::create_dir_all(&host_dir)?;
+            args.push(bind_args(&host_dir, dir));
+        }
+        Ok((args.join(" "), Some(shm)))
     }
 }
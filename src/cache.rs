@@ -0,0 +1,685 @@
+//! Content-addressed download cache.
+//!
+//! Downloaded files are stored under `objects/` keyed by their SHA-256
+//! digest, while a small TOML index maps file names to digests and source
+//! URLs. Any rootfs setup sharing the same cache directory reuses existing
+//! objects, and checking whether a file is already downloaded is a simple
+//! index lookup. Size and age limits from the settings are enforced after
+//! every download and by the `cache prune` subcommand.
+
+use crate::http;
+use crate::settings::{
+    settings_cache_dir, settings_cache_ttl_days, settings_download_segments,
+    settings_max_cache_size,
+};
+use crate::utils::{is_json, is_offline, new_progress_bar, parse_size};
+use indicatif::{ProgressBar, ProgressStyle};
+use sandbox_utils::{invalid_arg, missing_arg, parse_value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File name of the cache index inside the cache directory.
+const INDEX_FILE: &str = "index.toml";
+
+/// Number of attempts made for a download before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 4;
+
+/// Files smaller than this are always downloaded over a single connection.
+const SEGMENT_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Metadata recorded for each cached file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    /// SHA-256 digest of the file contents, in lowercase hex.
+    pub hash: String,
+    /// The URL the file was downloaded from.
+    pub url: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Unix timestamp of the last time the entry was used.
+    pub last_used: u64,
+}
+
+/// On-disk index mapping file names to cached objects.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CacheIndex {
+    /// Cached entries keyed by file name.
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+/// Handle to a content-addressed cache directory.
+pub struct Cache {
+    /// Root directory of the cache.
+    dir: PathBuf,
+    /// The loaded index.
+    index: CacheIndex,
+}
+
+impl Cache {
+    /// Opens the cache at the given directory, creating it if needed.
+    ///
+    /// A missing or unreadable index is treated as an empty cache.
+    ///
+    /// # Parameters
+    /// - `dir`: The cache directory.
+    ///
+    /// # Returns
+    /// - `Ok(Cache)` with the loaded index.
+    /// - `Err` if the directory structure cannot be created.
+    pub fn open(dir: PathBuf) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(dir.join("objects"))?;
+
+        let index = fs::read_to_string(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|s| toml::from_str(&s).ok())
+            .unwrap_or_default();
+
+        Ok(Cache { dir, index })
+    }
+
+    /// Returns the path of a cached file if it is present and intact.
+    ///
+    /// An entry is considered valid when it was downloaded from `url` and
+    /// its object exists with the size recorded in the index, so a file of
+    /// the same name from another mirror or architecture is not reused.
+    ///
+    /// # Parameters
+    /// - `url`: The remote location of the file.
+    /// - `name`: The file name used when the entry was stored.
+    pub fn lookup(&self, url: &str, name: &str) -> Option<PathBuf> {
+        self.index
+            .entries
+            .get(name)
+            .filter(|entry| entry.url == url)
+            .and_then(|entry| self.intact(entry))
+    }
+
+    /// Returns the object of an entry if it exists with the recorded size.
+    fn intact(&self, entry: &CacheEntry) -> Option<PathBuf> {
+        let path = self.object_path(&entry.hash);
+
+        match fs::metadata(&path) {
+            Ok(meta) if meta.is_file() && meta.len() == entry.size => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Returns the names of the cached files downloaded from under a URL prefix.
+    ///
+    /// # Parameters
+    /// - `prefix`: The start of the source URLs, e.g. a mirror release directory.
+    pub fn names_from(&self, prefix: &str) -> Vec<&str> {
+        self.index
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.url.starts_with(prefix) && self.intact(entry).is_some())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Returns the SHA-256 digest recorded for a cached file.
+    ///
+    /// # Parameters
+    /// - `name`: The file name used when the entry was stored.
+    pub fn hash(&self, name: &str) -> Option<&str> {
+        self.index.entries.get(name).map(|e| e.hash.as_str())
+    }
+
+    /// Drops a cached file, e.g. after it failed verification.
+    ///
+    /// # Parameters
+    /// - `name`: The file name used when the entry was stored.
+    pub fn forget(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(entry) = self.index.entries.remove(name) {
+            let shared = self.index.entries.values().any(|e| e.hash == entry.hash);
+            if !shared {
+                let _ = fs::remove_file(self.object_path(&entry.hash));
+            }
+        }
+        self.save()
+    }
+
+    /// Returns the cached file for `name`, downloading it from `url` if needed.
+    ///
+    /// New downloads are written to a temporary file while being hashed,
+    /// then moved to their content address and recorded in the index. The
+    /// temporary file is named after the URL, so an interrupted download is
+    /// only resumed from the same source.
+    ///
+    /// # Parameters
+    /// - `url`: The remote location of the file.
+    /// - `name`: The file name used as the index key.
+    ///
+    /// # Returns
+    /// - `Ok(PathBuf)` pointing to the cached object.
+    /// - `Err` if the download or any filesystem operation fails.
+    pub fn fetch(&mut self, url: &str, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        if let Some(path) = self.lookup(url, name) {
+            println!("Using cached file: {name}");
+            if let Some(entry) = self.index.entries.get_mut(name) {
+                entry.last_used = unix_now();
+            }
+            self.save()?;
+            return Ok(path);
+        }
+
+        if is_offline() {
+            return Err(format!(
+                "'{name}' is not in the cache ({}) and --offline forbids downloading it.",
+                self.dir.display()
+            )
+            .into());
+        }
+
+        let part = self.dir.join(format!(
+            "{}.part",
+            hex_digest(&Sha256::digest(url.as_bytes()))
+        ));
+        let (hash, size) = download_hashed(url, &part)?;
+        let path = self.object_path(&hash);
+        fs::rename(&part, &path)?;
+
+        self.index.entries.insert(
+            name.to_string(),
+            CacheEntry {
+                hash,
+                url: url.to_string(),
+                size,
+                last_used: unix_now(),
+            },
+        );
+        self.prune(
+            settings_max_cache_size(),
+            settings_cache_ttl_days(),
+            Some(name),
+        )?;
+        Ok(path)
+    }
+
+    /// Evicts cache entries exceeding the given age and size limits.
+    ///
+    /// Entries unused for longer than `ttl_days` are removed first, then the
+    /// least recently used entries are evicted until the total size fits in
+    /// `max_size`. Objects no longer referenced by any entry are deleted.
+    ///
+    /// # Parameters
+    /// - `max_size`: Maximum total size in bytes (0 = unlimited).
+    /// - `ttl_days`: Maximum age in days since last use (0 = unlimited).
+    /// - `keep`: An entry that must never be evicted, such as a file in use.
+    ///
+    /// # Returns
+    /// - `Ok((count, bytes))` with the number of evicted entries and freed bytes.
+    /// - `Err` if the index cannot be saved.
+    pub fn prune(
+        &mut self,
+        max_size: u64,
+        ttl_days: u64,
+        keep: Option<&str>,
+    ) -> Result<(usize, u64), Box<dyn Error>> {
+        let mut by_age: Vec<(String, u64)> = self
+            .index
+            .entries
+            .iter()
+            .filter(|(name, _)| Some(name.as_str()) != keep)
+            .map(|(name, entry)| (name.clone(), entry.last_used))
+            .collect();
+        by_age.sort_by_key(|(_, last_used)| *last_used);
+
+        let expiry = unix_now().saturating_sub(ttl_days * 86400);
+        let mut evicted = 0;
+
+        for (name, last_used) in by_age {
+            let expired = ttl_days > 0 && last_used < expiry;
+            let oversized = max_size > 0 && self.total_size() > max_size;
+
+            if expired || oversized {
+                self.index.entries.remove(&name);
+                evicted += 1;
+            }
+        }
+
+        let freed = self.remove_orphans()?;
+        self.save()?;
+        Ok((evicted, freed))
+    }
+
+    /// Sums the size of all distinct objects referenced by the index.
+    fn total_size(&self) -> u64 {
+        let mut seen = HashSet::new();
+        self.index
+            .entries
+            .values()
+            .filter(|entry| seen.insert(&entry.hash))
+            .map(|entry| entry.size)
+            .sum()
+    }
+
+    /// Deletes objects that are not referenced by any index entry.
+    ///
+    /// # Returns
+    /// - `Ok(bytes)` with the amount of disk space released.
+    /// - `Err` if the objects directory cannot be read.
+    fn remove_orphans(&self) -> Result<u64, Box<dyn Error>> {
+        let referenced: HashSet<&str> = self
+            .index
+            .entries
+            .values()
+            .map(|entry| entry.hash.as_str())
+            .collect();
+
+        let mut freed = 0;
+        for entry in fs::read_dir(self.dir.join("objects"))? {
+            let entry = entry?;
+            let name = entry.file_name();
+
+            if !referenced.contains(name.to_string_lossy().as_ref()) {
+                freed += entry.metadata().map(|m| m.len()).unwrap_or(0);
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        Ok(freed)
+    }
+
+    /// Writes the index back to disk.
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        fs::write(
+            self.dir.join(INDEX_FILE),
+            toml::to_string_pretty(&self.index)?,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the storage path for an object digest.
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("objects").join(hash)
+    }
+}
+
+/// Controller for the `cache` subcommand.
+pub struct CacheCommand {
+    /// Arguments captured after the `cache` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl CacheCommand {
+    /// Creates a new `CacheCommand` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        CacheCommand { remaining_args }
+    }
+
+    /// Parses the cache action and its options and executes it.
+    ///
+    /// Currently supports `prune`, which applies the configured limits or
+    /// the ones given with `--max-size` and `--ttl-days`.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the action completes.
+    /// - `Err` if an argument is invalid or the cache cannot be modified.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+
+        let Some(action) = args.pop_front() else {
+            return missing_arg!("cache");
+        };
+
+        let mut cache_dir = settings_cache_dir();
+        let mut max_size = settings_max_cache_size();
+        let mut ttl_days = settings_cache_ttl_days();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--max-size=") => {
+                    let size = parse_value!("cache", "size", arg)?;
+                    max_size = parse_size(&size).ok_or(format!("Invalid size: {size}"))?;
+                }
+                "--max-size" => {
+                    let size = parse_value!("cache", "size", arg, args.pop_front())?;
+                    max_size = parse_size(&size).ok_or(format!("Invalid size: {size}"))?;
+                }
+                a if a.starts_with("--ttl-days=") => {
+                    ttl_days = parse_value!("cache", "days", arg)?.parse()?;
+                }
+                "--ttl-days" => {
+                    ttl_days = parse_value!("cache", "days", arg, args.pop_front())?.parse()?;
+                }
+                a if a.starts_with("--cache=") => {
+                    cache_dir = parse_value!("cache", "directory", arg)?.into();
+                }
+                "--cache" => {
+                    cache_dir = parse_value!("cache", "directory", arg, args.pop_front())?.into();
+                }
+                _ => return invalid_arg!("cache", arg),
+            }
+        }
+
+        match action {
+            "prune" => {
+                let (count, freed) = Cache::open(cache_dir)?.prune(max_size, ttl_days, None)?;
+                if is_json() {
+                    println!("{}", json!({ "evicted": count, "freed": freed }));
+                } else {
+                    println!("Evicted {count} entries, freed {freed} bytes.");
+                }
+                Ok(())
+            }
+            other => invalid_arg!("cache", other),
+        }
+    }
+}
+
+/// Downloads a URL to `dest` while computing its SHA-256 digest.
+///
+/// Transient failures (connection errors, timeouts, `429` and `5xx`
+/// responses) are retried with exponential backoff, resuming from the bytes
+/// already written. A partial file left by an earlier run is resumed too.
+///
+/// # Parameters
+/// - `url`: The file to download.
+/// - `dest`: Where the downloaded bytes are written.
+///
+/// # Returns
+/// - `Ok((hash, size))` with the hex digest and byte count.
+/// - `Err` if the request or a write fails.
+fn download_hashed(url: &str, dest: &Path) -> Result<(String, u64), Box<dyn Error>> {
+    let segments = settings_download_segments();
+    let ranged = (segments > 1)
+        .then(|| ranged_length(url))
+        .flatten()
+        .filter(|len| *len >= SEGMENT_MIN_SIZE);
+    if let Some(total) = ranged {
+        return download_segmented(url, dest, total, segments);
+    }
+
+    let mut attempt = 1;
+    loop {
+        match download_resumed(url, dest) {
+            Err(e) if attempt < DOWNLOAD_ATTEMPTS && is_transient(e.as_ref()) => {
+                let delay = 1 << attempt;
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: Download interrupted ({e}), resuming in {delay}s..."
+                );
+                thread::sleep(Duration::from_secs(delay));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Downloads a URL to `dest`, continuing a partial file with a `Range` request.
+///
+/// Servers ignoring the range answer `200` and the file is restarted; a
+/// partial file the server rejects as out of range is discarded.
+///
+/// # Parameters
+/// - `url`: The file to download.
+/// - `dest`: The partial or new file.
+///
+/// # Returns
+/// - `Ok((hash, size))` with the hex digest and byte count of the whole file.
+/// - `Err` if the request or a write fails.
+fn download_resumed(url: &str, dest: &Path) -> Result<(String, u64), Box<dyn Error>> {
+    let offset = fs::metadata(dest).map(|m| m.len()).unwrap_or(0);
+
+    let mut request = http::get(url);
+    if offset > 0 {
+        request = request.header("Range", format!("bytes={offset}-"));
+    }
+
+    let mut response = match request.call() {
+        Err(ureq::Error::StatusCode(416)) if offset > 0 => {
+            fs::remove_file(dest)?;
+            return download_resumed(url, dest);
+        }
+        other => other?,
+    };
+
+    let resumed = offset > 0 && response.status() == 206;
+    let mut hasher = Sha256::new();
+    let (mut file, mut size) = if resumed {
+        io::copy(&mut File::open(dest)?, &mut hasher)?;
+        (OpenOptions::new().append(true).open(dest)?, offset)
+    } else {
+        (File::create(dest)?, 0)
+    };
+
+    let total = response.body().content_length().unwrap_or(0) + size;
+    let mut reader = response.body_mut().as_reader();
+
+    let bar = new_progress_bar(total);
+    bar.set_style(
+        ProgressStyle::with_template("Downloading [{bar:30.cyan/blue}] {bytes}/{total_bytes}")?
+            .progress_chars("=> "),
+    );
+    bar.set_position(size);
+
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        file.write_all(&buf[..n])?;
+        size += n as u64;
+        bar.inc(n as u64);
+    }
+
+    bar.finish_and_clear();
+    Ok((hex_digest(&hasher.finalize()), size))
+}
+
+/// Returns whether a download error is worth retrying.
+///
+/// Only network failures qualify: local I/O errors such as a full disk or
+/// a read-only cache directory would fail the same way on every attempt.
+fn is_transient(err: &(dyn Error + 'static)) -> bool {
+    if let Some(err) = err.downcast_ref::<ureq::Error>() {
+        return match err {
+            ureq::Error::StatusCode(code) => *code == 408 || *code == 429 || *code >= 500,
+            ureq::Error::Io(_) | ureq::Error::Timeout(_) | ureq::Error::ConnectionFailed => true,
+            ureq::Error::HostNotFound => true,
+            _ => false,
+        };
+    }
+
+    let Some(err) = err.downcast_ref::<io::Error>() else {
+        return false;
+    };
+    if let Some(inner) = err.get_ref().filter(|inner| inner.is::<ureq::Error>()) {
+        return is_transient(inner);
+    }
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::Interrupted
+    )
+}
+
+/// Returns the size of a file if its server accepts byte ranges.
+///
+/// # Parameters
+/// - `url`: The file to download.
+///
+/// # Returns
+/// The `Content-Length`, or `None` if ranges are unsupported or the
+/// request fails, in which case a single connection is used.
+fn ranged_length(url: &str) -> Option<u64> {
+    let response = http::head(url).call().ok()?;
+    let headers = response.headers();
+
+    let ranges = headers.get("accept-ranges")?.to_str().ok()?;
+    if !ranges.contains("bytes") {
+        return None;
+    }
+    headers.get("content-length")?.to_str().ok()?.parse().ok()
+}
+
+/// Downloads a file as concurrent byte ranges and verifies the result.
+///
+/// Each segment is written at its offset in a preallocated file and is
+/// resumed from its last written byte after a transient failure, like
+/// single-connection downloads. Every response must be a `206 Partial
+/// Content`; the bytes written by all segments must add up to the file
+/// size, and the assembled file is then hashed from disk, so the digest
+/// covers what was actually written.
+///
+/// # Parameters
+/// - `url`: The file to download.
+/// - `dest`: Where the downloaded bytes are written.
+/// - `total`: The file size reported by the server.
+/// - `segments`: The number of concurrent connections.
+///
+/// # Returns
+/// - `Ok((hash, size))` with the hex digest and byte count.
+/// - `Err` if a segment fails after its retries or comes back incomplete.
+fn download_segmented(
+    url: &str,
+    dest: &Path,
+    total: u64,
+    segments: u32,
+) -> Result<(String, u64), Box<dyn Error>> {
+    let file = File::create(dest)?;
+    file.set_len(total)?;
+
+    let bar = new_progress_bar(total);
+    bar.set_style(
+        ProgressStyle::with_template(&format!(
+            "Downloading ({segments} segments) [{{bar:30.cyan/blue}}] {{bytes}}/{{total_bytes}}"
+        ))?
+        .progress_chars("=> "),
+    );
+
+    let chunk = total.div_ceil(u64::from(segments));
+    let results: Vec<Result<u64, String>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..u64::from(segments))
+            .map(|i| (i * chunk, ((i + 1) * chunk).min(total)))
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| {
+                let (file, bar) = (&file, &bar);
+                scope.spawn(move || fetch_segment(url, file, start, end, bar))
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| {
+                h.join()
+                    .unwrap_or_else(|_| Err("download thread panicked".into()))
+            })
+            .collect()
+    });
+    bar.finish_and_clear();
+
+    let mut written = 0;
+    for result in results {
+        written += result.map_err(|e| format!("Segmented download of {url} failed: {e}"))?;
+    }
+    if written != total {
+        return Err(format!("Downloaded {written} bytes of {url}, expected {total}").into());
+    }
+
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut File::open(dest)?, &mut hasher)?;
+    Ok((hex_digest(&hasher.finalize()), size))
+}
+
+/// Downloads the byte range `start..end` of a file into the same range of `file`.
+///
+/// Transient failures are retried with exponential backoff, requesting
+/// only the bytes not written yet.
+///
+/// # Returns
+/// - `Ok(bytes)` with the number of bytes written, `end - start`.
+/// - `Err` if the range cannot be completed.
+fn fetch_segment(
+    url: &str,
+    file: &File,
+    start: u64,
+    end: u64,
+    bar: &ProgressBar,
+) -> Result<u64, String> {
+    let mut offset = start;
+    let mut attempt = 1;
+
+    loop {
+        match fetch_range(url, file, &mut offset, end, bar) {
+            Ok(()) => return Ok(offset - start),
+            Err(e) if attempt < DOWNLOAD_ATTEMPTS && is_transient(e.as_ref()) => {
+                thread::sleep(Duration::from_secs(1 << attempt));
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(format!(
+                    "segment {start}-{end} stopped after {} bytes: {e}",
+                    offset - start
+                ));
+            }
+        }
+    }
+}
+
+/// Downloads the byte range `offset..end` of a file into the same range of `file`.
+///
+/// `offset` is advanced past every byte written, so a failed attempt can
+/// be resumed from there.
+fn fetch_range(
+    url: &str,
+    file: &File,
+    offset: &mut u64,
+    end: u64,
+    bar: &ProgressBar,
+) -> Result<(), Box<dyn Error>> {
+    let mut response = http::get(url)
+        .header("Range", format!("bytes={offset}-{}", end - 1))
+        .call()?;
+
+    if response.status().as_u16() != 206 {
+        return Err(format!("server ignored the range request ({})", response.status()).into());
+    }
+
+    let mut reader = response.body_mut().as_reader();
+    let mut buf = vec![0u8; 64 * 1024];
+
+    while *offset < end {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let n = n.min((end - *offset) as usize);
+        file.write_all_at(&buf[..n], *offset)?;
+        *offset += n as u64;
+        bar.inc(n as u64);
+    }
+    Ok(())
+}
+
+/// Formats a digest as lowercase hexadecimal.
+pub fn hex_digest(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Returns the current Unix time in seconds.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -4,10 +4,12 @@
 //! persistent settings such as rootfs isolation tools, release channels,
 //! and directory paths via CLI arguments.
 
-use crate::settings::Settings;
+use crate::settings::{effective_settings, BindSettings, MirrorSetting, Settings};
+use crate::utils::{is_json, parse_size};
 use sandbox_utils::{invalid_arg, parse_value, InodeMode, OverlayAction};
 use std::collections::VecDeque;
 use std::error::Error;
+use std::fs;
 
 /// Configuration manager for updating application settings.
 pub struct Config {
@@ -36,6 +38,9 @@ impl Config {
 
         while let Some(arg) = args.pop_front() {
             match arg {
+                "--effective" if self.remaining_args.len() == 1 => {
+                    return Self::show_effective();
+                }
                 "--enable-overlay" | "--use-overlay" => sett.use_overlay = true,
                 "--disable-overlay" => sett.use_overlay = false,
                 "--use-persistent-inode" => sett.overlay_inode_mode = InodeMode::Persistent,
@@ -44,10 +49,34 @@ impl Config {
                 "--overlay-action-commit" => sett.overlay_action = OverlayAction::Commit,
                 "--overlay-action-commit-atomic" => sett.overlay_action = OverlayAction::CommitAtomic,
                 "--overlay-action-preserve" => sett.overlay_action = OverlayAction::Preserve,
+                "--enable-builder-ephemeral" => sett.builder_ephemeral = true,
+                "--disable-builder-ephemeral" => sett.builder_ephemeral = false,
+                "--enable-apk-cache" => sett.share_apk_cache = true,
+                "--disable-apk-cache" => sett.share_apk_cache = false,
+                "--enable-docs" => sett.with_docs = true,
+                "--disable-docs" => sett.with_docs = false,
+                "--enable-rootfs-ca-certs" => sett.rootfs_ca_certs = true,
+                "--disable-rootfs-ca-certs" => sett.rootfs_ca_certs = false,
+                "--clear-ca-certs" => sett.ca_certs.clear(),
+                "--enable-hardening" => sett.hardening = true,
+                "--disable-hardening" => sett.hardening = false,
+                "--enable-bind-cwd" => sett.run.bind_cwd = true,
+                "--disable-bind-cwd" => sett.run.bind_cwd = false,
+                "--enable-proot-kill-on-exit" => sett.proot.kill_on_exit = true,
+                "--disable-proot-kill-on-exit" => sett.proot.kill_on_exit = false,
+                "--enable-proot-sysvipc" => sett.proot.sysvipc = true,
+                "--disable-proot-sysvipc" => sett.proot.sysvipc = false,
+                "--enable-proot-su-bindings" => sett.proot.su_bindings = true,
+                "--disable-proot-su-bindings" => sett.proot.su_bindings = false,
+                "--enable-subids" => sett.subid_mapping = true,
+                "--disable-subids" => sett.subid_mapping = false,
                 "--use-proot" => sett.cmd_rootfs = "proot".to_string(),
                 "--use-bwrap" => sett.cmd_rootfs = "bwrap".to_string(),
                 "--use-latest-stable" => sett.release = "latest-stable".to_string(),
                 "--use-edge" => sett.release = "edge".to_string(),
+                "--use-ipv4" => sett.ip_family = "ipv4".to_string(),
+                "--use-ipv6" => sett.ip_family = "ipv6".to_string(),
+                "--use-any-ip" => sett.ip_family = "any".to_string(),
                 a if a.starts_with("--cache-dir=") => {
                     sett.cache_dir = parse_value!("config", "directory", arg)?.into();
                 }
@@ -69,11 +98,93 @@ impl Config {
                     sett.output_dir =
                         parse_value!("config", "directory", arg, args.pop_front())?.into();
                 }
+                a if a.starts_with("--logs-dir=") => {
+                    sett.logs_dir = parse_value!("config", "directory", arg)?.into();
+                }
+                "--logs-dir" => {
+                    sett.logs_dir =
+                        parse_value!("config", "directory", arg, args.pop_front())?.into();
+                }
                 a if a.starts_with("--default-mirror=") => {
-                    sett.default_mirror = parse_value!("config", "mirror", arg)?;
+                    sett.default_mirror =
+                        MirrorSetting::Single(parse_value!("config", "mirror", arg)?);
                 }
                 "--default-mirror" => {
-                    sett.default_mirror = parse_value!("config", "mirror", arg, args.pop_front())?;
+                    sett.default_mirror = MirrorSetting::Single(parse_value!(
+                        "config",
+                        "mirror",
+                        arg,
+                        args.pop_front()
+                    )?);
+                }
+                a if a.starts_with("--arch-mirror=") => {
+                    let value = parse_value!("config", "arch=url", arg)?;
+                    Self::set_arch_mirror(&mut sett, &value)?;
+                }
+                "--arch-mirror" => {
+                    let value = parse_value!("config", "arch=url", arg, args.pop_front())?;
+                    Self::set_arch_mirror(&mut sett, &value)?;
+                }
+                a if a.starts_with("--max-cache-size=") => {
+                    let size = parse_value!("config", "size", arg)?;
+                    sett.max_cache_size =
+                        parse_size(&size).ok_or(format!("Invalid size: {size}"))?;
+                }
+                "--max-cache-size" => {
+                    let size = parse_value!("config", "size", arg, args.pop_front())?;
+                    sett.max_cache_size =
+                        parse_size(&size).ok_or(format!("Invalid size: {size}"))?;
+                }
+                a if a.starts_with("--cache-ttl-days=") => {
+                    sett.cache_ttl_days = parse_value!("config", "days", arg)?.parse()?;
+                }
+                "--cache-ttl-days" => {
+                    sett.cache_ttl_days =
+                        parse_value!("config", "days", arg, args.pop_front())?.parse()?;
+                }
+                a if a.starts_with("--http-timeout=") => {
+                    sett.http_timeout = parse_value!("config", "seconds", arg)?.parse()?;
+                }
+                "--http-timeout" => {
+                    sett.http_timeout =
+                        parse_value!("config", "seconds", arg, args.pop_front())?.parse()?;
+                }
+                a if a.starts_with("--http-proxy=") => {
+                    sett.http_proxy = parse_value!("config", "proxy", arg)?;
+                }
+                "--http-proxy" => {
+                    sett.http_proxy = parse_value!("config", "proxy", arg, args.pop_front())?;
+                }
+                a if a.starts_with("--download-segments=") => {
+                    sett.download_segments = parse_value!("config", "count", arg)?.parse()?;
+                }
+                "--download-segments" => {
+                    sett.download_segments =
+                        parse_value!("config", "count", arg, args.pop_front())?.parse()?;
+                }
+                a if a.starts_with("--add-bind=") => {
+                    let spec = parse_value!("config", "bind", arg)?;
+                    Self::add_bind(&mut sett, &spec)?;
+                }
+                "--add-bind" => {
+                    let spec = parse_value!("config", "bind", arg, args.pop_front())?;
+                    Self::add_bind(&mut sett, &spec)?;
+                }
+                a if a.starts_with("--remove-bind=") => {
+                    let path = parse_value!("config", "path", arg)?;
+                    Self::remove_bind(&mut sett, &path)?;
+                }
+                "--remove-bind" => {
+                    let path = parse_value!("config", "path", arg, args.pop_front())?;
+                    Self::remove_bind(&mut sett, &path)?;
+                }
+                a if a.starts_with("--ca-cert=") => {
+                    let file = parse_value!("config", "file", arg)?;
+                    Self::add_ca_cert(&mut sett, &file)?;
+                }
+                "--ca-cert" => {
+                    let file = parse_value!("config", "file", arg, args.pop_front())?;
+                    Self::add_ca_cert(&mut sett, &file)?;
                 }
                 _ => return invalid_arg!("config", arg),
             }
@@ -85,4 +196,144 @@ impl Config {
         }
         Ok(())
     }
+
+    /// Prints the final value of every setting along with its source.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing.
+    /// - `Err` if the JSON output cannot be serialized.
+    fn show_effective() -> Result<(), Box<dyn Error>> {
+        let rows = effective_settings();
+
+        if is_json() {
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|(key, value, source)| {
+                    serde_json::json!({ "setting": key, "value": value, "source": source })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&rows)?);
+            return Ok(());
+        }
+
+        let width = rows.iter().map(|(key, ..)| key.len()).max().unwrap_or(0);
+        for (key, value, source) in rows {
+            println!("{key:<width$}  {value}  \x1b[2m[{source}]\x1b[0m");
+        }
+        Ok(())
+    }
+
+    /// Adds a root certificate file to the trusted certificates.
+    ///
+    /// The file is stored as an absolute path, so the setting keeps working
+    /// from any directory.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `file`: A PEM file with one or more certificates.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the file exists.
+    /// - `Err` if it cannot be found.
+    fn add_ca_cert(sett: &mut Settings, file: &str) -> Result<(), Box<dyn Error>> {
+        let path = fs::canonicalize(file)
+            .map_err(|e| format!("Cannot read certificate file '{file}': {e}"))?;
+
+        if !sett.ca_certs.contains(&path) {
+            sett.ca_certs.push(path);
+        }
+        Ok(())
+    }
+
+    /// Declares a host path bound into every sandbox session.
+    ///
+    /// The host path is stored as an absolute path, so the setting keeps
+    /// working from any directory.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `spec`: `SRC[:DST][:ro|:rw]`, e.g. `~/.fonts:ro` or `/srv/data:/data`.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the bind was added or updated.
+    /// - `Err` if the host path does not exist or the mount point is relative.
+    fn add_bind(sett: &mut Settings, spec: &str) -> Result<(), Box<dyn Error>> {
+        let mut parts: Vec<&str> = spec.split(':').collect();
+        let read_only = match parts.last() {
+            Some(&mode) if parts.len() > 1 && (mode == "ro" || mode == "rw") => {
+                parts.pop();
+                mode == "ro"
+            }
+            _ => false,
+        };
+
+        let (src, dest) = match parts.as_slice() {
+            [src] => (*src, ""),
+            [src, dest] if dest.starts_with('/') => (*src, *dest),
+            _ => {
+                return Err(format!("Invalid bind '{spec}', expected SRC[:DST][:ro|:rw]").into());
+            }
+        };
+
+        let src = fs::canonicalize(src).map_err(|e| format!("Cannot bind '{src}': {e}"))?;
+
+        sett.binds.insert(
+            src.display().to_string(),
+            BindSettings {
+                dest: dest.into(),
+                read_only,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes a host path from the binds.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `path`: The host path, as given to `--add-bind`.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the bind was removed.
+    /// - `Err` if no bind is declared for the path.
+    fn remove_bind(sett: &mut Settings, path: &str) -> Result<(), Box<dyn Error>> {
+        let key = fs::canonicalize(path)
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|_| path.to_string());
+
+        sett.binds
+            .remove(&key)
+            .or_else(|| sett.binds.remove(path))
+            .map(|_| ())
+            .ok_or_else(|| format!("No bind is configured for '{path}'").into())
+    }
+
+    /// Assigns a mirror to a single architecture.
+    ///
+    /// A plain mirror setting is converted into a per-arch table, keeping
+    /// the previous URL as the `default` entry.
+    ///
+    /// # Parameters
+    /// - `sett`: The settings being modified.
+    /// - `value`: An `ARCH=URL` pair.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the pair was valid.
+    /// - `Err` if the value is not in `ARCH=URL` form.
+    fn set_arch_mirror(sett: &mut Settings, value: &str) -> Result<(), Box<dyn Error>> {
+        let (arch, url) = value
+            .split_once('=')
+            .filter(|(arch, url)| !arch.is_empty() && !url.is_empty())
+            .ok_or(format!(
+                "Invalid mirror mapping '{value}', expected ARCH=URL"
+            ))?;
+
+        let mut table = match &sett.default_mirror {
+            MirrorSetting::PerArch(table) => table.clone(),
+            MirrorSetting::Single(url) => [("default".to_string(), url.clone())].into(),
+        };
+        table.insert(arch.to_string(), url.to_string());
+        sett.default_mirror = MirrorSetting::PerArch(table);
+        Ok(())
+    }
 }
@@ -0,0 +1,284 @@
+//! JSON-RPC daemon for graphical frontends.
+//!
+//! `daemon` listens on a Unix socket and accepts one JSON-RPC 2.0 request
+//! per line. Queries (`search`, `list`) are answered directly from the
+//! rootfs databases, while operations (`setup`, `run`, `apk`) are executed
+//! as ALPack subcommands whose output is streamed back as `progress`
+//! notifications before the final result, so frontends never need to
+//! scrape terminal output.
+
+use crate::index::{load_index, load_installed};
+use crate::settings::settings_rootfs_dir;
+use crate::utils::check_rootfs_exists;
+use sandbox_utils::{invalid_arg, missing_arg, parse_value};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Standard JSON-RPC error code for malformed requests.
+const PARSE_ERROR: i32 = -32700;
+/// Standard JSON-RPC error code for unknown methods.
+const METHOD_NOT_FOUND: i32 = -32601;
+/// Standard JSON-RPC error code for invalid parameters.
+const INVALID_PARAMS: i32 = -32602;
+/// Application error code for failed operations.
+const OPERATION_FAILED: i32 = -32000;
+
+/// Writer shared by the threads answering a single connection.
+type SharedWriter = Arc<Mutex<UnixStream>>;
+
+/// Controller for the `daemon` subcommand.
+pub struct Daemon {
+    /// Arguments captured after the `daemon` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Daemon {
+    /// Creates a new `Daemon` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Daemon { remaining_args }
+    }
+
+    /// Binds the socket and serves clients until the process is stopped.
+    ///
+    /// Every connection is handled in its own thread and requests on a
+    /// connection are processed in order. The socket is only accessible
+    /// to the daemon's user, since requests run commands on its behalf.
+    ///
+    /// # Returns
+    /// - `Err` if arguments are invalid or the socket cannot be bound.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut socket = None;
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--socket=") => {
+                    socket = Some(PathBuf::from(parse_value!("daemon", "path", arg)?));
+                }
+                "-S" | "--socket" => {
+                    socket = Some(parse_value!("daemon", "path", arg, args.pop_front())?.into());
+                }
+                _ => return invalid_arg!("daemon", arg),
+            }
+        }
+
+        let Some(socket) = socket else {
+            return missing_arg!("daemon");
+        };
+
+        if socket.exists() {
+            if UnixStream::connect(&socket).is_ok() {
+                return Err(
+                    format!("A daemon is already listening on {}", socket.display()).into(),
+                );
+            }
+            fs::remove_file(&socket)?;
+        }
+
+        // SAFETY: umask only changes the file mode creation mask of the process.
+        let umask = unsafe { libc::umask(0o177) };
+        let listener = UnixListener::bind(&socket);
+        // SAFETY: as above, restoring the previous mask.
+        unsafe { libc::umask(umask) };
+        let listener = listener?;
+        fs::set_permissions(&socket, fs::Permissions::from_mode(0o600))?;
+        println!("Listening on {}", socket.display());
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    thread::spawn(move || {
+                        if let Err(e) = Self::serve(stream) {
+                            eprintln!("\x1b[1;33mWarning\x1b[0m: Client error: {e}");
+                        }
+                    });
+                }
+                Err(e) => eprintln!("\x1b[1;33mWarning\x1b[0m: Connection failed: {e}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads requests from a client and writes one response per request.
+    fn serve(stream: UnixStream) -> Result<(), Box<dyn Error>> {
+        let reader = BufReader::new(stream.try_clone()?);
+        let writer: SharedWriter = Arc::new(Mutex::new(stream));
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str::<Value>(&line) {
+                Ok(request) => {
+                    let id = request.get("id").cloned().unwrap_or(Value::Null);
+                    let method = request["method"].as_str().unwrap_or_default();
+
+                    match Self::dispatch(method, &request["params"], &id, &writer) {
+                        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                        Err((code, message)) => Self::error(id, code, &message),
+                    }
+                }
+                Err(e) => Self::error(Value::Null, PARSE_ERROR, &e.to_string()),
+            };
+
+            Self::send(&writer, &response)?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single method call.
+    ///
+    /// # Parameters
+    /// - `method`: The JSON-RPC method name.
+    /// - `params`: The request parameters.
+    /// - `id`: The request id, echoed in progress notifications.
+    /// - `writer`: The connection used for progress notifications.
+    ///
+    /// # Returns
+    /// - `Ok(Value)` with the method result.
+    /// - `Err((code, message))` describing the JSON-RPC error.
+    fn dispatch(
+        method: &str,
+        params: &Value,
+        id: &Value,
+        writer: &SharedWriter,
+    ) -> Result<Value, (i32, String)> {
+        let rootfs = params["rootfs"]
+            .as_str()
+            .map(PathBuf::from)
+            .unwrap_or_else(settings_rootfs_dir);
+
+        match method {
+            "search" => {
+                let terms: Vec<String> = Self::string_list(&params["terms"])?
+                    .iter()
+                    .map(|t| t.to_lowercase())
+                    .collect();
+
+                check_rootfs_exists(rootfs.clone()).map_err(Self::failed)?;
+                let results: Vec<_> = load_index(&rootfs.join("rootfs"))
+                    .map_err(Self::failed)?
+                    .into_iter()
+                    .filter(|e| {
+                        let (name, desc) = (e.name.to_lowercase(), e.description.to_lowercase());
+                        terms.iter().all(|t| name.contains(t) || desc.contains(t))
+                    })
+                    .collect();
+
+                serde_json::to_value(results).map_err(Self::failed)
+            }
+            "list" => {
+                check_rootfs_exists(rootfs.clone()).map_err(Self::failed)?;
+                let installed = load_installed(&rootfs.join("rootfs")).map_err(Self::failed)?;
+                serde_json::to_value(installed).map_err(Self::failed)
+            }
+            "setup" | "run" | "apk" => {
+                let mut argv = vec![format!("--rootfs={}", rootfs.display())];
+                argv.push(method.to_string());
+                argv.extend(Self::string_list(&params["args"])?);
+
+                let code = Self::spawn(&argv, id, writer).map_err(Self::failed)?;
+                Ok(json!({ "exit_code": code }))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{method}'"))),
+        }
+    }
+
+    /// Runs an ALPack subcommand, streaming its output as notifications.
+    ///
+    /// # Parameters
+    /// - `argv`: The global options, subcommand and arguments.
+    /// - `id`: The request id attached to every notification.
+    /// - `writer`: The connection receiving the notifications.
+    ///
+    /// # Returns
+    /// - `Ok(i32)` with the exit code of the subcommand.
+    /// - `Err` if it cannot be started.
+    fn spawn(argv: &[String], id: &Value, writer: &SharedWriter) -> Result<i32, Box<dyn Error>> {
+        let mut child = Command::new(env::current_exe()?)
+            .arg("--quiet")
+            .args(argv)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let streams: Vec<(&str, Box<dyn Read + Send>)> = vec![
+            (
+                "stdout",
+                Box::new(child.stdout.take().ok_or("stdout unavailable")?),
+            ),
+            (
+                "stderr",
+                Box::new(child.stderr.take().ok_or("stderr unavailable")?),
+            ),
+        ];
+
+        let handles: Vec<_> = streams
+            .into_iter()
+            .map(|(name, pipe)| {
+                let (id, writer) = (id.clone(), Arc::clone(writer));
+                thread::spawn(move || {
+                    for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                        let event = json!({
+                            "jsonrpc": "2.0",
+                            "method": "progress",
+                            "params": { "id": id, "stream": name, "line": line },
+                        });
+                        let _ = Self::send(&writer, &event);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+        Ok(child.wait()?.code().unwrap_or(-1))
+    }
+
+    /// Extracts an optional array of strings from the parameters.
+    fn string_list(value: &Value) -> Result<Vec<String>, (i32, String)> {
+        match value {
+            Value::Null => Ok(Vec::new()),
+            Value::Array(items) => items
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(str::to_string)
+                        .ok_or((INVALID_PARAMS, "Expected an array of strings".to_string()))
+                })
+                .collect(),
+            _ => Err((INVALID_PARAMS, "Expected an array of strings".to_string())),
+        }
+    }
+
+    /// Maps an operation error to a JSON-RPC error.
+    fn failed(e: impl ToString) -> (i32, String) {
+        (OPERATION_FAILED, e.to_string())
+    }
+
+    /// Builds a JSON-RPC error response.
+    fn error(id: Value, code: i32, message: &str) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+    }
+
+    /// Writes a message as a single line on the connection.
+    fn send(writer: &SharedWriter, message: &Value) -> Result<(), Box<dyn Error>> {
+        let mut stream = writer.lock().map_err(|_| "connection lock poisoned")?;
+        writeln!(stream, "{message}")?;
+        Ok(())
+    }
+}
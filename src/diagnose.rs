@@ -0,0 +1,120 @@
+//! Runtime diagnostics module.
+//!
+//! `env` prints the fully resolved context ALPack is working with, after
+//! configuration files, profiles, environment variables and global options
+//! have been applied, in a form suitable for pasting into bug reports.
+
+use crate::mirror::env_release;
+use crate::settings::{
+    settings_cache_dir, settings_cmd, settings_config_file, settings_mirror, settings_rootfs_dir,
+};
+use crate::utils::is_json;
+use crate::wsl::is_wsl;
+use sandbox_utils::{app_arch, invalid_arg, safe_home};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// Controller for the `env` subcommand.
+pub struct Diagnose {
+    /// Arguments captured after the `env` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Diagnose {
+    /// Creates a new `Diagnose` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Diagnose { remaining_args }
+    }
+
+    /// Collects and prints the resolved runtime context.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing.
+    /// - `Err` if an argument is given or the JSON output cannot be serialized.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(arg) = self.remaining_args.first() {
+            return invalid_arg!("env", arg);
+        }
+
+        let arch = app_arch();
+        let rootfs = settings_rootfs_dir();
+        let backend = settings_cmd();
+
+        let rootfs_state = if rootfs.join("rootfs").is_dir() {
+            fs::read_to_string(rootfs.join("rootfs/etc/alpine-release"))
+                .map(|v| format!("Alpine {}", v.trim()))
+                .unwrap_or_else(|_| "present".to_string())
+        } else {
+            "not installed".to_string()
+        };
+
+        let home = match env::var("HOME") {
+            Ok(home) if !home.is_empty() => home,
+            _ => format!("{} (fallback, HOME is unset)", safe_home().display()),
+        };
+
+        let mut rows = vec![
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("config_file", settings_config_file().display().to_string()),
+            ("rootfs", format!("{} ({rootfs_state})", rootfs.display())),
+            ("arch", arch.clone()),
+            ("backend", backend.clone()),
+            (
+                "backend_path",
+                Self::find_in_path(&backend)
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|| "not found in PATH".to_string()),
+            ),
+            ("cache_dir", settings_cache_dir().display().to_string()),
+            ("mirror", settings_mirror(&arch)),
+            ("release", env_release(&rootfs)),
+            ("home", home),
+            ("wsl", is_wsl().to_string()),
+        ];
+
+        let mut vars: Vec<(String, String)> = env::vars()
+            .filter(|(key, _)| key.starts_with("ALPACK_"))
+            .map(|(key, value)| {
+                if key.ends_with("_PASSWORD") || key.ends_with("_TOKEN") {
+                    (key, "********".to_string())
+                } else {
+                    (key, value)
+                }
+            })
+            .collect();
+        vars.sort();
+
+        if is_json() {
+            let mut map: serde_json::Map<String, serde_json::Value> = rows
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.into()))
+                .collect();
+            let vars: serde_json::Map<String, serde_json::Value> =
+                vars.into_iter().map(|(k, v)| (k, v.into())).collect();
+            map.insert("variables".to_string(), vars.into());
+            println!("{}", serde_json::to_string_pretty(&map)?);
+            return Ok(());
+        }
+
+        if vars.is_empty() {
+            rows.push(("variables", "none".to_string()));
+        }
+
+        for (key, value) in rows {
+            println!("{key:<13} {value}");
+        }
+        for (key, value) in vars {
+            println!("{key:<13} {value}");
+        }
+        Ok(())
+    }
+
+    /// Resolves a program name against the host `PATH`.
+    fn find_in_path(program: &str) -> Option<PathBuf> {
+        env::split_paths(&env::var_os("PATH")?)
+            .map(|dir| dir.join(program))
+            .find(|path| path.is_file())
+    }
+}
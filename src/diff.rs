@@ -0,0 +1,225 @@
+//! Rootfs comparison module.
+//!
+//! `diff` compares two rootfs trees from the host and reports which
+//! installed packages changed version and which files were added, removed
+//! or modified, so the effect of an operation or an update can be reviewed.
+
+use crate::index::load_installed;
+use crate::utils::is_json;
+use sandbox_utils::{invalid_arg, missing_arg};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// A package present in both trees with different versions.
+#[derive(Serialize, Debug)]
+struct Upgrade {
+    /// The package name.
+    name: String,
+    /// The version in the first tree.
+    from: String,
+    /// The version in the second tree.
+    to: String,
+}
+
+/// Controller for the `diff` subcommand.
+pub struct Diff {
+    /// Arguments captured after the `diff` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Diff {
+    /// Creates a new `Diff` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Diff { remaining_args }
+    }
+
+    /// Compares the two given trees and prints the differences.
+    ///
+    /// Each argument may be an environment directory (containing `rootfs`)
+    /// or an extracted Alpine root.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing.
+    /// - `Err` if the arguments are invalid or a tree does not exist.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(arg) = self.remaining_args.iter().find(|a| a.starts_with('-')) {
+            return invalid_arg!("diff", arg);
+        }
+
+        let [a, b] = self.remaining_args.as_slice() else {
+            return match self.remaining_args.get(2) {
+                Some(extra) => invalid_arg!("diff", extra),
+                None => missing_arg!("diff", essential),
+            };
+        };
+
+        let (a, b) = (Self::resolve(a)?, Self::resolve(b)?);
+        let (added, removed, upgraded) = Self::diff_packages(&a, &b);
+
+        let mut old = BTreeMap::new();
+        let mut new = BTreeMap::new();
+        Self::collect(&a, Path::new(""), &mut old);
+        Self::collect(&b, Path::new(""), &mut new);
+
+        let mut files: Vec<(char, &PathBuf)> = Vec::new();
+        for (path, meta) in &old {
+            match new.get(path) {
+                None => files.push(('-', path)),
+                Some(other) if Self::changed(&a, &b, path, meta, other) => files.push(('~', path)),
+                Some(_) => {}
+            }
+        }
+        files.extend(
+            new.keys()
+                .filter(|p| !old.contains_key(*p))
+                .map(|p| ('+', p)),
+        );
+        files.sort_by(|x, y| x.1.cmp(y.1));
+
+        if is_json() {
+            let list = |kind: char| -> Vec<String> {
+                files
+                    .iter()
+                    .filter(|(k, _)| *k == kind)
+                    .map(|(_, p)| format!("/{}", p.display()))
+                    .collect()
+            };
+            let report = json!({
+                "packages": { "added": added, "removed": removed, "upgraded": upgraded },
+                "files": { "added": list('+'), "removed": list('-'), "changed": list('~') },
+            });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+
+        for pkg in &added {
+            println!("+ {pkg}");
+        }
+        for pkg in &removed {
+            println!("- {pkg}");
+        }
+        for u in &upgraded {
+            println!("~ {} {} -> {}", u.name, u.from, u.to);
+        }
+
+        if !(added.is_empty() && removed.is_empty() && upgraded.is_empty()) {
+            println!();
+        }
+
+        for (kind, path) in &files {
+            println!("{kind} /{}", path.display());
+        }
+
+        if files.is_empty() && added.is_empty() && removed.is_empty() && upgraded.is_empty() {
+            println!("No differences found.");
+        }
+        Ok(())
+    }
+
+    /// Resolves an argument to the Alpine root it designates.
+    fn resolve(arg: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let path = PathBuf::from(arg);
+
+        if path.join("rootfs").is_dir() {
+            Ok(path.join("rootfs"))
+        } else if path.is_dir() {
+            Ok(path)
+        } else {
+            Err(format!("'{arg}' is not a rootfs directory").into())
+        }
+    }
+
+    /// Compares the installed packages of two roots.
+    ///
+    /// # Returns
+    /// The added and removed packages as `name-version`, and the upgrades.
+    fn diff_packages(a: &Path, b: &Path) -> (Vec<String>, Vec<String>, Vec<Upgrade>) {
+        let versions = |root: &Path| -> BTreeMap<String, String> {
+            load_installed(root)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|e| (e.name, e.version))
+                .collect()
+        };
+        let (old, new) = (versions(a), versions(b));
+
+        let added = new
+            .iter()
+            .filter(|(name, _)| !old.contains_key(*name))
+            .map(|(name, v)| format!("{name}-{v}"))
+            .collect();
+
+        let removed = old
+            .iter()
+            .filter(|(name, _)| !new.contains_key(*name))
+            .map(|(name, v)| format!("{name}-{v}"))
+            .collect();
+
+        let upgraded = old
+            .iter()
+            .filter_map(|(name, from)| {
+                let to = new.get(name).filter(|to| *to != from)?;
+                Some(Upgrade {
+                    name: name.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                })
+            })
+            .collect();
+
+        (added, removed, upgraded)
+    }
+
+    /// Walks a tree collecting the metadata of every entry by relative path.
+    ///
+    /// Symbolic links are recorded, not followed. Directories that cannot be
+    /// read without root, such as `/root`, are compared by metadata only.
+    fn collect(root: &Path, rel: &Path, entries: &mut BTreeMap<PathBuf, Metadata>) {
+        let Ok(dir) = fs::read_dir(root.join(rel)) else {
+            return;
+        };
+
+        for entry in dir.flatten() {
+            let path = rel.join(entry.file_name());
+            let Ok(meta) = fs::symlink_metadata(root.join(&path)) else {
+                continue;
+            };
+
+            if meta.is_dir() {
+                Self::collect(root, &path, entries);
+            }
+            entries.insert(path, meta);
+        }
+    }
+
+    /// Returns whether an entry present in both trees differs.
+    ///
+    /// Entries differ in type, permissions, size or link target. Regular
+    /// files of equal size with different modification times are compared
+    /// byte for byte, so files merely rewritten with the same content are
+    /// not reported.
+    fn changed(a: &Path, b: &Path, rel: &Path, old: &Metadata, new: &Metadata) -> bool {
+        if old.file_type() != new.file_type() || old.mode() != new.mode() {
+            return true;
+        }
+
+        if old.file_type().is_symlink() {
+            return fs::read_link(a.join(rel)).ok() != fs::read_link(b.join(rel)).ok();
+        }
+
+        if !old.is_file() {
+            return false;
+        }
+
+        if old.len() != new.len() {
+            return true;
+        }
+
+        old.mtime() != new.mtime() && fs::read(a.join(rel)).ok() != fs::read(b.join(rel)).ok()
+    }
+}
@@ -0,0 +1,164 @@
+//! Named rootfs environments.
+//!
+//! `setup --name <ENV>` records the rootfs it creates in `envs.toml`, next
+//! to the configuration file, so `run`, `apk` and `builder` can select it
+//! with `--name <ENV>` instead of its path. `list` prints the registry.
+
+use crate::settings::{settings_rootfs_dir, settings_state_dir};
+use crate::utils::is_json;
+use sandbox_utils::{app_name, invalid_arg};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the registry file in the per-user state directory.
+const REGISTRY_FILE: &str = "envs.toml";
+
+/// Directory holding named environments created without `--rootfs`,
+/// next to the default rootfs directory.
+const ENVS_DIR: &str = "envs";
+
+/// Returns the location of the registry file.
+fn registry_file() -> PathBuf {
+    settings_state_dir().join(REGISTRY_FILE)
+}
+
+/// Reads the registry, mapping environment names to rootfs directories.
+fn load() -> BTreeMap<String, PathBuf> {
+    fs::read_to_string(registry_file())
+        .ok()
+        .and_then(|content| toml::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Checks that an environment name is usable as a directory name.
+fn check_name(name: &str) -> Result<(), Box<dyn Error>> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !name.starts_with('.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(
+            format!("Invalid environment name '{name}': use letters, digits, '-', '_' and '.'")
+                .into(),
+        )
+    }
+}
+
+/// Returns the rootfs directory of a registered environment.
+///
+/// # Returns
+/// - `Ok(PathBuf)` with the registered directory.
+/// - `Err` if no environment has this name.
+pub fn lookup(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    load().remove(name).ok_or_else(|| {
+        format!(
+            "Unknown environment '{name}'.\nCreate it with '{} setup --name {name}' or see '{} list'.",
+            app_name(),
+            app_name()
+        )
+        .into()
+    })
+}
+
+/// Returns the rootfs directory `setup --name` uses for an environment.
+///
+/// Registered environments keep their directory; new ones are placed in
+/// `envs/<name>` next to the default rootfs directory.
+pub fn setup_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    check_name(name)?;
+
+    if let Some(path) = load().remove(name) {
+        return Ok(path);
+    }
+
+    let base = settings_rootfs_dir();
+    let parent = base.parent().unwrap_or(Path::new("."));
+    Ok(parent.join(ENVS_DIR).join(name))
+}
+
+/// Records the rootfs directory of an environment in the registry.
+///
+/// # Parameters
+/// - `name`: The environment name.
+/// - `rootfs`: The environment directory containing `rootfs`.
+pub fn register(name: &str, rootfs: &Path) -> Result<(), Box<dyn Error>> {
+    check_name(name)?;
+
+    let mut envs = load();
+    envs.insert(name.to_string(), rootfs.canonicalize()?);
+
+    let file = registry_file();
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&file, toml::to_string_pretty(&envs)?)?;
+    Ok(())
+}
+
+/// Controller for the `list` subcommand.
+pub struct List {
+    /// Arguments captured after the `list` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl List {
+    /// Creates a new `List` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        List { remaining_args }
+    }
+
+    /// Prints the registered environments and whether they are set up.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing.
+    /// - `Err` if an argument is given.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(arg) = self.remaining_args.first() {
+            return invalid_arg!("list", arg);
+        }
+
+        let envs = load();
+
+        if is_json() {
+            let list: Vec<_> = envs
+                .iter()
+                .map(|(name, path)| {
+                    json!({
+                        "name": name,
+                        "rootfs": path,
+                        "ready": path.join("rootfs").is_dir(),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&list)?);
+            return Ok(());
+        }
+
+        if envs.is_empty() {
+            println!(
+                "No named environments. Create one with '{} setup --name <ENV>'.",
+                app_name()
+            );
+            return Ok(());
+        }
+
+        let width = envs.keys().map(String::len).max().unwrap_or(0).max(4);
+        println!("{:<width$}  {:<7}  ROOTFS", "NAME", "STATUS");
+        for (name, path) in &envs {
+            let status = if path.join("rootfs").is_dir() {
+                "ready"
+            } else {
+                "missing"
+            };
+            println!("{name:<width$}  {status:<7}  {}", path.display());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+//! Rootfs commands exported to the host.
+//!
+//! `export-bin <CMD>` writes a wrapper script to `~/.local/bin` that runs
+//! the command of the rootfs through `exec`, so tools installed in the
+//! rootfs can be called from the host like native commands.
+//!
+//! `export-app <PKG>` does the same for graphical applications: the
+//! `.desktop` launchers and icons of the package are copied to the host
+//! data directory, with `Exec=` running the application through
+//! `exec --gui`, so they appear in the host application menu.
+
+use crate::envs;
+use crate::index::installed_files;
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{check_rootfs_exists, in_path, shell_quote};
+use crate::which::Which;
+use sandbox_utils::{invalid_arg, missing_arg, parse_value};
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// First line after the shebang of every generated wrapper, used to
+/// recognize the files that may be overwritten.
+const WRAPPER_MARKER: &str = "# Exported by ALPack";
+
+/// Prefix of the launchers written by `export-app`, keeping them apart
+/// from the host ones with the same name.
+const LAUNCHER_PREFIX: &str = "alpack-";
+
+/// Controller for the `export-bin` subcommand.
+pub struct ExportBin {
+    /// Arguments captured after the `export-bin` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl ExportBin {
+    /// Creates a new `ExportBin` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        ExportBin { remaining_args }
+    }
+
+    /// Writes the host wrapper of a rootfs command.
+    ///
+    /// The wrapper runs the command with `exec`, so its arguments reach the
+    /// command unchanged.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the wrapper is written.
+    /// - `Err` if the command is not in the rootfs or a file that was not
+    ///   exported by ALPack already has the name.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+        let mut alias = None;
+        let mut command = None;
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--alias=") => {
+                    alias = Some(parse_value!("export-bin", "alias", arg)?);
+                }
+                "-a" | "--alias" => {
+                    alias = Some(parse_value!("export-bin", "alias", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--name=") => {
+                    rootfs = envs::lookup(&parse_value!("export-bin", "name", arg)?)?;
+                }
+                "--name" => {
+                    let value = parse_value!("export-bin", "name", arg, args.pop_front())?;
+                    rootfs = envs::lookup(&value)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("export-bin", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("export-bin", "directory", arg, args.pop_front())?.into();
+                }
+                a if a.starts_with('-') || command.is_some() => {
+                    return invalid_arg!("export-bin", arg);
+                }
+                _ => command = Some(arg),
+            }
+        }
+
+        let Some(command) = command else {
+            return missing_arg!("export-bin");
+        };
+        let alias = alias.unwrap_or_else(|| command.to_string());
+        if alias.is_empty() || alias.contains('/') {
+            return invalid_arg!("export-bin", &alias);
+        }
+
+        check_rootfs_exists(rootfs.clone())?;
+        let rootfs = fs::canonicalize(&rootfs)?;
+        let path = Which::find(&rootfs.join("rootfs"), command)
+            .ok_or(format!("Command not found in rootfs: {command}"))?;
+
+        let bin_dir = Self::bin_dir()?;
+        let wrapper = bin_dir.join(&alias);
+        let foreign = wrapper.exists()
+            && !fs::read_to_string(&wrapper).is_ok_and(|content| content.contains(WRAPPER_MARKER));
+        if foreign {
+            return Err(format!(
+                "{} exists and was not exported by ALPack",
+                wrapper.display()
+            )
+            .into());
+        }
+
+        let script = format!(
+            "#!/bin/sh\n{WRAPPER_MARKER}: {path} from {}\nexec {} exec --rootfs={} -- {} \"$@\"\n",
+            rootfs.display(),
+            shell_quote(&env::current_exe()?.to_string_lossy()),
+            shell_quote(&rootfs.to_string_lossy()),
+            shell_quote(&path),
+        );
+
+        fs::create_dir_all(&bin_dir)?;
+        fs::write(&wrapper, script)?;
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755))?;
+        println!("Exported {path} as {}", wrapper.display());
+
+        let in_path =
+            env::var_os("PATH").is_some_and(|p| env::split_paths(&p).any(|dir| dir == bin_dir));
+        if !in_path {
+            eprintln!(
+                "\x1b[1;33mWarning\x1b[0m: {} is not in PATH, add it to call '{alias}' directly.",
+                bin_dir.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the per-user directory receiving the wrappers, `~/.local/bin`.
+    fn bin_dir() -> Result<PathBuf, Box<dyn Error>> {
+        Ok(PathBuf::from(env::var("HOME")?).join(".local/bin"))
+    }
+}
+
+/// Controller for the `export-app` subcommand.
+pub struct ExportApp {
+    /// Arguments captured after the `export-app` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl ExportApp {
+    /// Creates a new `ExportApp` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        ExportApp { remaining_args }
+    }
+
+    /// Copies the launchers and icons of a rootfs package to the host.
+    ///
+    /// Launchers are written to `applications` and icons to `icons` in
+    /// `$XDG_DATA_HOME` (`~/.local/share` by default).
+    ///
+    /// # Returns
+    /// - `Ok(())` once the launchers are written.
+    /// - `Err` if the package is not installed or has no launcher.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+        let mut package = None;
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--name=") => {
+                    rootfs = envs::lookup(&parse_value!("export-app", "name", arg)?)?;
+                }
+                "--name" => {
+                    let value = parse_value!("export-app", "name", arg, args.pop_front())?;
+                    rootfs = envs::lookup(&value)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("export-app", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("export-app", "directory", arg, args.pop_front())?.into();
+                }
+                a if a.starts_with('-') || package.is_some() => {
+                    return invalid_arg!("export-app", arg);
+                }
+                _ => package = Some(arg),
+            }
+        }
+
+        let Some(package) = package else {
+            return missing_arg!("export-app");
+        };
+
+        check_rootfs_exists(rootfs.clone())?;
+        let rootfs = fs::canonicalize(&rootfs)?;
+        let root = rootfs.join("rootfs");
+        let files = installed_files(&root, package)?;
+
+        let data_dir = Self::data_dir()?;
+        let icons_dir = data_dir.join("icons");
+        let mut icons = Vec::new();
+        for file in &files {
+            let rest = file
+                .strip_prefix("usr/share/icons/")
+                .or_else(|| file.strip_prefix("usr/share/pixmaps/"));
+            let Some(dest) = rest.map(|rest| icons_dir.join(rest)) else {
+                continue;
+            };
+
+            let Some(src) = Which::resolve(&root, &format!("/{file}")).filter(|p| p.is_file())
+            else {
+                continue;
+            };
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&src, &dest)?;
+            icons.push((format!("/{file}"), dest));
+        }
+
+        let exec_prefix = [
+            env::current_exe()?.to_string_lossy().into_owned(),
+            "exec".to_string(),
+            format!("--rootfs={}", rootfs.display()),
+            "--gui".to_string(),
+            "--".to_string(),
+        ]
+        .iter()
+        .map(|arg| Self::desktop_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+        let apps_dir = data_dir.join("applications");
+        let mut written = 0;
+        for file in files
+            .iter()
+            .filter(|f| f.starts_with("usr/share/applications/") && f.ends_with(".desktop"))
+        {
+            let Some(src) = Which::resolve(&root, &format!("/{file}")) else {
+                continue;
+            };
+            let launcher = Self::rewrite_launcher(&fs::read_to_string(&src)?, &exec_prefix, &icons);
+
+            let name = Path::new(file)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let dest = apps_dir.join(format!("{LAUNCHER_PREFIX}{name}"));
+            fs::create_dir_all(&apps_dir)?;
+            fs::write(&dest, launcher)?;
+            println!("Exported {name} as {}", dest.display());
+            written += 1;
+        }
+
+        if written == 0 {
+            return Err(format!("Package '{package}' installs no .desktop launcher").into());
+        }
+
+        if in_path("update-desktop-database") {
+            let _ = Command::new("update-desktop-database")
+                .arg(&apps_dir)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+        Ok(())
+    }
+
+    /// Adapts a launcher of the rootfs to the host.
+    ///
+    /// Every `Exec=` line, including those of the desktop actions, runs its
+    /// command through ALPack; `TryExec=` is dropped as the program only
+    /// exists in the rootfs, and icons given by a path point at the copies.
+    ///
+    /// # Parameters
+    /// - `content`: The launcher of the rootfs.
+    /// - `exec_prefix`: The quoted ALPack command prepended to `Exec=`.
+    /// - `icons`: The copied icons, as rootfs path and host path.
+    ///
+    /// # Returns
+    /// The launcher to install on the host.
+    fn rewrite_launcher(content: &str, exec_prefix: &str, icons: &[(String, PathBuf)]) -> String {
+        content
+            .lines()
+            .filter(|line| !line.starts_with("TryExec="))
+            .map(|line| {
+                if let Some(cmd) = line.strip_prefix("Exec=") {
+                    return format!("Exec={exec_prefix} {cmd}\n");
+                }
+                let icon = line.strip_prefix("Icon=");
+                let copied = icons.iter().find(|(path, _)| Some(path.as_str()) == icon);
+                if let Some((_, host)) = copied {
+                    return format!("Icon={}\n", host.display());
+                }
+                format!("{line}\n")
+            })
+            .collect()
+    }
+
+    /// Quotes an argument of a launcher `Exec=` key.
+    ///
+    /// Arguments with reserved characters are wrapped in double quotes,
+    /// escaping the characters the desktop entry specification requires.
+    fn desktop_quote(arg: &str) -> String {
+        let reserved = |c: char| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c);
+        if !arg.is_empty() && !arg.contains(reserved) {
+            return arg.to_string();
+        }
+
+        let mut quoted = String::from('"');
+        for c in arg.chars() {
+            if matches!(c, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    /// Returns the per-user data directory, `$XDG_DATA_HOME` or `~/.local/share`.
+    fn data_dir() -> Result<PathBuf, Box<dyn Error>> {
+        match env::var("XDG_DATA_HOME") {
+            Ok(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+            _ => Ok(PathBuf::from(env::var("HOME")?).join(".local/share")),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Package prefetch module.
+//!
+//! `fetch` downloads packages and their whole dependency closure without
+//! installing them. By default they land in the shared apk cache, so later
+//! installs work offline, while `--output` collects them in a directory
+//! that can be copied to air-gapped machines.
+
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{apk_cache_dir, bind_args, check_rootfs_exists, map_result};
+use sandbox_utils::{invalid_arg, missing_arg, parse_value, SandBox, SandBoxConfig};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Mount point of the destination directory inside the rootfs.
+const FETCH_DIR: &str = "/var/cache/alpack-fetch";
+
+/// Controller for the `fetch` subcommand.
+pub struct Fetch {
+    /// Arguments captured after the `fetch` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Fetch {
+    /// Creates a new `Fetch` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Fetch { remaining_args }
+    }
+
+    /// Downloads the given packages with their dependencies.
+    ///
+    /// The package indexes are refreshed first so the closure matches the
+    /// current repositories, then `apk fetch --recursive` writes every
+    /// package into the bound destination directory.
+    ///
+    /// # Returns
+    /// - `Ok(())` if every package was downloaded.
+    /// - `Err` if no package is given, the rootfs is missing, or apk fails.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+        let mut output: Option<PathBuf> = None;
+        let mut pkgs = Vec::new();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--output=") => {
+                    output = Some(parse_value!("fetch", "directory", arg)?.into());
+                }
+                "-o" | "--output" => {
+                    output =
+                        Some(parse_value!("fetch", "directory", arg, args.pop_front())?.into());
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("fetch", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("fetch", "directory", arg, args.pop_front())?.into();
+                }
+                a if a.starts_with('-') => return invalid_arg!("fetch", arg),
+                _ => pkgs.push(arg),
+            }
+        }
+
+        if pkgs.is_empty() {
+            return missing_arg!("fetch");
+        }
+
+        check_rootfs_exists(rootfs.clone())?;
+
+        let dest = output.unwrap_or_else(|| apk_cache_dir(&rootfs));
+        fs::create_dir_all(&dest)?;
+        fs::create_dir_all(
+            rootfs
+                .join("rootfs")
+                .join(FETCH_DIR.trim_start_matches('/')),
+        )?;
+
+        let run_cmd = format!(
+            "apk update && apk fetch --recursive --output {FETCH_DIR} {}",
+            pkgs.join(" ")
+        );
+
+        let config = SandBoxConfig {
+            rootfs,
+            run_cmd,
+            args_bind: bind_args(&dest, Path::new(FETCH_DIR)),
+            use_root: true,
+            ignore_extra_bind: true,
+            ..Default::default()
+        };
+
+        map_result(SandBox::run(config))?;
+        println!("Packages saved to {}", dest.display());
+        Ok(())
+    }
+}
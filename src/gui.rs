@@ -0,0 +1,253 @@
+//! Graphical application support for sandbox sessions.
+//!
+//! `run --gui` exposes the host display server to the session: the X11
+//! socket directory and the Wayland socket are bound at their host paths
+//! and `DISPLAY`, `WAYLAND_DISPLAY` and `XAUTHORITY` are forwarded. As the
+//! X11 cookie may be tied to the host name, a copy valid for any host is
+//! written with `xauth` when it is installed.
+//!
+//! `run --gpu` binds the DRI and NVIDIA device nodes and the host graphics
+//! driver (ICD) directories, for hardware-accelerated applications.
+
+use crate::utils::{bind_args, dev_bind_args, in_path, ro_bind_args};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Directory holding the X11 server sockets.
+const X11_SOCKET_DIR: &str = "/tmp/.X11-unix";
+
+/// Host directories declaring the OpenGL, EGL and Vulkan drivers.
+const ICD_DIRS: [&str; 6] = [
+    "/usr/share/vulkan/icd.d",
+    "/usr/share/vulkan/implicit_layer.d",
+    "/usr/share/glvnd/egl_vendor.d",
+    "/usr/share/egl/egl_external_platform.d",
+    "/etc/vulkan/icd.d",
+    "/etc/OpenCL/vendors",
+];
+
+/// Location of the X11 cookie file inside the sandbox.
+const SANDBOX_XAUTHORITY: &str = "/tmp/.alpack-xauthority";
+
+/// Display server access prepared for a session.
+///
+/// The cookie file written for the session is deleted when dropped.
+pub struct GuiSession {
+    /// Backend arguments binding the sockets and the cookie file.
+    pub binds: Vec<String>,
+    /// Variables to export inside the session.
+    pub env: Vec<(String, String)>,
+    /// Cookie file written for the session, if any.
+    cookie: Option<PathBuf>,
+}
+
+impl GuiSession {
+    /// Prepares access to the host X11 and Wayland displays.
+    ///
+    /// # Returns
+    /// - `Ok(GuiSession)` with the binds and variables for every display found.
+    /// - `Err` if neither `DISPLAY` nor `WAYLAND_DISPLAY` is set.
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let mut session = GuiSession {
+            binds: Vec::new(),
+            env: Vec::new(),
+            cookie: None,
+        };
+
+        let display = env::var("DISPLAY").ok().filter(|d| !d.is_empty());
+        let wayland = env::var("WAYLAND_DISPLAY").ok().filter(|d| !d.is_empty());
+
+        if display.is_none() && wayland.is_none() {
+            return Err(
+                "--gui needs a graphical session, but DISPLAY and WAYLAND_DISPLAY are not set"
+                    .into(),
+            );
+        }
+
+        if let Some(display) = display {
+            session.setup_x11(&display)?;
+        }
+        if let Some(wayland) = wayland {
+            session.setup_wayland(&wayland)?;
+        }
+
+        for key in ["XDG_SESSION_TYPE", "XDG_CURRENT_DESKTOP"] {
+            if let Ok(value) = env::var(key) {
+                session.env.push((key.to_string(), value));
+            }
+        }
+        Ok(session)
+    }
+
+    /// Binds the X11 sockets and the cookie of the display.
+    fn setup_x11(&mut self, display: &str) -> Result<(), Box<dyn Error>> {
+        let socket_dir = Path::new(X11_SOCKET_DIR);
+        if socket_dir.is_dir() {
+            self.binds.push(bind_args(socket_dir, socket_dir));
+        }
+        self.env.push(("DISPLAY".to_string(), display.to_string()));
+
+        let host_cookie = env::var_os("XAUTHORITY")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".Xauthority")))
+            .filter(|path| path.is_file());
+
+        let cookie = match Self::wildcard_cookie(display)? {
+            Some(cookie) => {
+                self.cookie = Some(cookie.clone());
+                cookie
+            }
+            None => match host_cookie {
+                Some(cookie) => cookie,
+                None => return Ok(()),
+            },
+        };
+
+        self.binds
+            .push(bind_args(&cookie, Path::new(SANDBOX_XAUTHORITY)));
+        let xauthority = SANDBOX_XAUTHORITY.to_string();
+        self.env.push(("XAUTHORITY".to_string(), xauthority));
+        Ok(())
+    }
+
+    /// Writes a copy of the display cookie usable from any host name.
+    ///
+    /// The family of each entry is replaced with `ffff` (FamilyWild), the
+    /// usual way of sharing a cookie with containers.
+    ///
+    /// # Returns
+    /// - `Ok(Some(PathBuf))` with the written cookie file.
+    /// - `Ok(None)` if `xauth` is not installed or knows no cookie for the display.
+    fn wildcard_cookie(display: &str) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        if !in_path("xauth") {
+            return Ok(None);
+        }
+
+        let output = Command::new("xauth")
+            .args(["nlist", display])
+            .stderr(Stdio::null())
+            .output()?;
+        let entries: String = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| line.len() > 4)
+            .map(|line| format!("ffff{}\n", &line[4..]))
+            .collect();
+
+        if !output.status.success() || entries.is_empty() {
+            return Ok(None);
+        }
+
+        let cookie = env::temp_dir().join(format!("alpack-xauth-{}", std::process::id()));
+        fs::write(&cookie, "")?;
+
+        let mut child = Command::new("xauth")
+            .arg("-f")
+            .arg(&cookie)
+            .args(["nmerge", "-"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(entries.as_bytes())?;
+        }
+
+        if child.wait()?.success() {
+            Ok(Some(cookie))
+        } else {
+            let _ = fs::remove_file(&cookie);
+            Ok(None)
+        }
+    }
+
+    /// Binds the Wayland socket at its host path.
+    fn setup_wayland(&mut self, wayland: &str) -> Result<(), Box<dyn Error>> {
+        let runtime_dir = env::var("XDG_RUNTIME_DIR").ok();
+        let socket = match &runtime_dir {
+            _ if wayland.starts_with('/') => PathBuf::from(wayland),
+            Some(dir) => Path::new(dir).join(wayland),
+            None => {
+                return Err("WAYLAND_DISPLAY is relative but XDG_RUNTIME_DIR is not set".into());
+            }
+        };
+
+        if !socket.exists() {
+            eprintln!(
+                "\x1b[1;33mWarning\x1b[0m: Wayland socket {} not found, Wayland is not available in the session.",
+                socket.display()
+            );
+            return Ok(());
+        }
+
+        self.binds.push(bind_args(&socket, &socket));
+        self.env
+            .push(("WAYLAND_DISPLAY".to_string(), wayland.to_string()));
+        if let Some(dir) = runtime_dir {
+            self.env.push(("XDG_RUNTIME_DIR".to_string(), dir));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GuiSession {
+    fn drop(&mut self) {
+        if let Some(cookie) = &self.cookie {
+            let _ = fs::remove_file(cookie);
+        }
+    }
+}
+
+/// Returns the backend arguments giving a session access to the host GPUs.
+///
+/// `/dev/dri` and the `/dev/nvidia*` nodes are bound when present, and the
+/// host driver directories are bound read-only. A driver directory the
+/// rootfs already has is kept, as its entries point at the libraries
+/// installed in the rootfs.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+///
+/// # Returns
+/// - `Ok(Vec<String>)` with the binds.
+/// - `Err` if the host exposes no GPU device.
+pub fn gpu_binds(rootfs: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut devices: Vec<PathBuf> = fs::read_dir("/dev")?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("nvidia"))
+        })
+        .collect();
+    devices.sort();
+
+    let dri = Path::new("/dev/dri");
+    if dri.is_dir() {
+        devices.insert(0, dri.to_path_buf());
+    }
+
+    if devices.is_empty() {
+        return Err("--gpu found no GPU device (/dev/dri or /dev/nvidia*) on the host".into());
+    }
+
+    let drivers = ICD_DIRS
+        .iter()
+        .map(Path::new)
+        .filter(|dir| dir.is_dir())
+        .filter(|dir| {
+            !rootfs
+                .join("rootfs")
+                .join(dir.strip_prefix("/").unwrap_or(dir))
+                .is_dir()
+        })
+        .map(|dir| ro_bind_args(dir, dir));
+
+    Ok(devices
+        .iter()
+        .map(|dev| dev_bind_args(dev))
+        .chain(drivers)
+        .collect())
+}
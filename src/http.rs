@@ -0,0 +1,365 @@
+//! Shared HTTP client for ALPack.
+//!
+//! All network access (mirror scraping, metadata fetches and downloads)
+//! goes through a single lazily configured `ureq::Agent`, so connections
+//! to the same mirror are reused and global network options such as
+//! timeouts, proxy, user agent and extra root certificates are applied
+//! consistently.
+
+use crate::settings::{
+    settings_ca_certs, settings_http_proxy, settings_http_timeout, settings_ip_family,
+    settings_mirror, settings_mirror_auth, MirrorAuth,
+};
+use sandbox_utils::app_arch;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use ureq::config::IpFamily;
+use ureq::tls::{parse_pem, Certificate, PemItem, RootCerts, TlsConfig};
+use ureq::typestate::WithoutBody;
+use ureq::{Agent, Proxy, RequestBuilder};
+
+/// Host CA bundles used as the base trust store when extra certificates
+/// are configured, in the locations used by common distributions.
+const HOST_CA_BUNDLES: [&str; 4] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// Global HTTP agent shared by every network operation.
+static AGENT: OnceLock<Agent> = OnceLock::new();
+
+/// Address family requested on the command line, overriding the settings.
+static IP_FAMILY: OnceLock<String> = OnceLock::new();
+
+/// Root certificates given with the global `--ca-cert` option.
+static CA_CERTS: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+/// Host of the mirror selected for this invocation.
+static MIRROR_HOST: OnceLock<String> = OnceLock::new();
+
+/// Overrides the configured address family for this invocation.
+///
+/// Must be called before the first request, since the agent is built once.
+///
+/// # Parameters
+/// - `family`: One of `"any"`, `"ipv4"` or `"ipv6"`.
+pub fn set_ip_family(family: &str) {
+    let _ = IP_FAMILY.set(family.to_string());
+}
+
+/// Adds root certificates for this invocation, on top of the settings.
+///
+/// Must be called before the first request, since the agent is built once.
+///
+/// # Parameters
+/// - `files`: PEM files with one or more certificates each.
+pub fn set_ca_certs(files: Vec<PathBuf>) {
+    let _ = CA_CERTS.set(files);
+}
+
+/// Returns every additional root certificate file in use.
+///
+/// # Returns
+/// The files from the settings followed by those given on the command line.
+pub fn ca_cert_files() -> Vec<PathBuf> {
+    let mut files = settings_ca_certs();
+    files.extend(CA_CERTS.get().cloned().unwrap_or_default());
+    files
+}
+
+/// Provides global access to the configured HTTP agent.
+///
+/// # Returns
+/// A reference to the shared `Agent`, built on first use from the settings.
+pub fn agent() -> &'static Agent {
+    AGENT.get_or_init(build_agent)
+}
+
+/// Records the mirror in use, which receives the environment credentials.
+///
+/// # Parameters
+/// - `url`: The mirror base URL.
+pub fn set_mirror_host(url: &str) {
+    let _ = MIRROR_HOST.set(url_host(url));
+}
+
+/// Starts a GET request, authenticated if the host has credentials.
+///
+/// # Parameters
+/// - `url`: The URL to request.
+///
+/// # Returns
+/// A request builder from the shared agent.
+pub fn get(url: &str) -> RequestBuilder<WithoutBody> {
+    authorize(agent().get(url), url)
+}
+
+/// Starts a HEAD request, authenticated if the host has credentials.
+///
+/// # Parameters
+/// - `url`: The URL to request.
+///
+/// # Returns
+/// A request builder from the shared agent.
+pub fn head(url: &str) -> RequestBuilder<WithoutBody> {
+    authorize(agent().head(url), url)
+}
+
+/// Adds the `Authorization` header matching the credentials of a URL.
+fn authorize(request: RequestBuilder<WithoutBody>, url: &str) -> RequestBuilder<WithoutBody> {
+    match credentials(url) {
+        Some(auth) if !auth.user.is_empty() => {
+            let pair = format!("{}:{}", auth.user, auth.password);
+            request.header(
+                "Authorization",
+                format!("Basic {}", base64(pair.as_bytes())),
+            )
+        }
+        Some(auth) if !auth.token.is_empty() => {
+            request.header("Authorization", format!("Bearer {}", auth.token))
+        }
+        _ => request,
+    }
+}
+
+/// Adds basic auth credentials to the URLs of an apk repositories line.
+///
+/// apk only supports credentials embedded in the URL, so bearer tokens
+/// cannot be passed on and are reported instead.
+///
+/// # Parameters
+/// - `line`: A line of `/etc/apk/repositories`, possibly tagged (`@tag url`).
+///
+/// # Returns
+/// The line with `user:password@` inserted where credentials apply.
+pub fn with_credentials(line: &str) -> String {
+    line.split(' ')
+        .map(|part| match (part.split_once("://"), credentials(part)) {
+            (Some((scheme, rest)), Some(auth)) if !auth.user.is_empty() => format!(
+                "{scheme}://{}:{}@{rest}",
+                url_encode(&auth.user),
+                url_encode(&auth.password)
+            ),
+            (Some(_), Some(_)) => {
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: apk cannot use bearer tokens, '{part}' is written without credentials."
+                );
+                part.to_string()
+            }
+            _ => part.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the credentials that apply to a URL.
+fn credentials(url: &str) -> Option<MirrorAuth> {
+    if !url.contains("://") {
+        return None;
+    }
+
+    let mirror_host = MIRROR_HOST
+        .get()
+        .cloned()
+        .unwrap_or_else(|| url_host(&settings_mirror(&app_arch())));
+
+    settings_mirror_auth(&url_host(url), &mirror_host)
+}
+
+/// Extracts the `host[:port]` part of a URL, without user information.
+fn url_host(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host)
+        .to_string()
+}
+
+/// Percent-encodes a credential for use in a URL.
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Encodes bytes in standard base64 with padding.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    data.chunks(3)
+        .flat_map(|chunk| {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+
+            (0..4).map(move |i| {
+                if i <= chunk.len() {
+                    ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char
+                } else {
+                    '='
+                }
+            })
+        })
+        .collect()
+}
+
+/// Builds the HTTP agent from the global settings.
+///
+/// The timeout applies to connecting and to waiting for response headers,
+/// leaving long body transfers unbounded. An empty proxy setting keeps the
+/// standard proxy environment variables in effect.
+fn build_agent() -> Agent {
+    let timeout = Some(Duration::from_secs(settings_http_timeout()));
+
+    let mut builder = Agent::config_builder()
+        .user_agent(format!("ALPack/{}", env!("CARGO_PKG_VERSION")))
+        .timeout_connect(timeout)
+        .timeout_recv_response(timeout)
+        .ip_family(ip_family());
+
+    if let Some(roots) = root_certs() {
+        builder = builder.tls_config(TlsConfig::builder().root_certs(roots).build());
+    }
+
+    let proxy = settings_http_proxy();
+    if !proxy.is_empty() {
+        match Proxy::new(&proxy) {
+            Ok(p) => builder = builder.proxy(Some(p)),
+            Err(e) => eprintln!("\x1b[1;33mWarning\x1b[0m: Ignoring invalid proxy '{proxy}': {e}"),
+        }
+    }
+
+    builder.build().into()
+}
+
+/// Resolves the address family from the CLI override or the settings.
+///
+/// Unknown values fall back to allowing both families.
+fn ip_family() -> IpFamily {
+    let family = IP_FAMILY.get().cloned().unwrap_or_else(settings_ip_family);
+
+    match family.as_str() {
+        "ipv4" => IpFamily::Ipv4Only,
+        "ipv6" => IpFamily::Ipv6Only,
+        _ => IpFamily::Any,
+    }
+}
+
+/// Builds the trust store when additional certificates are configured.
+///
+/// Specific roots replace the built-in ones, so the host CA bundle (or the
+/// one in `SSL_CERT_FILE`) is loaded first to keep public mirrors working.
+/// ureq cannot extend its bundled roots, so without a host bundle only the
+/// additional certificates are trusted and a warning says so.
+/// Unreadable files are reported and skipped.
+///
+/// # Returns
+/// - `Some(RootCerts)` with the host and additional certificates.
+/// - `None` if no additional certificate is configured.
+fn root_certs() -> Option<RootCerts> {
+    let files = ca_cert_files();
+    if files.is_empty() {
+        return None;
+    }
+
+    let host_bundle = env::var_os("SSL_CERT_FILE")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(HOST_CA_BUNDLES.iter().map(PathBuf::from))
+        .find(|path| path.is_file());
+    if host_bundle.is_none() {
+        eprintln!(
+            "\x1b[1;33mWarning\x1b[0m: No host CA bundle found, only the additional certificates are trusted.\nSet SSL_CERT_FILE to a CA bundle to reach public servers."
+        );
+    }
+
+    let mut certs = Vec::new();
+    for file in host_bundle.iter().chain(&files) {
+        match read_certs(file) {
+            Ok(found) => certs.extend(found),
+            Err(e) => eprintln!(
+                "\x1b[1;33mWarning\x1b[0m: Ignoring certificates in '{}': {e}",
+                file.display()
+            ),
+        }
+    }
+
+    Some(RootCerts::Specific(Arc::new(certs)))
+}
+
+/// Reads every certificate from a PEM file.
+fn read_certs(file: &Path) -> Result<Vec<Certificate<'static>>, Box<dyn Error>> {
+    let pem = fs::read(file)?;
+    let mut certs = Vec::new();
+
+    for item in parse_pem(&pem) {
+        if let PemItem::Certificate(cert) = item? {
+            certs.push(cert);
+        }
+    }
+
+    if certs.is_empty() {
+        return Err("no PEM certificate found".into());
+    }
+    Ok(certs)
+}
+
+/// Adds the additional certificates to the trust store of a rootfs.
+///
+/// Each file is copied to `/usr/local/share/ca-certificates`, where
+/// `update-ca-certificates` picks it up, and appended to the bundle read
+/// by apk and most TLS libraries, so it is trusted right away.
+///
+/// # Parameters
+/// - `root`: The extracted rootfs on the host.
+///
+/// # Returns
+/// - `Ok(())` once every certificate is installed.
+/// - `Err` if a file cannot be read or written.
+pub fn install_rootfs_ca_certs(root: &Path) -> Result<(), Box<dyn Error>> {
+    let files = ca_cert_files();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let local_dir = root.join("usr/local/share/ca-certificates");
+    let bundle_path = root.join("etc/ssl/certs/ca-certificates.crt");
+    fs::create_dir_all(&local_dir)?;
+    fs::create_dir_all(bundle_path.parent().unwrap_or(root))?;
+
+    let mut bundle = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&bundle_path)?;
+
+    for (i, file) in files.iter().enumerate() {
+        let pem = fs::read_to_string(file)
+            .map_err(|e| format!("Cannot read certificate file '{}': {e}", file.display()))?;
+
+        fs::write(local_dir.join(format!("alpack-{i}.crt")), &pem)?;
+        write!(bundle, "\n{}", pem.trim_end())?;
+        writeln!(bundle)?;
+    }
+
+    println!(
+        "Installed {} additional CA certificate(s) into the rootfs",
+        files.len()
+    );
+    Ok(())
+}
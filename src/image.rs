@@ -0,0 +1,235 @@
+//! Rootfs creation from OCI/Docker images.
+//!
+//! `setup --from-image <REF>` turns a container image into a rootfs. With
+//! `skopeo`, the image is copied into a temporary OCI layout whose layers
+//! are applied in order, honoring whiteout files. Without it, `podman` or
+//! `docker` creates a container and its flattened filesystem is exported.
+
+use crate::ownership::{Owner, OwnershipDb};
+use crate::utils::{decompressor, in_path, new_spinner};
+use serde_json::Value;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use tar::Archive;
+
+/// Transports accepted by skopeo that are passed through unchanged.
+const TRANSPORTS: [&str; 6] = [
+    "docker://",
+    "oci:",
+    "oci-archive:",
+    "docker-archive:",
+    "containers-storage:",
+    "dir:",
+];
+
+/// Prefix of the files hiding lower-layer entries.
+const WHITEOUT: &str = ".wh.";
+
+/// Marker hiding every lower-layer entry of its directory.
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Extracts a container image into a directory.
+///
+/// # Parameters
+/// - `reference`: The image, e.g. `alpine:3.20` or `docker://quay.io/org/img:tag`.
+/// - `dest`: The rootfs root receiving the image filesystem.
+/// - `owners`: Database recording the archived ownership, if tracked.
+///
+/// # Returns
+/// - `Ok(())` once the filesystem is extracted.
+/// - `Err` if no supported tool is installed or pulling the image fails.
+pub fn extract(
+    reference: &str,
+    dest: &Path,
+    owners: Option<&mut OwnershipDb>,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dest)?;
+
+    if in_path("skopeo") {
+        return extract_with_skopeo(reference, dest, owners);
+    }
+
+    match ["podman", "docker"].into_iter().find(|tool| in_path(tool)) {
+        Some(tool) => extract_with_engine(tool, reference, dest, owners),
+        None => Err("Setting up from an image requires skopeo, podman or docker".into()),
+    }
+}
+
+/// Copies the image into an OCI layout with skopeo and applies its layers.
+fn extract_with_skopeo(
+    reference: &str,
+    dest: &Path,
+    mut owners: Option<&mut OwnershipDb>,
+) -> Result<(), Box<dyn Error>> {
+    let source = if TRANSPORTS.iter().any(|t| reference.starts_with(t)) {
+        reference.to_string()
+    } else {
+        format!("docker://{reference}")
+    };
+
+    let layout = env::temp_dir().join(format!("alpack-image-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&layout);
+
+    let spinner = new_spinner(&format!("Pulling {reference}"))?;
+    let status = Command::new("skopeo")
+        .arg("copy")
+        .arg(&source)
+        .arg(format!("oci:{}:image", layout.display()))
+        .stdout(Stdio::null())
+        .status()?;
+
+    if !status.success() {
+        spinner.finish_with_message(format!("Pulling {reference}: failed"));
+        let _ = fs::remove_dir_all(&layout);
+        return Err(format!("skopeo could not copy '{source}'").into());
+    }
+    spinner.finish_with_message(format!("Pulling {reference}: done"));
+
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        let index: Value = serde_json::from_str(&fs::read_to_string(layout.join("index.json"))?)?;
+        let digest = index["manifests"][0]["digest"]
+            .as_str()
+            .ok_or("The image index has no manifest")?;
+        let manifest: Value = serde_json::from_str(&fs::read_to_string(blob(&layout, digest)?)?)?;
+        let layers = manifest["layers"]
+            .as_array()
+            .ok_or("The image manifest has no layers")?;
+
+        let spinner = new_spinner("Applying image layers")?;
+        for layer in layers {
+            let digest = layer["digest"].as_str().ok_or("Layer without digest")?;
+            let file = File::open(blob(&layout, digest)?)?;
+
+            // Layers are gzip, zstd or uncompressed tarballs, recognized by content.
+            unpack_layer(decompressor(file)?, dest, owners.as_deref_mut())?;
+        }
+        spinner.finish_with_message("Applying image layers: done");
+        Ok(())
+    })();
+
+    let _ = fs::remove_dir_all(&layout);
+    result
+}
+
+/// Returns the path of a blob in an OCI layout.
+fn blob(layout: &Path, digest: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let (algo, hex) = digest
+        .split_once(':')
+        .filter(|(_, hex)| !hex.contains('/'))
+        .ok_or(format!("Invalid digest '{digest}'"))?;
+    Ok(layout.join("blobs").join(algo).join(hex))
+}
+
+/// Exports the filesystem of a container created from the image.
+fn extract_with_engine(
+    tool: &str,
+    reference: &str,
+    dest: &Path,
+    owners: Option<&mut OwnershipDb>,
+) -> Result<(), Box<dyn Error>> {
+    let spinner = new_spinner(&format!("Pulling {reference} with {tool}"))?;
+    let output = Command::new(tool)
+        .args(["create", reference])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    if !output.status.success() {
+        spinner.finish_with_message(format!("Pulling {reference} with {tool}: failed"));
+        return Err(format!("{tool} could not create a container from '{reference}'").into());
+    }
+    spinner.finish_with_message(format!("Pulling {reference} with {tool}: done"));
+
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        let spinner = new_spinner("Exporting the image filesystem")?;
+        let mut child = Command::new(tool)
+            .args(["export", &id])
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().ok_or("No output from the export")?;
+        unpack_layer(stdout, dest, owners)?;
+
+        if !child.wait()?.success() {
+            return Err(format!("{tool} export failed").into());
+        }
+        spinner.finish_with_message("Exporting the image filesystem: done");
+        Ok(())
+    })();
+
+    let _ = Command::new(tool)
+        .args(["rm", &id])
+        .stdout(Stdio::null())
+        .status();
+    result
+}
+
+/// Applies one layer tarball over the directory.
+///
+/// Whiteout files remove the entry they name and opaque markers empty their
+/// directory, as lower layers were already applied.
+fn unpack_layer<R: Read>(
+    reader: R,
+    dest: &Path,
+    mut owners: Option<&mut OwnershipDb>,
+) -> Result<(), Box<dyn Error>> {
+    let mut archive = Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_overwrite(true);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(format!(
+                "Refusing layer entry outside the rootfs: {}",
+                path.display()
+            )
+            .into());
+        }
+
+        let rel: PathBuf = path
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let name = rel.file_name().map(|n| n.to_string_lossy().into_owned());
+        let parent = dest.join(rel.parent().unwrap_or(Path::new("")));
+
+        match name.as_deref() {
+            Some(OPAQUE_WHITEOUT) => {
+                for child in fs::read_dir(&parent).into_iter().flatten().flatten() {
+                    remove_entry(&child.path())?;
+                }
+            }
+            Some(n) if n.starts_with(WHITEOUT) => {
+                remove_entry(&parent.join(&n[WHITEOUT.len()..]))?;
+            }
+            _ => {
+                let header = entry.header();
+                let owner = Owner {
+                    uid: header.uid()?,
+                    gid: header.gid()?,
+                    mode: header.mode()?,
+                };
+                entry.unpack_in(dest)?;
+                if let Some(db) = owners.as_deref_mut() {
+                    db.record(&rel, owner);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes a file, link or directory tree if it exists.
+fn remove_entry(path: &Path) -> Result<(), Box<dyn Error>> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    Ok(())
+}
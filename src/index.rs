@@ -0,0 +1,156 @@
+//! Host-side access to the cached APKINDEX files of a rootfs.
+//!
+//! After `apk update`, every configured repository leaves a signed
+//! `APKINDEX.<hash>.tar.gz` in `/var/cache/apk`. This module reads those
+//! archives directly from the host, so package listings can be searched
+//! without starting a sandbox.
+
+use crate::settings::settings_share_apk_cache;
+use crate::utils::apk_cache_dir;
+use flate2::read::MultiGzDecoder;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tar::Archive;
+
+/// A package record from an APKINDEX.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexEntry {
+    /// The package name (`P:`).
+    pub name: String,
+    /// The package version (`V:`).
+    pub version: String,
+    /// The one-line description (`T:`).
+    pub description: String,
+}
+
+/// Loads all package records from the cached indexes of a rootfs.
+///
+/// # Parameters
+/// - `root`: The root directory of the Alpine system (the extracted rootfs).
+///
+/// # Returns
+/// - `Ok(Vec<IndexEntry>)` sorted by name, without duplicates.
+/// - `Err` if no index is cached or an archive cannot be read.
+pub fn load_index(root: &Path) -> Result<Vec<IndexEntry>, Box<dyn Error>> {
+    let cache = root.join("var/cache/apk");
+    let mut entries = BTreeSet::new();
+
+    let mut dirs = vec![cache.clone()];
+    if settings_share_apk_cache() {
+        dirs.push(apk_cache_dir(root.parent().unwrap_or(root)));
+    }
+
+    for file in dirs.iter().flat_map(fs::read_dir).flatten().flatten() {
+        let name = file.file_name();
+        let name = name.to_string_lossy();
+
+        if name.starts_with("APKINDEX.") && name.ends_with(".tar.gz") {
+            entries.extend(parse_index(&read_index_archive(&file.path())?));
+        }
+    }
+
+    if entries.is_empty() {
+        return Err(format!(
+            "No package index found in {}\nPlease run 'apk update' in the rootfs first.",
+            cache.display()
+        )
+        .into());
+    }
+
+    Ok(entries.into_iter().collect())
+}
+
+/// Loads the packages installed in a rootfs from the apk database.
+///
+/// # Parameters
+/// - `root`: The root directory of the Alpine system (the extracted rootfs).
+///
+/// # Returns
+/// - `Ok(Vec<IndexEntry>)` sorted by name.
+/// - `Err` if the installed database cannot be read.
+pub fn load_installed(root: &Path) -> Result<Vec<IndexEntry>, Box<dyn Error>> {
+    let content = fs::read_to_string(root.join("lib/apk/db/installed"))?;
+    let mut entries = parse_index(&content);
+    entries.sort();
+    Ok(entries)
+}
+
+/// Lists the files installed by a package, from the apk database.
+///
+/// # Parameters
+/// - `root`: The root directory of the Alpine system (the extracted rootfs).
+/// - `package`: The package name.
+///
+/// # Returns
+/// - `Ok(Vec<String>)` with the paths relative to `root`.
+/// - `Err` if the database cannot be read or the package is not installed.
+pub fn installed_files(root: &Path, package: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let content = fs::read_to_string(root.join("lib/apk/db/installed"))?;
+    let record = content
+        .split("\n\n")
+        .find(|record| {
+            record
+                .lines()
+                .any(|line| line.strip_prefix("P:") == Some(package))
+        })
+        .ok_or(format!(
+            "Package '{package}' is not installed in the rootfs"
+        ))?;
+
+    let mut dir = "";
+    let mut files = Vec::new();
+    for line in record.lines() {
+        match line.split_once(':') {
+            Some(("F", v)) => dir = v,
+            Some(("R", v)) if dir.is_empty() => files.push(v.to_string()),
+            Some(("R", v)) => files.push(format!("{dir}/{v}")),
+            _ => {}
+        }
+    }
+    Ok(files)
+}
+
+/// Extracts the `APKINDEX` text file from a signed index archive.
+///
+/// Index archives are a signature gzip stream followed by the data stream,
+/// so a multi-member decoder is required to reach the index itself.
+fn read_index_archive(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut archive = Archive::new(MultiGzDecoder::new(File::open(path)?));
+    archive.set_ignore_zeros(true);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() == "APKINDEX" {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            return Ok(content);
+        }
+    }
+    Ok(String::new())
+}
+
+/// Parses the blank-line separated records of an APKINDEX.
+fn parse_index(content: &str) -> Vec<IndexEntry> {
+    content
+        .split("\n\n")
+        .filter_map(|record| {
+            let field = |key: &str| {
+                record
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key))
+                    .map(str::to_string)
+            };
+
+            Some(IndexEntry {
+                name: field("P:")?,
+                version: field("V:").unwrap_or_default(),
+                description: field("T:").unwrap_or_default(),
+            })
+        })
+        .collect()
+}
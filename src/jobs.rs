@@ -0,0 +1,356 @@
+//! Background sessions.
+//!
+//! `run --detach` starts the session as a new ALPack process in its own
+//! session, with its output appended to a log file, and records it in the
+//! `jobs` directory of the environment. `ps` lists these jobs and `kill`
+//! stops one together with every process it started.
+
+use crate::envs;
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{check_rootfs_exists, is_json};
+use sandbox_utils::{app_name, invalid_arg, missing_arg, parse_value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Directory of the environment holding the job records and logs.
+const JOBS_DIR: &str = "jobs";
+
+/// Seconds `kill` waits for a job to exit before reporting it as still running.
+const KILL_TIMEOUT: u64 = 10;
+
+/// A session started with `run --detach`.
+#[derive(Serialize, Deserialize, Debug)]
+struct Job {
+    /// Number identifying the job in the environment.
+    id: u32,
+    /// PID of the ALPack process, leader of the job's process group.
+    pid: i32,
+    /// Start time of the process in clock ticks since boot, to detect PID reuse.
+    start_ticks: u64,
+    /// Start time as seconds since the Unix epoch.
+    started: u64,
+    /// The command of the session, empty for a shell.
+    command: String,
+    /// File receiving the output of the session.
+    log: PathBuf,
+}
+
+impl Job {
+    /// Returns whether the process of the job is still running.
+    fn is_running(&self) -> bool {
+        start_ticks(self.pid) == Some(self.start_ticks)
+    }
+}
+
+/// Starts a `run` session in the background.
+///
+/// The current command line is executed again without the detach flag,
+/// detached from the terminal, and the job is recorded in the environment.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+/// - `flag`: The flag that requested the detach (`-d` or `--detach`).
+/// - `command`: The command of the session, empty for a shell.
+///
+/// # Returns
+/// - `Ok(())` once the job is started.
+/// - `Err` if the process cannot be started or the job cannot be recorded.
+pub fn detach(rootfs: &Path, flag: &str, command: &[String]) -> Result<(), Box<dyn Error>> {
+    check_rootfs_exists(rootfs.to_path_buf())?;
+
+    let dir = rootfs.join(JOBS_DIR);
+    fs::create_dir_all(&dir)?;
+    let id = load(rootfs).iter().map(|job| job.id).max().unwrap_or(0) + 1;
+    let log = dir.join(format!("{id}.log"));
+    let output = OpenOptions::new().create(true).append(true).open(&log)?;
+
+    let mut argv: Vec<_> = env::args_os().skip(1).collect();
+    if let Some(pos) = argv.iter().position(|arg| arg == flag) {
+        argv.remove(pos);
+    }
+
+    let mut process = Command::new(env::current_exe()?);
+    process
+        .args(argv)
+        .stdin(Stdio::null())
+        .stdout(output.try_clone()?)
+        .stderr(output);
+    // SAFETY: setsid is async-signal-safe and only affects the child.
+    unsafe {
+        process.pre_exec(|| {
+            if libc::setsid() < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = process.spawn()?;
+
+    let pid = child.id() as i32;
+    let job = Job {
+        id,
+        pid,
+        start_ticks: start_ticks(pid).unwrap_or_default(),
+        started: now(),
+        command: command.join(" "),
+        log,
+    };
+    fs::write(
+        dir.join(format!("{id}.json")),
+        serde_json::to_string_pretty(&job)?,
+    )?;
+
+    println!(
+        "Started job {id} (PID {pid}), output in {}",
+        job.log.display()
+    );
+    Ok(())
+}
+
+/// Reads the jobs recorded in an environment, ordered by number.
+fn load(rootfs: &Path) -> Vec<Job> {
+    let mut jobs: Vec<Job> = fs::read_dir(rootfs.join(JOBS_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| serde_json::from_str(&fs::read_to_string(path).ok()?).ok())
+        .collect();
+    jobs.sort_by_key(|job| job.id);
+    jobs
+}
+
+/// Removes the record of a job, keeping its log.
+fn forget(rootfs: &Path, job: &Job) -> Result<(), Box<dyn Error>> {
+    fs::remove_file(rootfs.join(JOBS_DIR).join(format!("{}.json", job.id)))?;
+    Ok(())
+}
+
+/// Returns the start time of a process in clock ticks since boot.
+///
+/// # Returns
+/// - `Some(u64)` with field 22 of `/proc/<pid>/stat`.
+/// - `None` if the process does not exist.
+fn start_ticks(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name may contain spaces, so fields are counted after it.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Returns the current time as seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Formats an elapsed time with its largest unit, e.g. `45s` or `3h`.
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// Parses the `--name`/`--rootfs` options shared by `ps` and `kill`.
+///
+/// # Returns
+/// - `Ok((PathBuf, Vec<&str>))` with the environment and the other arguments.
+/// - `Err` if an option value is missing or the environment is unknown.
+fn parse_rootfs<'a>(
+    cmd: &str,
+    mut args: VecDeque<&'a str>,
+) -> Result<(PathBuf, Vec<&'a str>), Box<dyn Error>> {
+    let mut rootfs = settings_rootfs_dir();
+    let mut rest = Vec::new();
+
+    while let Some(arg) = args.pop_front() {
+        match arg {
+            a if a.starts_with("--name=") => {
+                rootfs = envs::lookup(&parse_value!(cmd, "name", arg)?)?;
+            }
+            "--name" => {
+                rootfs = envs::lookup(&parse_value!(cmd, "name", arg, args.pop_front())?)?;
+            }
+            a if a.starts_with("--rootfs=") => {
+                rootfs = parse_value!(cmd, "directory", arg)?.into();
+            }
+            "-R" | "--rootfs" => {
+                rootfs = parse_value!(cmd, "directory", arg, args.pop_front())?.into();
+            }
+            _ => rest.push(arg),
+        }
+    }
+    Ok((rootfs, rest))
+}
+
+/// Controller for the `ps` subcommand.
+pub struct Ps {
+    /// Arguments captured after the `ps` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Ps {
+    /// Creates a new `Ps` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Ps { remaining_args }
+    }
+
+    /// Prints the background jobs of the environment.
+    ///
+    /// Like shell job control, a job that has exited is shown once and
+    /// then forgotten; its log file is kept.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing.
+    /// - `Err` if an argument is invalid.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let args = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let (rootfs, rest) = parse_rootfs("ps", args)?;
+        if let Some(arg) = rest.first() {
+            return invalid_arg!("ps", arg);
+        }
+
+        let jobs = load(&rootfs);
+        let now = now();
+
+        if is_json() {
+            let list: Vec<_> = jobs
+                .iter()
+                .map(|job| {
+                    json!({
+                        "id": job.id,
+                        "pid": job.pid,
+                        "running": job.is_running(),
+                        "started": job.started,
+                        "command": job.command,
+                        "log": job.log,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&list)?);
+        } else if jobs.is_empty() {
+            println!(
+                "No background jobs. Start one with '{} run --detach <CMD>'.",
+                app_name()
+            );
+        } else {
+            println!(
+                "{:<4}  {:<8}  {:<7}  {:<5}  COMMAND",
+                "ID", "PID", "STATUS", "AGE"
+            );
+            for job in &jobs {
+                let status = if job.is_running() {
+                    "running"
+                } else {
+                    "exited"
+                };
+                let command = if job.command.is_empty() {
+                    "(shell)"
+                } else {
+                    &job.command
+                };
+                let age = format_age(now.saturating_sub(job.started));
+                println!(
+                    "{:<4}  {:<8}  {status:<7}  {age:<5}  {command}",
+                    job.id, job.pid
+                );
+            }
+        }
+
+        for job in jobs.iter().filter(|job| !job.is_running()) {
+            forget(&rootfs, job)?;
+        }
+        Ok(())
+    }
+}
+
+/// Controller for the `kill` subcommand.
+pub struct Kill {
+    /// Arguments captured after the `kill` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Kill {
+    /// Creates a new `Kill` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Kill { remaining_args }
+    }
+
+    /// Stops a background job and every process it started.
+    ///
+    /// `SIGTERM` is sent to the process group of the job, or `SIGKILL`
+    /// with `--force`, and the job is forgotten once it has exited.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the job has exited.
+    /// - `Err` if the job is unknown or still running after the signal.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let args = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let (rootfs, rest) = parse_rootfs("kill", args)?;
+
+        let mut force = false;
+        let mut id = None;
+        for arg in rest {
+            match arg {
+                "-f" | "--force" => force = true,
+                a if id.is_none() && !a.starts_with('-') => {
+                    id = Some(
+                        a.parse::<u32>()
+                            .map_err(|_| format!("Invalid job ID: {a}"))?,
+                    );
+                }
+                _ => return invalid_arg!("kill", arg),
+            }
+        }
+        let Some(id) = id else {
+            return missing_arg!("kill");
+        };
+
+        let job = load(&rootfs)
+            .into_iter()
+            .find(|job| job.id == id)
+            .ok_or_else(|| format!("No job {id}. See '{} ps'.", app_name()))?;
+
+        if job.is_running() {
+            let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+            // SAFETY: kill has no memory-safety requirements; the negative
+            // PID targets the process group the job leads.
+            if unsafe { libc::kill(-job.pid, signal) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            let mut waited = 0;
+            while job.is_running() && waited < KILL_TIMEOUT * 10 {
+                thread::sleep(Duration::from_millis(100));
+                waited += 1;
+            }
+            if job.is_running() {
+                return Err(format!(
+                    "Job {id} is still running, stop it with '{} kill --force {id}'.",
+                    app_name()
+                )
+                .into());
+            }
+        }
+
+        forget(&rootfs, &job)?;
+        println!("Stopped job {id}");
+        Ok(())
+    }
+}
@@ -4,30 +4,229 @@
 //! environments, allowing for automated setup, package management, and
 //! repository indexing through a modular architecture.
 
+mod adelie;
 mod apk;
-mod aports;
-mod aptree;
 mod builder;
+mod cache;
 mod config;
+mod daemon;
+mod diagnose;
+mod diff;
+mod envs;
+mod export;
+mod fetch;
+mod gui;
+mod http;
+mod image;
+mod index;
+mod jobs;
+mod manifest;
 mod mirror;
+mod ownership;
+mod publish;
+mod qemu;
 mod run;
+mod search;
+mod seccomp;
+mod service;
 mod settings;
 mod setup;
+mod shellhook;
+mod slirp;
+mod snapshot;
+mod stats;
+mod tree;
+mod upgrade;
+mod userns;
 mod utils;
+mod verify;
+mod which;
+mod world;
+mod wsl;
 
 use crate::apk::Apk;
-use crate::aports::Aports;
-use crate::aptree::Aptree;
 use crate::builder::Builder;
+use crate::cache::CacheCommand;
 use crate::config::Config;
+use crate::daemon::Daemon;
+use crate::diagnose::Diagnose;
+use crate::diff::Diff;
+use crate::envs::List;
+use crate::export::{ExportApp, ExportBin};
+use crate::fetch::Fetch;
+use crate::http::set_ca_certs;
+use crate::jobs::{Kill, Ps};
+use crate::manifest::Info;
+use crate::mirror::MirrorCommand;
+use crate::publish::Publish;
 use crate::run::Run;
-use crate::settings::{settings_cmd, Settings};
+use crate::search::Search;
+use crate::service::Service;
+use crate::settings::{
+    is_system_mode, prepare_system_dirs, set_cmd_override, set_config_file, set_profile,
+    set_rootfs_override, set_system_mode, settings_cmd, settings_subid_mapping, Settings,
+};
 use crate::setup::Setup;
-use pico_args::Arguments;
-use sandbox_utils::{app_name, invalid_arg, sandbox_init, set_sandbox_tool};
+use crate::shellhook::ShellHook;
+use crate::snapshot::Restore;
+use crate::stats::Stats;
+use crate::tree::Tree;
+use crate::upgrade::UpgradeRootfs;
+use crate::utils::{
+    set_json, set_offline, set_quiet, ExitStatusError, TimeoutError, TIMEOUT_EXIT_CODE,
+};
+use crate::which::Which;
+use crate::world::World;
+use sandbox_utils::{app_name, invalid_arg, parse_value, sandbox_init, set_sandbox_tool};
+use std::collections::VecDeque;
 use std::env;
 use std::error::Error;
 use std::path::PathBuf;
+use std::process;
+
+/// Handler of a subcommand, called with the name it was invoked as and its arguments.
+type Handler = fn(&str, Vec<String>) -> Result<(), Box<dyn Error>>;
+
+/// Subcommands accepted by the dispatcher, with their aliases and handlers.
+///
+/// The same table serves the suggestions for mistyped commands, so every
+/// dispatched name is also suggested.
+const COMMANDS: &[(&[&str], Handler)] = &[
+    (
+        &["add", "del", "install", "remove", "update", "fix", "-u"],
+        |cmd, args| Apk::new(Some(cmd.to_string()), args, None).run(),
+    ),
+    (&["apk"], apk_command),
+    (&["-s", "search"], |_, args| Search::new(args).run()),
+    (&["aports", "aptree", "pmaports"], |cmd, args| {
+        Tree::new(cmd.to_string(), args).run()
+    }),
+    (&["tree"], |_, args| Tree::dispatch(args)),
+    (&["search-tree"], |_, args| Tree::search_all(args)),
+    (&["builder"], |_, args| Builder::new(args).run()),
+    (&["cache"], |_, args| CacheCommand::new(args).run()),
+    (&["config"], |_, args| Config::new(args).run()),
+    (&["diff"], |_, args| Diff::new(args).run()),
+    (&["env"], |_, args| Diagnose::new(args).run()),
+    (&["exec"], |_, args| Run::exec(args).run()),
+    (&["export-app"], |_, args| ExportApp::new(args).run()),
+    (&["export-bin"], |_, args| ExportBin::new(args).run()),
+    (&["info"], |_, args| Info::new(args).run()),
+    (&["list"], |_, args| List::new(args).run()),
+    (&["mirror"], |_, args| MirrorCommand::new(args).run()),
+    (&["fetch"], |_, args| Fetch::new(args).run()),
+    (&["daemon"], |_, args| Daemon::new(args).run()),
+    (&["kill"], |_, args| Kill::new(args).run()),
+    (&["ps"], |_, args| Ps::new(args).run()),
+    (&["publish"], |_, args| Publish::new(args).run()),
+    (&["restore"], |_, args| Restore::new(args).run()),
+    (&["run"], |_, args| Run::new(args).run()), // Todo: -w caminho, --pwd=caminho, --cwd=caminho; --kill-on-exit: limpar processos "órfãos".
+    (&["service"], |_, args| Service::new(args).run()),
+    (&["setup"], |_, args| Setup::new(args).run()),
+    (&["shell-hook"], |_, args| ShellHook::new(args).run()),
+    (&["stats"], |_, args| Stats::new(args).run()),
+    (&["upgrade-rootfs"], |_, args| {
+        UpgradeRootfs::new(args).run()
+    }),
+    (&["which"], |_, args| Which::new(args).run()),
+    (&["world"], |_, args| World::new(args).run()),
+];
+
+/// Subcommands starting a bwrap sandbox, re-executed in the mapped user
+/// namespace when subordinate IDs are enabled. Running without a command
+/// starts a shell and is included as well.
+const SANDBOX_COMMANDS: [&str; 21] = [
+    "add",
+    "apk",
+    "aports",
+    "aptree",
+    "builder",
+    "del",
+    "exec",
+    "fetch",
+    "fix",
+    "install",
+    "pmaports",
+    "publish",
+    "remove",
+    "run",
+    "search",
+    "tree",
+    "update",
+    "upgrade-rootfs",
+    "world",
+    "-s",
+    "-u",
+];
+
+/// Runs the `apk` subcommand.
+///
+/// The rootfs options are taken wherever they appear; the first other
+/// argument is the apk command and the rest are passed to it.
+///
+/// # Parameters
+/// - `remaining_args`: Arguments captured after the `apk` keyword.
+fn apk_command(_: &str, remaining_args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut args = remaining_args.into_iter();
+    let (mut rootfs, mut subcommand) = (None, None);
+    let mut subargs: Vec<String> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-R" | "--rootfs" => rootfs = args.next().map(PathBuf::from),
+            a if a.starts_with("--rootfs=") => {
+                rootfs = a.split_once('=').map(|(_, v)| PathBuf::from(v));
+            }
+            "--name" => {
+                let value = args.next();
+                let name = parse_value!("apk", "name", &arg, value.as_deref())?;
+                rootfs = Some(envs::lookup(&name)?);
+            }
+            a if a.starts_with("--name=") => {
+                rootfs = Some(envs::lookup(&parse_value!("apk", "name", a)?)?);
+            }
+            _ if subcommand.is_none() => subcommand = Some(arg),
+            _ => subargs.push(arg),
+        }
+    }
+
+    Apk::new(subcommand, subargs, rootfs).run()
+}
+
+/// Returns the known subcommands closest to a mistyped one.
+///
+/// Candidates within an edit distance of two, or starting with the given
+/// text, are returned from the closest to the farthest (at most three).
+///
+/// # Parameters
+/// - `input`: The unknown subcommand.
+fn similar_commands(input: &str) -> Vec<&'static str> {
+    let mut matches: Vec<(usize, &str)> = COMMANDS
+        .iter()
+        .flat_map(|(names, _)| names.iter())
+        .map(|cmd| (edit_distance(input, cmd), *cmd))
+        .filter(|(dist, cmd)| *dist <= 2 || (input.len() >= 3 && cmd.starts_with(input)))
+        .collect();
+
+    matches.sort();
+    matches.into_iter().take(3).map(|(_, cmd)| cmd).collect()
+}
+
+/// Computes the Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
 
 /// Prints the help message and usage instructions to the console.
 ///
@@ -42,17 +241,41 @@ to create and manage Alpine Linux rootfs containers
 easily using proot or bubblewrap(bwrap).
 
 Usage:
-    {cmd} <parameters> [options] [--] [ARGS...]
+    {cmd} [global options] <parameters> [options] [--] [ARGS...]
 
 Available parameters:
         setup                   Initialize or configure the rootfs environment
         run                     Execute command inside the rootfs
+        exec -- <ARGV...>       Execute an exact argv inside the rootfs, without shell interpolation
+        ps                      List the sessions started with 'run --detach'
+        kill <ID>               Stop a background session and its processes
         config                  Display or modify global configuration
+        env                     Print the resolved runtime context for bug reports
+        info                    Show how the rootfs was created (source, release, packages...)
+        list                    List the named environments created with 'setup --name'
+        upgrade-rootfs          Upgrade the rootfs to another Alpine release in place
+        restore --baseline      Reset the rootfs to the baseline archived by 'setup --snapshot-baseline'
+        stats                   Summarize packages, disk usage and builds of the rootfs
+        diff <A> <B>            Show package and file changes between two rootfs trees
         aports                  Manage local aports repository
         aptree                  Manage local Adélie Package Tree repository
+        pmaports                Manage local postmarketOS pmaports repository
+        tree <NAME>             Manage a package tree declared in the configuration
+        search-tree <PKG...>    Search all synchronized package trees at once
         builder                 Build utility for packages and images
+        cache prune             Evict cached downloads exceeding the configured limits
+        mirror test             Probe the known Alpine mirrors and rank them by speed
+        publish                 Upload built packages and a signed index to a repository
+        service install <NAME>  Write a systemd user unit running a rootfs command
+        daemon                  Serve JSON-RPC requests for graphical frontends
+        shell-hook <SHELL>      Print a bash/zsh/fish hook activating project directories
+        export-app <PKG>        Add the .desktop launchers of a rootfs package to the host menu
+        export-bin <CMD>        Write a ~/.local/bin wrapper running a rootfs command from the host
+        which <CMD...>          Locate commands in the rootfs and show their owning package
+        world <ACTION>          Edit /etc/apk/world (add, remove, list) and apply it (commit)
+        fetch <PKGS...>         Download packages and dependencies without installing them
         apk                     Run the Alpine package manager (apk)
-        add | install <ARGS>    Install packages into the rootfs
+        add | install <ARGS>    Install packages (or local .apk files) into the rootfs
         del | remove <ARGS>     Remove packages from the rootfs
     -s, search <ARGS>           Search for available packages
     -u, update                  Update the package index and upgrade installed packages
@@ -60,17 +283,105 @@ Available parameters:
 
 Options for 'setup':
         --no-cache              Disable caching during the operation
+        --stream                Extract while downloading, without caching the tarball
+        --dry-run               Print the resolved mirror, version, directories and packages, then exit
+        --snapshot-baseline     Archive the fresh rootfs so 'restore --baseline' can reset it later
+        --ip4 | --ip6           Connect to the mirror over IPv4 or IPv6 only
     -r, --reinstall             Reinstall packages without forcing
         --edge                  Use the edge (testing) repository
+        --release <BRANCH>      Use a release branch: latest-stable (default), edge, v3.20...
+        --distro <NAME>         Distribution to set up: alpine (default) or adelie
+        --from-image <REF>      Create the rootfs from an OCI/Docker image (skopeo, podman or docker)
+        --from-tarball <FILE|URL>
+                                Create the rootfs from a .tar.gz, .tar.xz or .tar.zst archive
         --minimal               Install only the minimal set of packages
+        --packages-file <FILE>  Install the packages listed in FILE instead of the default set
+        --with-docs | --no-docs Install documentation or not (overrides the configuration)
         --mirror <URL>          Use the specified mirror instead of the default one
         --mirror=<URL>          Use the specified mirror instead of the default one (inline)
+        --fastest-mirror        Probe the known mirrors and use the fastest one
+        --repositories <FILE>   Write this repositories file instead of the mirror-generated one
+        --repo <URL>            Add a repository next to the generated or given ones (can be repeated)
+        --on-failure <ACTION>   If package installation fails: abort (default) or shell
         --cache <DIR>           Specify cache directory
         --cache=<DIR>           Specify cache directory (inline)
+        --arch <ARCH>           Set up a rootfs for another architecture, run through QEMU
+        --name <ENV>            Create a named environment (see 'list'), in -R or the default place
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'upgrade-rootfs':
+        --to <RELEASE>          Target release: latest-stable (default), edge, v3.21...
+        --name <ENV>            Upgrade the named environment
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'restore':
+        --baseline              Restore the rootfs archived right after setup
+        --name <ENV>            Restore the named environment
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'ps' and 'kill':
+    -f, --force                 Kill the session with SIGKILL instead of SIGTERM ('kill')
+        --name <ENV>            Use the named environment
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'export-bin' and 'export-app':
+    -a, --alias <NAME>          Name of the host command (default: the command name, 'export-bin')
+        --name <ENV>            Export from the named environment
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'publish':
+    -d, --dest <URL>            Destination (rsync://host/path, s3://bucket/prefix, sftp://host/path)
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
 
+Options for 'service install <NAME> -- <CMD>':
+        --restart <POLICY>      systemd restart policy (default on-failure)
+    -e, --env <KEY=VALUE>       Set an environment variable in the unit (can be repeated)
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'daemon':
+    -S, --socket <PATH>         Unix socket to listen on (one JSON-RPC 2.0 request per line)
+        --socket=<PATH>         Unix socket to listen on (inline)
+                                Methods: search, list, setup, run, apk (output sent as 'progress')
+
+Options for 'search':
+    -i, --interactive           Pick results from a numbered list and install them
+        --regex <PATTERN>       Match names and descriptions with a regular expression
+        --glob <PATTERN>        Match names and descriptions with a shell-style glob
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'cache prune':
+        --max-size <SIZE>       Override the maximum cache size (e.g., 2G)
+        --ttl-days <DAYS>       Override the number of days unused files are kept
+        --cache <DIR>           Specify cache directory
+
+Options for 'mirror test':
+        --release <BRANCH>      Probe this release instead of the configured one
+        --arch <ARCH>           Probe this architecture instead of the host one
+
 Options for 'apk':
+        --simulate              Preview add/del/update without changing the rootfs
+        --no-rollback           Do not snapshot the rootfs to restore it if update fails
+        --db-lock               With 'fix', clear a stale apk database lock before repairing
+        --name <ENV>            Use the named environment instead of a rootfs directory
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'shell-hook':
+        bash | zsh | fish       Print the hook, e.g. eval \"$({cmd} shell-hook bash)\" in ~/.bashrc
+        allow [DIR]             Run missing commands of this project in its rootfs
+        deny [DIR]              Only print a hint when entering this project
+
+Options for 'search-tree':
+    -S, --strict                Match exact package names only
+        --no-pager              Print the results without paging them
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
 
@@ -78,6 +389,7 @@ Options for 'aports':
     -u, --update                Update the local aports repository to the latest version
     -s, --search=<PKG>          Search for a package in the Alpine aports
     -S, --strict-search=<PKG>   Search for a package with an exact name match
+        --no-pager              Print search results without paging them
     -g, --get=<PKG>             Download the APKBUILD in the Alpine aports
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
@@ -86,43 +398,148 @@ Options for 'aptree':
     -u, --update                Update the local aptree repository to the latest version
     -s, --search=<PKG>          Search for a package in the Adélie aptree
     -S, --strict-search=<PKG>   Search for a package with an exact name match
+        --no-pager              Print search results without paging them
     -g, --get=<PKG>             Download the APKBUILD from the Adélie aptree
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
 
+Options for 'pmaports':
+    -u, --update                Update the local pmaports repository to the latest version
+    -s, --search=<PKG>          Search for a package in the postmarketOS pmaports
+    -S, --strict-search=<PKG>   Search for a package with an exact name match
+        --no-pager              Print search results without paging them
+    -g, --get=<PKG>             Download the APKBUILD from the postmarketOS pmaports
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'tree':
+    -l, --list                  List built-in and configured package trees
+        <NAME> [options]        Same options as 'aports' for the named tree
+
 Options for 'builder':
     -a, --apkbuild <APKBUILD>   Use a specific APKBUILD file as input
         --apkbuild=<APKBUILD>   Use a specific APKBUILD file as input (inline)
+    -t, --tree <NAME> <PKG...>  Build packages checked out from a synchronized tree (e.g. pmaports)
         --force-key             Force regeneration of RSA signing keys
-    -e, --ephemeral             Use a temporary overlay to discard changes after execution
+    -e, --ephemeral             Build in a discarded overlay, keeping the rootfs pristine
+        --no-ephemeral          Persist build changes even if enabled in the configuration
+        --install               Install the built package and subpackages (default)
+        --no-install            Only build, leaving the packages in the repository
+        --check                 Run the package test suite with its checkdepends (default)
+        --no-check              Skip the check() phase of the APKBUILD
+        --tmpfs-build           Keep the build work directories in memory
+        --size <SIZE>           Size limit of each in-memory work directory (e.g. 4G)
+        --log-file <FILE>       Also append the build output to a file
+        --on-failure <ACTION>   On build failure: abort (default) or open a debug shell (shell)
+        --name <ENV>            Build in the named environment
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'fetch':
+    -o, --output <DIR>          Save the packages here instead of the shared apk cache
+        --output=<DIR>          Save the packages here (inline)
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'info':
+        --name <ENV>            Show the named environment
+    -R, --rootfs <DIR>          Specify rootfs directory
+        --rootfs=<DIR>          Specify rootfs directory (inline)
+
+Options for 'stats':
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
 
-Options for 'run':
+Options for 'run' and 'exec':
     -0, --root                  Run with root privileges inside rootfs
     -i, --ignore-extra-binds    Ignore additional bind mounts
     -s, --secure-rootfs         Minimal mounting with maximum isolation and restricted integration
+    -l, --login                 Start a login shell (sources /etc/profile and ~/.profile)
+        --hostname <NAME>       Host name of the session (own UTS namespace with bwrap)
+        --shell <PATH>          Shell started by the session and running --command (default: /bin/sh)
     -e, --ephemeral             Use a temporary overlay to discard changes after execution
+        --no-home               Hide the host home directory behind an empty tmpfs (bwrap)
+        --home <DIR>            Use DIR as home directory instead of the host one (created if missing)
+        --bind-cwd              Bind the current directory at the same path and start the session in it
+        --no-bind-cwd           Do not bind the current directory even if enabled in the configuration
+        --read-only             Mount the rootfs read-only, with tmpfs on /tmp and /run (bwrap)
+    -d, --detach                Run the session in the background (see 'ps' and 'kill')
+        --gui                   Share the host X11/Wayland display with the session
+        --gpu                   Give the session the host GPUs (/dev/dri, /dev/nvidia*, driver ICDs)
     -b, --bind-args <ARGS>      Additional bind arguments (can be inline or next argument)
         --bind-args=<ARGS>      Additional bind arguments (inline)
+        --bind-profile <NAME>   Apply the binds and variables of a [bind_profiles.<NAME>] (can be repeated)
+        --proot-arg <ARG>       Pass an argument without whitespace to proot (can be repeated)
+        --bwrap-arg <ARG>       Pass an argument without whitespace to bwrap (can be repeated)
+        --hardened              Isolate the session (new session, own PID namespace, no capabilities)
+        --no-hardened           Do not harden the session even if enabled in the configuration
+        --kill-on-exit          Kill leftover processes when the command exits (proot)
+        --sysvipc               Emulate System V IPC for the session (proot)
+    -S, --su-bindings           Use proot's -S mode: fake root with common host bindings
+        --cap-drop <CAP>        Drop a capability, or ALL, with bwrap (can be repeated)
+        --cap-add <CAP>         Keep a capability after --cap-drop ALL (can be repeated)
+    -p, --publish <[IP:]HOST:GUEST[/udp]>
+                                Publish a port of a network-isolated bwrap session through slirp4netns
+        --publish=<[IP:]HOST:GUEST[/udp]>
+                                Publish a port through slirp4netns (inline, can be repeated)
+        --seccomp <PROFILE>     Apply a seccomp filter with bwrap: strict, a deny-list .json or a compiled .bpf
+    -t, --timeout <TIME>        Kill the command after a time limit (e.g. 90, 30m, 2h; exits 124)
+        --timeout=<TIME>        Kill the command after a time limit (inline)
+        --log-file <FILE>       Also append the session output to a file
+        --env <KEY=VALUE>       Set a variable in the session, or forward KEY from the host (can be repeated)
+        --preserve-env[=VARS]   Forward host variables: the listed ones, or locale, TZ, SSH agent and proxies
+        --env-file <FILE>       Set the KEY=VALUE variables listed in FILE
     -c, --command <CMD>         Command to execute inside rootfs (can be repeated)
         --command=<CMD>         Command to execute (inline)
+        --name <ENV>            Run in the named environment
     -R, --rootfs <DIR>          Specify rootfs directory
         --rootfs=<DIR>          Specify rootfs directory (inline)
 
 General Options for 'config':
+        --effective             Show every effective setting and where it comes from
         --use-proot             Use 'proot' as rootfs handler (default)
         --use-bwrap             Use 'bwrap' as rootfs handler
         --use-latest-stable     Use 'latest-stable' release (default)
         --use-edge              Use 'edge' release
+        --use-ipv4 | --use-ipv6 Download over IPv4 or IPv6 only
+        --use-any-ip            Download over any address family (default)
         --cache-dir <DIR>       Set cache directory
         --cache-dir=<DIR>       Set cache directory (inline)
         --output-dir <DIR>      Set output directory (default current directory)
         --output-dir=<DIR>      Set output directory (inline)
+        --enable-docs           Install documentation (docs, man pages) in new rootfs
+        --disable-docs          Do not install documentation in new rootfs (default)
+        --enable-apk-cache      Share downloaded packages between all rootfs through the cache dir
+        --disable-apk-cache     Keep downloaded packages inside each rootfs (default)
+        --logs-dir <DIR>        Log every run and builder session to this directory
+        --logs-dir=<DIR>        Log every run and builder session (inline)
         --rootfs-dir <DIR>      Set rootfs directory
         --rootfs-dir=<DIR>      Set rootfs directory (inline)
         --default-mirror <URL>  Set default Alpine mirror
         --default-mirror=<URL>  Set default Alpine mirror (inline)
+        --arch-mirror <ARCH=URL> Set the mirror used for a specific architecture
+        --max-cache-size <SIZE> Set maximum cache size, evicting oldest files (0 = unlimited)
+        --cache-ttl-days <DAYS> Evict cached files unused for this many days (0 = never)
+        --http-timeout <SECS>   Set connection/response timeout for downloads
+        --http-proxy <URL>      Set proxy for downloads (default from environment)
+        --download-segments <N> Split large downloads into N concurrent ranged requests (1 = off)
+        --ca-cert <FILE>        Trust an additional root certificate for downloads
+        --clear-ca-certs        Remove all additional root certificates
+        --add-bind <SRC[:DST][:ro]>
+                                Bind a host path in every run, apk and builder session
+        --remove-bind <SRC>     Stop binding a host path added with --add-bind
+        --enable-rootfs-ca-certs   Also trust the additional certificates in new rootfs
+        --disable-rootfs-ca-certs  Only use the additional certificates for downloads (default)
+        --enable-proot-kill-on-exit   Kill leftover processes after every proot session
+        --enable-proot-sysvipc        Emulate System V IPC in every proot session
+        --enable-proot-su-bindings    Use proot's -S mode in every session
+        --disable-proot-<OPTION>      Turn one of the proot options above off (default)
+        --enable-subids         Always map subordinate IDs with bwrap (see --subids)
+        --disable-subids        Map only the current user with bwrap (default)
+        --enable-hardening      Harden every run session by default (see run --hardened)
+        --disable-hardening     Only harden sessions started with --hardened (default)
+        --enable-bind-cwd       Start every run session in the current directory (see run --bind-cwd)
+        --disable-bind-cwd      Only bind the current directory with --bind-cwd (default)
 
 Overlay Options for 'config':
         --use-overlay | --enable-overlay  Enable OverlayFS to layer changes over the rootfs
@@ -133,8 +550,20 @@ Overlay Options for 'config':
         --overlay-action-commit           Merge changes back to the rootfs after execution
         --overlay-action-commit-atomic    Merge changes to the rootfs using an atomic operation
         --overlay-action-preserve         Preserve the upper layer data without discarding it
+        --enable-builder-ephemeral        Run every build in a discarded overlay by default
+        --disable-builder-ephemeral       Let builds modify the rootfs (default)
 
-Global Options:
+Global Options (before the parameter):
+        --rootfs <DIR>          Use this rootfs directory by default
+        --config <FILE>         Use an alternative configuration file
+        --system                Use the shared installation in /etc/alpack, /var/cache/alpack
+                                and /var/lib/alpack (writable by the 'alpack' group)
+        --profile <NAME>        Use the named configuration profile
+        --ca-cert <FILE>        Trust an additional root certificate (can be repeated)
+        --subids                Map /etc/subuid and /etc/subgid ranges into bwrap sessions
+    -q, --quiet                 Hide progress bars and spinners
+        --json                  Print results in JSON format
+        --offline               Use cached downloads only and fail at once when data is missing
     -h, --help                  Show this help message
     -V, --version               Show version
 
@@ -142,15 +571,91 @@ Environment variables:
     ALPACK_ARCH       Define the target architecture for rootfs (e.g., x86_64, aarch64)
     ALPACK_ROOTFS     Specify the path to the root filesystem used by ALPack
     ALPACK_CACHE      Specify the path to the cache directory used by ALPack
+    ALPACK_MIRROR_USER, ALPACK_MIRROR_PASSWORD
+                      Basic auth credentials for the mirror in use
+    ALPACK_MIRROR_TOKEN
+                      Bearer token for the mirror in use (ALPack downloads only)
 
 Examples:
     {cmd} setup --rootfs=/mnt/alpine --minimal --edge
     {cmd} apk --rootfs=/mnt/alpine install curl
-    {cmd} run -R /mnt/alpine -0 -- fdisk -l"
+    {cmd} run -R /mnt/alpine -0 -- fdisk -l
+    {cmd} --profile=work --json search --glob 'py3-*'"
     );
     Ok(())
 }
 
+/// Consumes the global options given before the subcommand.
+///
+/// Global options apply to every subcommand: `--rootfs` changes the
+/// default rootfs, `--config` and `--profile` select the configuration
+/// file, `--ca-cert` trusts an additional root certificate, `--quiet` hides
+/// progress output and `--json` prints results in a machine-readable form.
+/// Under WSL, Windows paths given to `--rootfs`, `--config` and `--ca-cert`
+/// are translated. Parsing stops at the first other argument.
+///
+/// # Parameters
+/// - `args`: The command-line arguments, without the program name.
+///
+/// # Returns
+/// - `Ok(bool)` once all leading global options were applied, `true` if
+///   subordinate ID mapping was requested with `--subids`.
+/// - `Err` if an option is missing its value.
+fn parse_global_options(args: &mut VecDeque<String>) -> Result<bool, Box<dyn Error>> {
+    let mut ca_certs = Vec::new();
+    let mut subids = false;
+
+    while let Some(arg) = args.pop_front() {
+        match arg.as_str() {
+            "-q" | "--quiet" => set_quiet(),
+            "--json" => set_json(),
+            "--offline" => set_offline(),
+            "--subids" => subids = true,
+            "--system" => set_system_mode(),
+            a if a.starts_with("--rootfs=") => {
+                let a = wsl::translate_path(a.to_string());
+                set_rootfs_override(parse_value!("alpack", "directory", &a)?.into());
+            }
+            "--rootfs" => {
+                let value = args.pop_front().map(wsl::translate_path);
+                set_rootfs_override(
+                    parse_value!("alpack", "directory", &arg, value.as_deref())?.into(),
+                );
+            }
+            a if a.starts_with("--config=") => {
+                let a = wsl::translate_path(a.to_string());
+                set_config_file(parse_value!("alpack", "file", &a)?.into());
+            }
+            "--config" => {
+                let value = args.pop_front().map(wsl::translate_path);
+                set_config_file(parse_value!("alpack", "file", &arg, value.as_deref())?.into());
+            }
+            a if a.starts_with("--profile=") => {
+                set_profile(&parse_value!("alpack", "profile", a)?);
+            }
+            "--profile" => {
+                let value = args.pop_front();
+                set_profile(&parse_value!("alpack", "profile", &arg, value.as_deref())?);
+            }
+            a if a.starts_with("--ca-cert=") => {
+                let a = wsl::translate_path(a.to_string());
+                ca_certs.push(PathBuf::from(parse_value!("alpack", "file", &a)?));
+            }
+            "--ca-cert" => {
+                let value = args.pop_front().map(wsl::translate_path);
+                ca_certs.push(parse_value!("alpack", "file", &arg, value.as_deref())?.into());
+            }
+            _ => {
+                args.push_front(arg);
+                break;
+            }
+        }
+    }
+
+    set_ca_certs(ca_certs);
+    Ok(subids)
+}
+
 /// Core logic dispatcher for the ALPack CLI.
 ///
 /// This function handles the initial environment parsing, identifies the
@@ -161,61 +666,73 @@ Examples:
 /// - `Err` if argument parsing fails or a submodule returns an error.
 fn alpack() -> Result<(), Box<dyn Error>> {
     sandbox_init("ALPack", "ALPACK_ARCH")?;
+
+    let mut args: VecDeque<String> = env::args_os()
+        .skip(1)
+        .map(|s| {
+            s.into_string()
+                .unwrap_or_else(|os| os.to_string_lossy().into_owned())
+        })
+        .collect();
+
+    let original_args: Vec<String> = args.iter().cloned().collect();
+    let subids = parse_global_options(&mut args)?;
     Settings::global();
+
+    if is_system_mode() {
+        prepare_system_dirs()?;
+    }
+
+    if wsl::is_wsl() && settings_cmd() == "bwrap" && !wsl::bwrap_usable() {
+        eprintln!(
+            "\x1b[1;33mWarning\x1b[0m: bwrap cannot create user namespaces on this WSL kernel, using proot."
+        );
+        set_cmd_override("proot");
+    }
     set_sandbox_tool(&settings_cmd())?;
 
-    let mut pargs = Arguments::from_env();
-    let command: Option<String> = pargs.opt_free_from_str().ok().flatten();
+    let command: Option<String> = args.pop_front();
+
+    let starts_sandbox = command
+        .as_deref()
+        .is_none_or(|cmd| SANDBOX_COMMANDS.contains(&cmd));
+    if starts_sandbox
+        && (subids || settings_subid_mapping())
+        && settings_cmd() == "bwrap"
+        && !userns::in_mapped_namespace()
+    {
+        process::exit(userns::run_with_subids(&original_args)?);
+    }
 
     let remaining_args: Vec<String> = match command.as_deref() {
         Some("-h") | Some("--help") | Some("-V") | Some("--version") => Vec::new(),
-        _ => pargs
-            .finish()
-            .into_iter()
-            .map(|s| {
-                s.into_string()
-                    .unwrap_or_else(|os| os.to_string_lossy().into_owned())
-            })
-            .collect(),
+        Some("run") | Some("exec") | None => wsl::translate_args(args.into(), true),
+        _ => wsl::translate_args(args.into(), false),
     };
 
-    match command.as_deref() {
-        Some("apk") => {
-            let mut args = remaining_args.into_iter();
-            let (mut rootfs, mut subcommand) = (None, None);
-            let mut subargs: Vec<String> = Vec::new();
-
-            while let Some(arg) = args.next() {
-                match arg.as_str() {
-                    "-R" | "--rootfs" => rootfs = args.next().map(PathBuf::from),
-                    a if a.starts_with("--rootfs=") => {
-                        rootfs = a.split_once('=').map(|(_, v)| PathBuf::from(v));
-                    }
-                    _ if subcommand.is_none() => subcommand = Some(arg),
-                    _ => subargs.push(arg),
-                }
-            }
+    let Some(command) = command else {
+        return Run::new(remaining_args).run();
+    };
 
-            Apk::new(subcommand, subargs, rootfs).run()
-        }
+    match command.as_str() {
+        "-h" | "--help" => print_help(app_name()),
+        "-V" | "--version" => Ok(println!("{}", env!("CARGO_PKG_VERSION"))),
+        cmd => {
+            if let Some((_, handler)) = COMMANDS.iter().find(|(names, _)| names.contains(&cmd)) {
+                return handler(cmd, remaining_args);
+            }
 
-        Some("add") | Some("del") | Some("install") | Some("remove") | Some("-s")
-        | Some("search") | Some("update") | Some("fix") | Some("-u") => {
-            Apk::new(command, remaining_args, None).run()
+            let similar = similar_commands(cmd);
+            if similar.is_empty() {
+                return invalid_arg!(cmd);
+            }
+            Err(format!(
+                "Unknown command '{cmd}'.\nDid you mean: {}?\nRun '{} --help' for the list of commands.",
+                similar.join(", "),
+                app_name()
+            )
+            .into())
         }
-
-        Some("aports") => Aports::new(remaining_args).run(),
-        Some("aptree") => Aptree::new(remaining_args).run(),
-        Some("builder") => Builder::new(remaining_args).run(),
-        Some("config") => Config::new(remaining_args).run(),
-        Some("run") => Run::new(remaining_args).run(), // Todo: -w caminho, --pwd=caminho, --cwd=caminho; --kill-on-exit: limpar processos "órfãos".
-        Some("setup") => Setup::new(remaining_args).run(),
-
-        Some("-h") | Some("--help") => print_help(app_name()),
-        Some("-V") | Some("--version") => Ok(println!("{}", env!("CARGO_PKG_VERSION"))),
-
-        Some(other) => invalid_arg!(other),
-        None => Run::new(remaining_args).run(),
     }
 }
 
@@ -228,10 +745,17 @@ fn alpack() -> Result<(), Box<dyn Error>> {
 fn main() {
     let exit_code: i32 = match alpack() {
         Ok(()) => 0,
-        Err(e) => {
-            eprintln!("{}", e);
-            1
-        }
+        Err(e) => match e.downcast_ref::<ExitStatusError>() {
+            Some(status) => status.0,
+            None => {
+                eprintln!("{}", e);
+                if e.downcast_ref::<TimeoutError>().is_some() {
+                    TIMEOUT_EXIT_CODE
+                } else {
+                    1
+                }
+            }
+        },
     };
-    std::process::exit(exit_code);
+    process::exit(exit_code);
 }
@@ -0,0 +1,191 @@
+//! Rootfs metadata manifest.
+//!
+//! `setup` records what a rootfs is (where it comes from, its architecture
+//! and release, when and by which ALPack version it was created and the
+//! packages requested) in `.alpack.toml` in the environment directory.
+//! `info` prints it.
+
+use crate::envs;
+use crate::mirror::env_release;
+use crate::qemu;
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{check_rootfs_exists, is_json};
+use indicatif::HumanDuration;
+use sandbox_utils::{invalid_arg, parse_value};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the manifest inside the environment directory.
+const MANIFEST_FILE: &str = ".alpack.toml";
+
+/// Metadata describing how a rootfs was created.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Manifest {
+    /// The distribution, empty for Alpine.
+    pub distro: String,
+    /// The tarball URL, image reference or archive the rootfs was created from.
+    pub source: String,
+    /// The Alpine version of the minirootfs, when set up from a mirror.
+    pub version: String,
+    /// The Alpine architecture of the rootfs.
+    pub arch: String,
+    /// The release branch its repositories point to.
+    pub release: String,
+    /// The mirror it was downloaded from, when set up from a mirror.
+    pub mirror: String,
+    /// Unix timestamp of the creation.
+    pub created: u64,
+    /// Version of ALPack that created the rootfs.
+    pub alpack_version: String,
+    /// Packages requested on top of the base system.
+    pub packages: Vec<String>,
+}
+
+impl Manifest {
+    /// Creates a manifest stamped with the current time and ALPack version.
+    ///
+    /// # Parameters
+    /// - `source`: Where the rootfs comes from.
+    /// - `packages`: The package lists installed by `setup`.
+    pub fn new(source: String, packages: &[&str]) -> Self {
+        Manifest {
+            source,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            alpack_version: env!("CARGO_PKG_VERSION").to_string(),
+            packages: packages
+                .iter()
+                .flat_map(|list| list.split_whitespace())
+                .map(|atom| atom.trim_matches('\'').to_string())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Writes the manifest into an environment directory.
+    ///
+    /// # Parameters
+    /// - `rootfs`: The environment directory containing `rootfs`.
+    pub fn write(&self, rootfs: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(rootfs.join(MANIFEST_FILE), toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Reads the manifest of an environment.
+    ///
+    /// # Parameters
+    /// - `rootfs`: The environment directory containing `rootfs`.
+    ///
+    /// # Returns
+    /// `None` if the rootfs was set up before manifests were written.
+    pub fn read(rootfs: &Path) -> Option<Self> {
+        fs::read_to_string(rootfs.join(MANIFEST_FILE))
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+    }
+}
+
+/// Controller for the `info` subcommand.
+pub struct Info {
+    /// Arguments captured after the `info` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Info {
+    /// Creates a new `Info` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Info { remaining_args }
+    }
+
+    /// Prints the manifest of a rootfs.
+    ///
+    /// Rootfs set up before manifests were written only show their
+    /// architecture and release.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing.
+    /// - `Err` if an argument is invalid or the rootfs is missing.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--name=") => {
+                    rootfs = envs::lookup(&parse_value!("info", "name", arg)?)?;
+                }
+                "--name" => {
+                    rootfs = envs::lookup(&parse_value!("info", "name", arg, args.pop_front())?)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("info", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("info", "directory", arg, args.pop_front())?.into();
+                }
+                _ => return invalid_arg!("info", arg),
+            }
+        }
+
+        check_rootfs_exists(rootfs.clone())?;
+
+        let manifest = Manifest::read(&rootfs).unwrap_or_else(|| Manifest {
+            arch: qemu::env_arch(&rootfs),
+            release: env_release(&rootfs),
+            ..Default::default()
+        });
+
+        if is_json() {
+            println!("{}", serde_json::to_string_pretty(&manifest)?);
+            return Ok(());
+        }
+
+        let or_unknown = |value: String| {
+            if value.is_empty() {
+                "unknown".to_string()
+            } else {
+                value
+            }
+        };
+
+        let created = match manifest.created {
+            0 => "unknown".to_string(),
+            secs => {
+                let age = SystemTime::now()
+                    .duration_since(UNIX_EPOCH + Duration::from_secs(secs))
+                    .unwrap_or_default();
+                format!("{secs} ({} ago)", HumanDuration(age))
+            }
+        };
+
+        let distro = match manifest.distro.as_str() {
+            "" => "alpine".to_string(),
+            other => other.to_string(),
+        };
+
+        let rows = [
+            ("rootfs", rootfs.display().to_string()),
+            ("distro", distro),
+            ("source", or_unknown(manifest.source)),
+            ("version", or_unknown(manifest.version)),
+            ("arch", or_unknown(manifest.arch)),
+            ("release", or_unknown(manifest.release)),
+            ("mirror", or_unknown(manifest.mirror)),
+            ("created", created),
+            ("alpack_version", or_unknown(manifest.alpack_version)),
+            ("packages", or_unknown(manifest.packages.join(" "))),
+        ];
+
+        for (key, value) in rows {
+            println!("{key:<15} {value}");
+        }
+        Ok(())
+    }
+}
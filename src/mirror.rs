@@ -2,11 +2,168 @@
 //!
 //! This module orchestrates the construction of Alpine Linux download URLs.
 //! It combines mirrors, release branches, and system architecture to generate
-//! valid paths for rootfs tarballs and APK repositories.
+//! valid paths for rootfs tarballs and APK repositories. Known mirrors can
+//! be probed concurrently to pick the fastest one (`setup --fastest-mirror`,
+//! `mirror test`).
 
+use crate::http;
+use crate::http::set_mirror_host;
+use crate::qemu;
 use crate::settings::{settings_mirror, settings_release};
-use sandbox_utils::app_arch;
+use crate::utils::{is_json, new_spinner};
+use indicatif::HumanBytes;
+use sandbox_utils::{app_arch, invalid_arg, missing_arg, parse_value};
+use serde_json::json;
+use std::collections::VecDeque;
 use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Name of the file holding the release branch inside the environment directory.
+const RELEASE_FILE: &str = "release";
+
+/// Well-known Alpine mirrors probed to find the fastest one, next to the
+/// configured mirror.
+const KNOWN_MIRRORS: [&str; 8] = [
+    "https://dl-cdn.alpinelinux.org/alpine/",
+    "https://mirrors.edge.kernel.org/alpine/",
+    "https://mirror.leaseweb.com/alpine/",
+    "https://ftp.halifax.rwth-aachen.de/alpine/",
+    "https://mirrors.ustc.edu.cn/alpine/",
+    "https://mirror.aarnet.edu.au/pub/alpine/",
+    "https://alpine.mirror.wearetriple.com/",
+    "https://mirror.yandex.ru/mirrors/alpine/",
+];
+
+/// Bytes downloaded from each mirror to measure its throughput.
+const PROBE_BYTES: u64 = 512 * 1024;
+
+/// Result of probing a mirror.
+pub struct Probe {
+    /// Time until the response headers arrived.
+    pub latency: Duration,
+    /// Download speed, in bytes per second.
+    pub throughput: u64,
+}
+
+/// Validates a release branch given on the command line.
+///
+/// # Parameters
+/// - `release`: `latest-stable`, `edge` or a branch such as `v3.20`.
+///
+/// # Returns
+/// - `Ok(String)` with the release.
+/// - `Err` if the value is not a release branch.
+pub fn parse_release(release: &str) -> Result<String, Box<dyn Error>> {
+    let branch = release.strip_prefix('v').and_then(|v| v.split_once('.'));
+    let is_branch = branch.is_some_and(|(major, minor)| {
+        [major, minor]
+            .iter()
+            .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    });
+
+    if is_branch || release == "edge" || release == "latest-stable" {
+        Ok(release.to_string())
+    } else {
+        Err(
+            format!("Invalid release '{release}'. Use latest-stable, edge or a branch like v3.20")
+                .into(),
+        )
+    }
+}
+
+/// Returns the release branch an environment was set up from.
+///
+/// Environments set up before releases were recorded use the configured one.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+pub fn env_release(rootfs: &Path) -> String {
+    fs::read_to_string(rootfs.join(RELEASE_FILE))
+        .ok()
+        .map(|release| release.trim().to_string())
+        .filter(|release| !release.is_empty())
+        .unwrap_or_else(settings_release)
+}
+
+/// Records the release branch of an environment.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+/// - `release`: The release branch its repositories point to.
+pub fn write_release(rootfs: &Path, release: &str) -> Result<(), Box<dyn Error>> {
+    fs::write(rootfs.join(RELEASE_FILE), format!("{release}\n"))?;
+    Ok(())
+}
+
+/// Measures the latency and throughput of a mirror.
+///
+/// The main repository index of the release is requested and up to
+/// [`PROBE_BYTES`] of it are downloaded.
+fn probe(mirror: &str, release: &str, arch: &str) -> Result<Probe, Box<dyn Error>> {
+    let url = format!("{mirror}{release}/main/{arch}/APKINDEX.tar.gz");
+
+    let start = Instant::now();
+    let response = http::get(&url).call()?;
+    let latency = start.elapsed();
+
+    let start = Instant::now();
+    let mut body = response.into_body().into_reader().take(PROBE_BYTES);
+    let bytes = io::copy(&mut body, &mut io::sink())?;
+    let secs = start.elapsed().as_secs_f64().max(0.001);
+
+    Ok(Probe {
+        latency,
+        throughput: (bytes as f64 / secs) as u64,
+    })
+}
+
+/// Probes the configured and the well-known mirrors concurrently.
+///
+/// # Parameters
+/// - `release`: The release branch whose index is downloaded.
+/// - `arch`: The architecture whose index is downloaded.
+///
+/// # Returns
+/// Every mirror with its probe result or the reason it failed, the
+/// fastest first and the unreachable ones last.
+pub fn probe_mirrors(release: &str, arch: &str) -> Vec<(String, Result<Probe, String>)> {
+    let mut mirrors = vec![settings_mirror(arch)];
+    for mirror in KNOWN_MIRRORS {
+        if !mirrors.iter().any(|m| m == mirror) {
+            mirrors.push(mirror.to_string());
+        }
+    }
+
+    let mut results: Vec<(String, Result<Probe, String>)> = thread::scope(|scope| {
+        let handles: Vec<_> = mirrors
+            .iter()
+            .map(|mirror| {
+                scope.spawn(move || probe(mirror, release, arch).map_err(|e| e.to_string()))
+            })
+            .collect();
+
+        mirrors
+            .iter()
+            .zip(handles)
+            .map(|(mirror, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err("probe panicked".to_string()));
+                (mirror.clone(), result)
+            })
+            .collect()
+    });
+
+    results.sort_by_key(|(_, result)| match result {
+        Ok(probe) => (0, u64::MAX - probe.throughput, probe.latency),
+        Err(_) => (1, 0, Duration::ZERO),
+    });
+    results
+}
 
 /// Manager for Alpine Linux mirror and release metadata.
 pub struct Mirror {
@@ -14,28 +171,90 @@ pub struct Mirror {
     mirror: Option<String>,
     /// The target release version or branch (e.g., v3.18, edge).
     release: Option<String>,
+    /// The target architecture, when different from the one ALPack runs for.
+    arch: Option<String>,
 }
 
 impl Mirror {
     /// Creates a new Mirror instance with optional overrides.
     pub fn new(mirror: Option<String>, release: Option<String>) -> Self {
-        Mirror { mirror, release }
+        Mirror {
+            mirror,
+            release,
+            arch: None,
+        }
+    }
+
+    /// Targets another architecture than the one ALPack runs for.
+    ///
+    /// Must be called before [`Mirror::run`].
+    pub fn set_arch(&mut self, arch: String) {
+        self.arch = Some(arch);
+    }
+
+    /// Returns the effective target architecture.
+    pub fn get_arch(&self) -> String {
+        self.arch.clone().unwrap_or_else(app_arch)
     }
 
     /// Initializes missing mirror/release values using global settings.
     ///
+    /// The default mirror is resolved for the effective target architecture,
+    /// honoring per-arch mirror tables in the configuration.
+    ///
     /// # Returns
     /// * `Ok(())` - Always returns success after ensuring values are present.
     pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
         if self.mirror.as_deref().unwrap_or("").is_empty() {
-            self.mirror = Some(settings_mirror());
+            self.mirror = Some(settings_mirror(&self.get_arch()));
         }
         if self.release.as_deref().unwrap_or("").is_empty() {
             self.release = Some(settings_release());
         }
+        set_mirror_host(self.mirror.as_deref().unwrap_or(""));
         Ok(())
     }
 
+    /// Replaces the mirror with the fastest reachable one.
+    ///
+    /// Must be called before [`Mirror::run`].
+    ///
+    /// # Returns
+    /// - `Ok(())` once a mirror was selected.
+    /// - `Err` if no mirror serves the release and architecture.
+    pub fn use_fastest(&mut self) -> Result<(), Box<dyn Error>> {
+        let release = match self.release.as_deref() {
+            Some(release) if !release.is_empty() => release.to_string(),
+            _ => settings_release(),
+        };
+        let arch = self.get_arch();
+
+        let spinner = new_spinner("Probing mirrors")?;
+        let probes = probe_mirrors(&release, &arch);
+        let Some((mirror, Ok(probe))) = probes.into_iter().next() else {
+            spinner.finish_with_message("Probing mirrors: failed");
+            return Err(format!("No mirror serves release '{release}' for '{arch}'.").into());
+        };
+        spinner.finish_with_message(format!(
+            "Probing mirrors: {mirror} ({} ms, {}/s)",
+            probe.latency.as_millis(),
+            HumanBytes(probe.throughput)
+        ));
+
+        self.mirror = Some(mirror);
+        Ok(())
+    }
+
+    /// Returns the mirror base URL in use, once [`Mirror::run`] resolved it.
+    pub fn get_base_url(&self) -> String {
+        self.mirror.clone().unwrap_or_default()
+    }
+
+    /// Returns the release branch in use, once [`Mirror::run`] resolved it.
+    pub fn get_release(&self) -> String {
+        self.release.clone().unwrap_or_default()
+    }
+
     /// Constructs the base URL for fetching the rootfs tarball.
     ///
     /// # Returns
@@ -45,7 +264,7 @@ impl Mirror {
             "{}{}/releases/{}/",
             self.mirror.as_deref().unwrap_or(""),
             self.release.as_deref().unwrap_or(""),
-            app_arch()
+            self.get_arch()
         )
     }
 
@@ -66,4 +285,131 @@ impl Mirror {
 
         repos
     }
+
+    /// Verifies that the mirror serves the requested release and architecture.
+    ///
+    /// A few `HEAD` requests are issued before any long-running or
+    /// destructive step, so an unreachable mirror, a missing release or an
+    /// unsupported architecture is reported up front.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the main repository index for the architecture exists.
+    /// - `Err` with a message naming what is missing otherwise.
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        let mirror = self.mirror.as_deref().unwrap_or("");
+        let release = self.release.as_deref().unwrap_or("");
+        let arch = self.get_arch();
+
+        let index = format!("{mirror}{release}/main/{arch}/APKINDEX.tar.gz");
+
+        match http::head(&index).call() {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::StatusCode(401 | 403)) => Err(format!(
+                "Mirror '{mirror}' denied access.\nConfigure credentials under [mirror_auth] or with ALPACK_MIRROR_USER/ALPACK_MIRROR_PASSWORD."
+            )
+            .into()),
+            Err(ureq::Error::StatusCode(404)) => {
+                if http::head(&format!("{mirror}{release}/")).call().is_ok() {
+                    Err(format!(
+                        "Architecture '{arch}' is not available for release '{release}' on mirror '{mirror}'."
+                    )
+                    .into())
+                } else {
+                    Err(format!("Release '{release}' was not found on mirror '{mirror}'.").into())
+                }
+            }
+            Err(e) => Err(format!("Mirror '{mirror}' is unreachable: {e}").into()),
+        }
+    }
+}
+
+/// Controller for the `mirror` subcommand.
+pub struct MirrorCommand {
+    /// Arguments captured after the `mirror` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl MirrorCommand {
+    /// Creates a new `MirrorCommand` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        MirrorCommand { remaining_args }
+    }
+
+    /// Parses the mirror action and its options and executes it.
+    ///
+    /// Currently supports `test`, which probes the known mirrors and prints
+    /// them from the fastest to the slowest.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing the results.
+    /// - `Err` if an argument is invalid.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+
+        let Some(action) = args.pop_front() else {
+            return missing_arg!("mirror");
+        };
+
+        let mut release = settings_release();
+        let mut arch = app_arch();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--release=") => {
+                    release = parse_release(&parse_value!("mirror", "release", arg)?)?;
+                }
+                "--release" => {
+                    let value = parse_value!("mirror", "release", arg, args.pop_front())?;
+                    release = parse_release(&value)?;
+                }
+                a if a.starts_with("--arch=") => {
+                    arch = qemu::parse_arch(&parse_value!("mirror", "arch", arg)?)?;
+                }
+                "--arch" => {
+                    let value = parse_value!("mirror", "arch", arg, args.pop_front())?;
+                    arch = qemu::parse_arch(&value)?;
+                }
+                _ => return invalid_arg!("mirror", arg),
+            }
+        }
+
+        match action {
+            "test" => {
+                let spinner = new_spinner("Probing mirrors")?;
+                let probes = probe_mirrors(&release, &arch);
+                spinner.finish_and_clear();
+
+                if is_json() {
+                    let list: Vec<_> = probes
+                        .iter()
+                        .map(|(mirror, result)| match result {
+                            Ok(probe) => json!({
+                                "mirror": mirror,
+                                "latency_ms": probe.latency.as_millis() as u64,
+                                "throughput": probe.throughput,
+                            }),
+                            Err(e) => json!({ "mirror": mirror, "error": e }),
+                        })
+                        .collect();
+                    println!("{}", serde_json::to_string_pretty(&list)?);
+                    return Ok(());
+                }
+
+                let width = probes.iter().map(|(m, _)| m.len()).max().unwrap_or(0);
+                println!("{:<width$}  {:>8}  THROUGHPUT", "MIRROR", "LATENCY");
+                for (mirror, result) in &probes {
+                    match result {
+                        Ok(probe) => println!(
+                            "{mirror:<width$}  {:>5} ms  {}/s",
+                            probe.latency.as_millis(),
+                            HumanBytes(probe.throughput)
+                        ),
+                        Err(e) => println!("{mirror:<width$}  unreachable: {e}"),
+                    }
+                }
+                Ok(())
+            }
+            other => invalid_arg!("mirror", other),
+        }
+    }
 }
@@ -0,0 +1,148 @@
+//! Ownership database for unprivileged rootfs trees.
+//!
+//! Without root, every file ALPack extracts or copies into a rootfs ends up
+//! owned by the invoking user, losing the ownership recorded in the archive.
+//! Like fakeroot's saved state, the intended owner and mode of those files
+//! are kept in `ownership.db` next to the `rootfs` directory, so exports can
+//! restore them. Files installed by apk are already described by the apk
+//! database and are not duplicated here.
+
+use crate::utils::current_uid;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+
+/// Name of the database file inside the environment directory.
+const OWNERSHIP_DB: &str = "ownership.db";
+
+/// Intended owner and permission bits of a rootfs entry.
+#[derive(Clone, Copy, Debug)]
+pub struct Owner {
+    /// Numeric user ID.
+    pub uid: u64,
+    /// Numeric group ID.
+    pub gid: u64,
+    /// Permission bits, including setuid, setgid and sticky.
+    pub mode: u32,
+}
+
+/// The ownership records of one environment, keyed by path inside the rootfs.
+pub struct OwnershipDb {
+    /// Location of `ownership.db`.
+    file: PathBuf,
+    /// Records sorted by absolute path as seen inside the rootfs.
+    entries: BTreeMap<String, Owner>,
+}
+
+impl OwnershipDb {
+    /// Opens the database of an environment, starting empty if missing.
+    ///
+    /// Malformed lines are skipped.
+    ///
+    /// # Parameters
+    /// - `rootfs`: The environment directory containing `rootfs`.
+    pub fn open(rootfs: &Path) -> Self {
+        let file = rootfs.join(OWNERSHIP_DB);
+        let entries = fs::read_to_string(&file)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(4, ' ');
+                let uid = fields.next()?.parse().ok()?;
+                let gid = fields.next()?.parse().ok()?;
+                let mode = u32::from_str_radix(fields.next()?, 8).ok()?;
+                Some((fields.next()?.to_string(), Owner { uid, gid, mode }))
+            })
+            .collect();
+
+        OwnershipDb { file, entries }
+    }
+
+    /// Returns whether ownership has to be tracked for this process.
+    ///
+    /// Only unprivileged runs lose ownership, so root skips the database.
+    pub fn needed() -> bool {
+        current_uid() != 0
+    }
+
+    /// Records the owner of a path inside the rootfs.
+    ///
+    /// # Parameters
+    /// - `path`: The path relative to the rootfs root.
+    /// - `owner`: The intended owner and mode.
+    pub fn record(&mut self, path: &Path, owner: Owner) {
+        let path = format!("/{}", path.to_string_lossy().trim_matches('/'));
+        self.entries.insert(path, owner);
+    }
+
+    /// Unpacks a tar archive into a directory, recording each entry's owner.
+    ///
+    /// # Parameters
+    /// - `archive`: The archive to unpack.
+    /// - `dest`: The rootfs root receiving the entries.
+    ///
+    /// # Returns
+    /// - `Ok(())` if every entry was unpacked.
+    /// - `Err` if reading or unpacking an entry fails.
+    pub fn unpack<R: Read>(
+        &mut self,
+        archive: &mut Archive<R>,
+        dest: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let header = entry.header();
+            let owner = Owner {
+                uid: header.uid()?,
+                gid: header.gid()?,
+                mode: header.mode()?,
+            };
+            let path = entry.path()?.into_owned();
+
+            entry.unpack_in(dest)?;
+            self.record(&path, owner);
+        }
+        Ok(())
+    }
+
+    /// Records files copied from the host as owned by root.
+    ///
+    /// Directories are walked recursively and keep their current mode.
+    ///
+    /// # Parameters
+    /// - `root`: The rootfs root on the host.
+    /// - `rel`: The copied path relative to `root`.
+    pub fn record_copied(&mut self, root: &Path, rel: &Path) -> Result<(), Box<dyn Error>> {
+        let meta = fs::symlink_metadata(root.join(rel))?;
+        let owner = Owner {
+            uid: 0,
+            gid: 0,
+            mode: meta.permissions().mode() & 0o7777,
+        };
+        self.record(rel, owner);
+
+        if meta.is_dir() {
+            for entry in fs::read_dir(root.join(rel))? {
+                self.record_copied(root, &rel.join(entry?.file_name()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the database back, one `uid gid mode path` record per line.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let content: String = self
+            .entries
+            .iter()
+            .map(|(path, o)| format!("{} {} {:o} {path}\n", o.uid, o.gid, o.mode))
+            .collect();
+
+        fs::write(&self.file, content)
+            .map_err(|e| format!("Failed to write {}: {e}", self.file.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Package publishing module.
+//!
+//! Uploads the packages produced by `builder` to a remote repository,
+//! turning a rootfs into a complete self-hosted repository pipeline. The
+//! APKINDEX is regenerated and signed inside the rootfs, packages are
+//! uploaded first and the index last through a temporary name, so clients
+//! never see an index referring to packages that are not there yet.
+
+use crate::qemu;
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{check_rootfs_exists, map_result};
+use sandbox_utils::{invalid_arg, missing_arg, parse_value, SandBox, SandBoxConfig};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Controller for the `publish` subcommand.
+pub struct Publish {
+    /// Arguments captured after the `publish` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Publish {
+    /// Creates a new `Publish` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Publish { remaining_args }
+    }
+
+    /// Regenerates the repository index and uploads it with the packages.
+    ///
+    /// Supported destinations are `rsync://`, `s3://` (via the `aws` CLI)
+    /// and `sftp://`. Files are placed in an architecture subdirectory of
+    /// the destination, matching Alpine's repository layout.
+    ///
+    /// # Returns
+    /// - `Ok(())` if every file was uploaded.
+    /// - `Err` if no packages exist, signing fails, or an upload fails.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+        let mut dest = None;
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--dest=") => {
+                    dest = Some(parse_value!("publish", "url", arg)?);
+                }
+                "-d" | "--dest" => {
+                    dest = Some(parse_value!("publish", "url", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("publish", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("publish", "directory", arg, args.pop_front())?.into();
+                }
+                _ => return invalid_arg!("publish", arg),
+            }
+        }
+
+        let Some(dest) = dest else {
+            return missing_arg!("publish");
+        };
+
+        check_rootfs_exists(rootfs.clone())?;
+
+        let arch = qemu::env_arch(&rootfs);
+        let build_dir = rootfs.join("build");
+        let repo_dir = build_dir.join("packages/build").join(&arch);
+
+        let has_packages = fs::read_dir(&repo_dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .any(|e| e.file_name().to_string_lossy().ends_with(".apk"))
+            })
+            .unwrap_or(false);
+
+        if !has_packages {
+            return Err(format!("No built packages found in {}", repo_dir.display()).into());
+        }
+
+        let run_cmd = format!(
+            "HOME={b}
+            cd {r} && \
+            rm -f APKINDEX.tar.gz && \
+            apk index --allow-untrusted -o APKINDEX.tar.gz *.apk && \
+            abuild-sign APKINDEX.tar.gz",
+            b = build_dir.display(),
+            r = repo_dir.display(),
+        );
+
+        let config = SandBoxConfig {
+            rootfs,
+            run_cmd,
+            use_root: true,
+            ignore_extra_bind: true,
+            ..Default::default()
+        };
+
+        map_result(SandBox::run(config))?;
+
+        let dest = dest.trim_end_matches('/');
+        match dest.split_once("://") {
+            Some(("rsync", _)) => Self::publish_rsync(&repo_dir, &format!("{dest}/{arch}")),
+            Some(("s3", _)) => Self::publish_s3(&repo_dir, &format!("{dest}/{arch}")),
+            Some(("sftp", target)) => Self::publish_sftp(&repo_dir, target, &arch),
+            _ => Err(
+                format!("Unsupported destination '{dest}'. Use rsync://, s3:// or sftp://").into(),
+            ),
+        }?;
+
+        println!("Published {} to {dest}/{arch}", repo_dir.display());
+        Ok(())
+    }
+
+    /// Uploads the repository with rsync.
+    ///
+    /// `--delay-updates` makes rsync write into temporary files and rename
+    /// them at the end, and the index is sent in a second, final transfer.
+    fn publish_rsync(repo_dir: &Path, dest: &str) -> Result<(), Box<dyn Error>> {
+        let src = format!("{}/", repo_dir.display());
+
+        Self::exec(
+            Command::new("rsync")
+                .args(["-a", "--delay-updates", "--exclude", "APKINDEX.tar.gz"])
+                .args([&src, &format!("{dest}/")]),
+        )?;
+        Self::exec(Command::new("rsync").args([
+            "-a",
+            "--delay-updates",
+            &format!("{src}APKINDEX.tar.gz"),
+            &format!("{dest}/"),
+        ]))
+    }
+
+    /// Uploads the repository to S3 with the `aws` CLI.
+    ///
+    /// Object uploads are atomic, so uploading the index after the
+    /// packages is enough to keep the repository consistent.
+    fn publish_s3(repo_dir: &Path, dest: &str) -> Result<(), Box<dyn Error>> {
+        Self::exec(
+            Command::new("aws")
+                .args(["s3", "cp", "--recursive", "--exclude", "APKINDEX.tar.gz"])
+                .arg(repo_dir)
+                .arg(format!("{dest}/")),
+        )?;
+        Self::exec(
+            Command::new("aws")
+                .args(["s3", "cp"])
+                .arg(repo_dir.join("APKINDEX.tar.gz"))
+                .arg(format!("{dest}/APKINDEX.tar.gz")),
+        )
+    }
+
+    /// Uploads the repository over SFTP.
+    ///
+    /// The index is uploaded under a temporary name and renamed over the
+    /// previous one once all packages are in place.
+    ///
+    /// # Parameters
+    /// - `repo_dir`: The local repository directory.
+    /// - `target`: The destination without scheme, as `[user@]host[:port]/path`.
+    /// - `arch`: The architecture subdirectory.
+    fn publish_sftp(repo_dir: &Path, target: &str, arch: &str) -> Result<(), Box<dyn Error>> {
+        let (host, path) = target.split_once('/').unwrap_or((target, ""));
+        let remote = format!("/{path}/{arch}");
+
+        let mut cmd = Command::new("sftp");
+        match host.rsplit_once(':') {
+            Some((host, port)) => cmd.args(["-P", port, "-b", "-", host]),
+            None => cmd.args(["-b", "-", host]),
+        };
+
+        let batch = format!(
+            "-mkdir /{path}\n\
+            -mkdir {remote}\n\
+            cd {remote}\n\
+            lcd {local}\n\
+            put *.apk\n\
+            put APKINDEX.tar.gz APKINDEX.tar.gz.tmp\n\
+            rename APKINDEX.tar.gz.tmp APKINDEX.tar.gz\n",
+            local = repo_dir.display(),
+        );
+
+        let mut child = cmd.stdin(Stdio::piped()).spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(batch.as_bytes())?;
+        }
+
+        if !child.wait()?.success() {
+            return Err("sftp upload failed".into());
+        }
+        Ok(())
+    }
+
+    /// Runs a host command and turns a non-zero exit into an error.
+    fn exec(cmd: &mut Command) -> Result<(), Box<dyn Error>> {
+        let program = cmd.get_program().to_string_lossy().into_owned();
+        let status = cmd
+            .status()
+            .map_err(|e| format!("Failed to run '{program}': {e}"))?;
+
+        if !status.success() {
+            return Err(format!("'{program}' exited with {status}").into());
+        }
+        Ok(())
+    }
+}
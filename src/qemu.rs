@@ -0,0 +1,312 @@
+//! Foreign-architecture rootfs support through QEMU user-mode emulation.
+//!
+//! `setup --arch <ARCH>` records the architecture of the rootfs in an
+//! `arch` file of the environment. Sessions in a rootfs the host cannot
+//! run natively get a static `qemu-<arch>-static` binary: proot runs it
+//! with `-q`, while bwrap relies on a binfmt_misc handler, registered with
+//! the `F` flag so it keeps working inside the sandbox. Static binaries
+//! found in the host `PATH` are preferred; otherwise they are downloaded
+//! into the cache like the other ALPack downloads.
+
+use crate::cache::Cache;
+use crate::settings::{settings_cache_dir, settings_cmd};
+use crate::utils::current_uid;
+use flate2::read::GzDecoder;
+use sandbox_utils::{app_arch, app_name};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+
+/// Name of the file holding the architecture inside the environment directory.
+const ARCH_FILE: &str = "arch";
+
+/// Release of the static QEMU user-mode binaries downloaded when missing.
+const QEMU_STATIC_URL: &str =
+    "https://github.com/multiarch/qemu-user-static/releases/download/v7.2.0-1";
+
+/// Location of the binfmt_misc handlers.
+const BINFMT_DIR: &str = "/proc/sys/fs/binfmt_misc";
+
+/// ELF identification of an architecture, used for binfmt_misc.
+struct ElfArch {
+    /// Alpine architecture name.
+    alpine: &'static str,
+    /// QEMU target name (`qemu-<name>-static`).
+    qemu: &'static str,
+    /// Whether binaries are 64-bit.
+    wide: bool,
+    /// Whether binaries are big-endian.
+    big_endian: bool,
+    /// The `e_machine` value of the ELF header.
+    machine: u16,
+}
+
+/// Architectures Alpine publishes, with their QEMU and ELF identification.
+const ARCHES: [ElfArch; 9] = [
+    ElfArch {
+        alpine: "x86_64",
+        qemu: "x86_64",
+        wide: true,
+        big_endian: false,
+        machine: 62,
+    },
+    ElfArch {
+        alpine: "x86",
+        qemu: "i386",
+        wide: false,
+        big_endian: false,
+        machine: 3,
+    },
+    ElfArch {
+        alpine: "aarch64",
+        qemu: "aarch64",
+        wide: true,
+        big_endian: false,
+        machine: 183,
+    },
+    ElfArch {
+        alpine: "armv7",
+        qemu: "arm",
+        wide: false,
+        big_endian: false,
+        machine: 40,
+    },
+    ElfArch {
+        alpine: "armhf",
+        qemu: "arm",
+        wide: false,
+        big_endian: false,
+        machine: 40,
+    },
+    ElfArch {
+        alpine: "ppc64le",
+        qemu: "ppc64le",
+        wide: true,
+        big_endian: false,
+        machine: 21,
+    },
+    ElfArch {
+        alpine: "s390x",
+        qemu: "s390x",
+        wide: true,
+        big_endian: true,
+        machine: 22,
+    },
+    ElfArch {
+        alpine: "riscv64",
+        qemu: "riscv64",
+        wide: true,
+        big_endian: false,
+        machine: 243,
+    },
+    ElfArch {
+        alpine: "loongarch64",
+        qemu: "loongarch64",
+        wide: true,
+        big_endian: false,
+        machine: 258,
+    },
+];
+
+/// Returns the Alpine name of the host architecture.
+fn host_arch() -> &'static str {
+    match env::consts::ARCH {
+        "x86" => "x86",
+        "arm" => "armv7",
+        "powerpc64" => "ppc64le",
+        other => ARCHES
+            .iter()
+            .find(|a| a.alpine == other)
+            .map(|a| a.alpine)
+            .unwrap_or("x86_64"),
+    }
+}
+
+/// Looks up an Alpine architecture.
+///
+/// # Returns
+/// - `Ok(&ElfArch)` for a known architecture.
+/// - `Err` listing the supported ones otherwise.
+fn lookup(arch: &str) -> Result<&'static ElfArch, Box<dyn Error>> {
+    ARCHES.iter().find(|a| a.alpine == arch).ok_or_else(|| {
+        let names: Vec<&str> = ARCHES.iter().map(|a| a.alpine).collect();
+        format!(
+            "Unknown architecture '{arch}'. Supported: {}",
+            names.join(", ")
+        )
+        .into()
+    })
+}
+
+/// Validates an architecture given on the command line.
+///
+/// # Returns
+/// - `Ok(String)` with the architecture.
+/// - `Err` if Alpine does not publish it.
+pub fn parse_arch(arch: &str) -> Result<String, Box<dyn Error>> {
+    lookup(arch).map(|a| a.alpine.to_string())
+}
+
+/// Returns the architecture of an environment.
+///
+/// Environments set up without `--arch` use the architecture ALPack runs for.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+pub fn env_arch(rootfs: &Path) -> String {
+    fs::read_to_string(rootfs.join(ARCH_FILE))
+        .ok()
+        .map(|arch| arch.trim().to_string())
+        .filter(|arch| !arch.is_empty())
+        .unwrap_or_else(app_arch)
+}
+
+/// Records the architecture of an environment.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+/// - `arch`: The Alpine architecture of the rootfs.
+pub fn write_arch(rootfs: &Path, arch: &str) -> Result<(), Box<dyn Error>> {
+    fs::write(rootfs.join(ARCH_FILE), format!("{arch}\n"))?;
+    Ok(())
+}
+
+/// Returns whether the host runs binaries of an architecture natively.
+fn is_native(arch: &str) -> bool {
+    let host = host_arch();
+    arch == host || (host == "x86_64" && arch == "x86") || (host == "armv7" && arch == "armhf")
+}
+
+/// Prepares the emulation of a foreign-architecture rootfs.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+///
+/// # Returns
+/// - `Ok(String)` with the backend arguments: `-q <qemu>` for proot and
+///   nothing for bwrap or native environments.
+/// - `Err` if QEMU cannot be found or downloaded, or bwrap has no
+///   binfmt_misc handler and it cannot be registered.
+pub fn emulation_args(rootfs: &Path) -> Result<String, Box<dyn Error>> {
+    let arch = env_arch(rootfs);
+    if is_native(&arch) {
+        return Ok(String::new());
+    }
+
+    let target = lookup(&arch)?;
+    let qemu = find_qemu(target)?;
+
+    if settings_cmd() == "bwrap" {
+        ensure_binfmt(target, &qemu)?;
+        Ok(String::new())
+    } else {
+        Ok(format!("-q {}", qemu.display()))
+    }
+}
+
+/// Finds the static QEMU binary for an architecture, downloading it if needed.
+fn find_qemu(target: &ElfArch) -> Result<PathBuf, Box<dyn Error>> {
+    let name = format!("qemu-{}-static", target.qemu);
+
+    let in_path = env::var_os("PATH").and_then(|path| {
+        env::split_paths(&path)
+            .map(|dir| dir.join(&name))
+            .find(|bin| bin.is_file())
+    });
+    if let Some(bin) = in_path {
+        return Ok(bin);
+    }
+
+    let dir = settings_cache_dir().join("qemu");
+    let bin = dir.join(&name);
+    if bin.is_file() {
+        return Ok(bin);
+    }
+
+    let host = lookup(host_arch())?;
+    let tarball_name = format!("{}_{name}.tar.gz", host.qemu);
+    let url = format!("{QEMU_STATIC_URL}/{tarball_name}");
+    let tarball = Cache::open(settings_cache_dir())?.fetch(&url, &tarball_name)?;
+
+    fs::create_dir_all(&dir)?;
+    Archive::new(GzDecoder::new(File::open(&tarball)?)).unpack(&dir)?;
+    if !bin.is_file() {
+        return Err(format!("{tarball_name} does not contain {name}").into());
+    }
+    fs::set_permissions(&bin, fs::Permissions::from_mode(0o755))?;
+    Ok(bin)
+}
+
+/// Makes sure a binfmt_misc handler runs binaries of an architecture.
+///
+/// Handlers are registered with the `F` flag, so the interpreter is opened
+/// once by the kernel and does not need to exist inside the sandbox.
+/// Registering requires root; other users get the command to run.
+fn ensure_binfmt(target: &ElfArch, qemu: &Path) -> Result<(), Box<dyn Error>> {
+    let handler = Path::new(BINFMT_DIR).join(format!("qemu-{}", target.qemu));
+    if fs::read_to_string(&handler).is_ok_and(|s| s.starts_with("enabled")) {
+        return Ok(());
+    }
+
+    let registration = binfmt_registration(target, qemu);
+    if current_uid() == 0 {
+        fs::write(Path::new(BINFMT_DIR).join("register"), &registration).map_err(|e| {
+            format!(
+                "Failed to register the binfmt_misc handler for {}: {e}",
+                target.alpine
+            )
+        })?;
+        return Ok(());
+    }
+
+    Err(format!(
+        "bwrap needs a binfmt_misc handler to run {} binaries.\nRegister it once as root:\n    echo '{registration}' > {BINFMT_DIR}/register\nor use proot with '{} config --use-proot'.",
+        target.alpine,
+        app_name()
+    )
+    .into())
+}
+
+/// Builds the binfmt_misc registration line of an architecture.
+///
+/// The magic matches the ELF identification, executable or shared object
+/// type and machine; the mask ignores the OS ABI byte.
+fn binfmt_registration(target: &ElfArch, qemu: &Path) -> String {
+    let word = |v: u16| {
+        if target.big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+
+    let mut magic = vec![0x7f, b'E', b'L', b'F'];
+    magic.push(if target.wide { 2 } else { 1 });
+    magic.push(if target.big_endian { 2 } else { 1 });
+    magic.push(1);
+    magic.extend([0; 9]);
+    magic.extend(word(2));
+    magic.extend(word(target.machine));
+
+    let mut mask = vec![0xff; 7];
+    mask.push(0x00);
+    mask.extend([0xff; 8]);
+    mask.extend(if target.big_endian {
+        [0xff, 0xfe]
+    } else {
+        [0xfe, 0xff]
+    });
+    mask.extend([0xff; 2]);
+
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("\\x{b:02x}")).collect() };
+    format!(
+        ":qemu-{}:M::{}:{}:{}:F",
+        target.qemu,
+        hex(&magic),
+        hex(&mask),
+        qemu.display()
+    )
+}
@@ -4,24 +4,90 @@
 //! override the rootfs path, inject custom bind mounts, and define the
 //! command to be executed within the sandbox.
 
+use crate::envs;
+use crate::gui::{gpu_binds, GuiSession};
+use crate::jobs;
+use crate::qemu;
+use crate::seccomp::SeccompFilter;
 use crate::settings::{
-    settings_overlay_action, settings_overlay_inode_mode, settings_rootfs_dir, settings_use_overlay,
+    settings_bind_cwd, settings_bind_profile, settings_cmd, settings_hardening,
+    settings_overlay_action, settings_overlay_inode_mode, settings_proot, settings_rootfs_dir,
+    settings_run_env, settings_use_overlay,
+};
+use crate::slirp::{PortMapping, SlirpSession};
+use crate::utils::{
+    bind_args, binds_args, check_exit_status, configured_binds, current_uid, map_result,
+    parse_duration, parse_env_var, preserved_env, proot_options, read_env_file, resolve_log_file,
+    ro_bind_args, shell_quote, terminal_env, with_env, with_log_file, with_timeout, TimeoutError,
+    TIMEOUT_EXIT_CODE,
+};
+use crate::wsl;
+use sandbox_utils::{
+    invalid_arg, missing_arg, parse_value, safe_home, OverlayAction, SandBox, SandBoxConfig,
 };
-use crate::utils::map_result;
-use sandbox_utils::{invalid_arg, parse_value, OverlayAction, SandBox, SandBoxConfig};
 use std::collections::VecDeque;
+use std::env;
 use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Capabilities kept by root sessions in secure mode unless `--cap-drop` or
+/// `--cap-add` is given: enough for apk to install files with their owners
+/// and for daemons to switch users.
+const SECURE_CAPS: [&str; 7] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+];
+
+/// bwrap options applied by `--hardened`: the session cannot inject input
+/// into the calling terminal, cannot see or signal host processes, and is
+/// torn down with ALPack. bwrap also sets no-new-privs for every sandbox,
+/// so setuid binaries cannot gain privileges inside it.
+const HARDENED_BWRAP_ARGS: [&str; 3] = ["--new-session", "--die-with-parent", "--unshare-pid"];
+
+/// bwrap options applied by `--read-only`: the rootfs is remounted
+/// read-only before any other bind, and only `/tmp` and `/run` stay
+/// writable, on tmpfs discarded with the session.
+const READ_ONLY_BWRAP_ARGS: [&str; 3] = ["--remount-ro /", "--tmpfs /tmp", "--tmpfs /run"];
+
+/// File of the environment holding the `--hostname` of its last session.
+const HOSTNAME_FILE: &str = "hostname";
+
+/// Shell started by sessions without `--shell`.
+const DEFAULT_SHELL: &str = "/bin/sh";
 
 /// Manager for the `run` subcommand execution.
 pub struct Run {
     /// Arguments captured after the `run` keyword.
     remaining_args: Vec<String>,
+    /// Whether the command is an exact argv (`exec`) rather than a shell command.
+    exact_argv: bool,
 }
 
 impl Run {
     /// Creates a new `Run` instance with the provided arguments.
     pub fn new(remaining_args: Vec<String>) -> Self {
-        Run { remaining_args }
+        Run {
+            remaining_args,
+            exact_argv: false,
+        }
+    }
+
+    /// Creates a `Run` for the `exec` subcommand.
+    ///
+    /// The command arguments are passed to the program as they are, each
+    /// quoted for the sandbox shell, instead of being joined into a shell
+    /// command line.
+    pub fn exec(remaining_args: Vec<String>) -> Self {
+        Run {
+            remaining_args,
+            exact_argv: true,
+        }
     }
 
     /// Orchestrates the parsing of arguments and triggers the command execution.
@@ -38,7 +104,25 @@ impl Run {
 
         let mut cmd_args = Vec::new();
         let mut args_bind = String::new();
+        let (mut proot_args, mut bwrap_args) = (Vec::new(), Vec::new());
         let (mut use_root, mut ignore_extra_bind, mut secure_rootfs) = (false, false, false);
+        let (mut login, mut gui, mut gpu) = (false, false, false);
+        let mut hardened = settings_hardening();
+        let mut proot_opts = settings_proot();
+        let mut timeout = None;
+        let mut shell: Option<String> = None;
+        let mut hostname: Option<String> = None;
+        let mut detach = None;
+        let mut read_only = false;
+        let mut bind_cwd = settings_bind_cwd();
+        let (mut no_home, mut home_dir) = (false, None);
+        let mut log_file = None;
+        let mut user_env = Vec::new();
+        let mut preserved = Vec::new();
+        let mut profiles = Vec::new();
+        let mut seccomp = None;
+        let mut publish = Vec::new();
+        let (mut cap_drop, mut cap_add) = (Vec::new(), Vec::new());
         let mut use_overlay = settings_use_overlay();
         let mut overlay_action = settings_overlay_action();
         let inode_mode = settings_overlay_inode_mode();
@@ -48,6 +132,26 @@ impl Run {
                 "-0" | "--root" => use_root = true,
                 "-i" | "--ignore-extra-binds" => ignore_extra_bind = true,
                 "-s" | "--secure-rootfs" => secure_rootfs = true,
+                "--read-only" => read_only = true,
+                "--no-home" => no_home = true,
+                a if a.starts_with("--home=") => {
+                    home_dir = Some(PathBuf::from(parse_value!("run", "directory", arg)?));
+                }
+                "--home" => {
+                    home_dir =
+                        Some(parse_value!("run", "directory", arg, args.pop_front())?.into());
+                }
+                "--bind-cwd" => bind_cwd = true,
+                "--no-bind-cwd" => bind_cwd = false,
+                "-l" | "--login" => login = true,
+                "-d" | "--detach" => detach = Some(arg),
+                "--gui" => gui = true,
+                "--gpu" => gpu = true,
+                "--hardened" => hardened = true,
+                "--no-hardened" => hardened = false,
+                "--kill-on-exit" => proot_opts.kill_on_exit = true,
+                "--sysvipc" => proot_opts.sysvipc = true,
+                "-S" | "--su-bindings" => proot_opts.su_bindings = true,
                 "-e" | "--ephemeral" => {
                     use_overlay = true;
                     overlay_action = OverlayAction::Discard;
@@ -58,12 +162,115 @@ impl Run {
                 "-b" | "--bind-args" => {
                     args_bind = parse_value!("run", "parameters", arg, args.pop_front())?;
                 }
+                a if a.starts_with("--proot-arg=") => {
+                    proot_args.push(Self::backend_arg(parse_value!("run", "argument", arg)?)?);
+                }
+                "--proot-arg" => {
+                    let value = parse_value!("run", "argument", arg, args.pop_front())?;
+                    proot_args.push(Self::backend_arg(value)?);
+                }
+                a if a.starts_with("--bwrap-arg=") => {
+                    bwrap_args.push(Self::backend_arg(parse_value!("run", "argument", arg)?)?);
+                }
+                "--bwrap-arg" => {
+                    let value = parse_value!("run", "argument", arg, args.pop_front())?;
+                    bwrap_args.push(Self::backend_arg(value)?);
+                }
+                a if a.starts_with("--publish=") => {
+                    publish.push(PortMapping::parse(&parse_value!("run", "ports", arg)?)?);
+                }
+                "-p" | "--publish" => {
+                    let spec = parse_value!("run", "ports", arg, args.pop_front())?;
+                    publish.push(PortMapping::parse(&spec)?);
+                }
+                a if a.starts_with("--seccomp=") => {
+                    seccomp = Some(parse_value!("run", "profile", arg)?);
+                }
+                "--seccomp" => {
+                    seccomp = Some(parse_value!("run", "profile", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--cap-drop=") => {
+                    cap_drop.push(Self::capability(&parse_value!("run", "capability", arg)?)?);
+                }
+                "--cap-drop" => {
+                    let cap = parse_value!("run", "capability", arg, args.pop_front())?;
+                    cap_drop.push(Self::capability(&cap)?);
+                }
+                a if a.starts_with("--cap-add=") => {
+                    cap_add.push(Self::capability(&parse_value!("run", "capability", arg)?)?);
+                }
+                "--cap-add" => {
+                    let cap = parse_value!("run", "capability", arg, args.pop_front())?;
+                    cap_add.push(Self::capability(&cap)?);
+                }
+                a if a.starts_with("--timeout=") => {
+                    let time = parse_value!("run", "duration", arg)?;
+                    timeout =
+                        Some(parse_duration(&time).ok_or(format!("Invalid duration: {time}"))?);
+                }
+                "-t" | "--timeout" => {
+                    let time = parse_value!("run", "duration", arg, args.pop_front())?;
+                    timeout =
+                        Some(parse_duration(&time).ok_or(format!("Invalid duration: {time}"))?);
+                }
+                a if a.starts_with("--bind-profile=") => {
+                    profiles.push(settings_bind_profile(&parse_value!(
+                        "run", "profile", arg
+                    )?)?);
+                }
+                "--bind-profile" => {
+                    let name = parse_value!("run", "profile", arg, args.pop_front())?;
+                    profiles.push(settings_bind_profile(&name)?);
+                }
+                a if a.starts_with("--env=") => {
+                    user_env.extend(parse_env_var(&parse_value!("run", "variable", arg)?));
+                }
+                "--env" => {
+                    let var = parse_value!("run", "variable", arg, args.pop_front())?;
+                    user_env.extend(parse_env_var(&var));
+                }
+                a if a.starts_with("--preserve-env=") => {
+                    preserved.extend(preserved_env(Some(&parse_value!("run", "variables", arg)?)));
+                }
+                "--preserve-env" => preserved.extend(preserved_env(None)),
+                a if a.starts_with("--env-file=") => {
+                    let file = parse_value!("run", "file", arg)?;
+                    user_env.extend(read_env_file(file.as_ref())?);
+                }
+                "--env-file" => {
+                    let file = parse_value!("run", "file", arg, args.pop_front())?;
+                    user_env.extend(read_env_file(file.as_ref())?);
+                }
+                a if a.starts_with("--hostname=") => {
+                    hostname = Some(parse_value!("run", "name", arg)?);
+                }
+                "--hostname" => {
+                    hostname = Some(parse_value!("run", "name", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--shell=") => {
+                    shell = Some(parse_value!("run", "shell", arg)?);
+                }
+                "--shell" => {
+                    shell = Some(parse_value!("run", "shell", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--log-file=") => {
+                    log_file = Some(parse_value!("run", "file", arg)?.into());
+                }
+                "--log-file" => {
+                    log_file = Some(parse_value!("run", "file", arg, args.pop_front())?.into());
+                }
                 a if a.starts_with("--command=") => {
                     cmd_args.push(parse_value!("run", "command", arg)?);
                 }
                 "-c" | "--command" => {
                     cmd_args.push(parse_value!("run", "command", arg, args.pop_front())?);
                 }
+                a if a.starts_with("--name=") => {
+                    rootfs = envs::lookup(&parse_value!("run", "name", arg)?)?;
+                }
+                "--name" => {
+                    rootfs = envs::lookup(&parse_value!("run", "name", arg, args.pop_front())?)?;
+                }
                 a if a.starts_with("--rootfs=") => {
                     rootfs = parse_value!("run", "directory", arg)?.into();
                 }
@@ -83,12 +290,212 @@ impl Run {
             }
         }
 
+        if self.exact_argv && cmd_args.is_empty() {
+            return missing_arg!("exec", essential);
+        }
+
+        if let Some(flag) = detach {
+            return jobs::detach(&rootfs, flag, &cmd_args);
+        }
+
+        if let Some(shell) = &shell {
+            let path = rootfs.join("rootfs").join(shell.trim_start_matches('/'));
+            if !shell.starts_with('/') || path.symlink_metadata().is_err() {
+                return Err(format!("Shell '{shell}' not found in the rootfs").into());
+            }
+        }
+
+        let explicit_caps = !cap_drop.is_empty() || !cap_add.is_empty();
+        if !explicit_caps && secure_rootfs && use_root {
+            cap_drop.push("ALL".to_string());
+            cap_add.extend(SECURE_CAPS.map(String::from));
+        }
+
+        if hardened && cap_drop.is_empty() && cap_add.is_empty() {
+            cap_drop.push("ALL".to_string());
+            if use_root {
+                cap_add.extend(SECURE_CAPS.map(String::from));
+            }
+        }
+
+        if seccomp.is_some() && settings_cmd() != "bwrap" {
+            return Err("--seccomp requires the bwrap backend (config --use-bwrap)".into());
+        }
+        let seccomp_filter = seccomp.as_deref().map(SeccompFilter::load).transpose()?;
+
+        if no_home && home_dir.is_some() {
+            return Err("--no-home and --home cannot be used together".into());
+        }
+        if no_home && settings_cmd() != "bwrap" {
+            return Err("--no-home requires the bwrap backend (config --use-bwrap)".into());
+        }
+
+        if read_only && settings_cmd() != "bwrap" {
+            return Err("--read-only requires the bwrap backend (config --use-bwrap)".into());
+        }
+
+        if !publish.is_empty() && settings_cmd() != "bwrap" {
+            return Err("--publish requires the bwrap backend (config --use-bwrap)".into());
+        }
+        let slirp = Some(publish)
+            .filter(|ports| !ports.is_empty())
+            .map(SlirpSession::start)
+            .transpose()?;
+
+        let backend_args: Vec<String> = if settings_cmd() == "bwrap" {
+            let caps = cap_drop
+                .iter()
+                .map(|cap| format!("--cap-drop {cap}"))
+                .chain(cap_add.iter().map(|cap| format!("--cap-add {cap}")));
+            let hardening = HARDENED_BWRAP_ARGS.iter().filter(|_| hardened);
+            let read_only = READ_ONLY_BWRAP_ARGS.iter().filter(|_| read_only);
+            read_only
+                .chain(hardening)
+                .map(|a| a.to_string())
+                .chain(caps)
+                .chain(bwrap_args)
+                .chain(seccomp_filter.iter().map(SeccompFilter::bwrap_args))
+                .chain(slirp.iter().map(|s| s.bwrap_args.clone()))
+                .collect()
+        } else {
+            if explicit_caps {
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: proot does not support capabilities, ignoring --cap-drop/--cap-add."
+                );
+            }
+            // --kill-on-exit is the closest proot equivalent of the bundle.
+            proot_opts.kill_on_exit |= hardened;
+            let options = proot_options(&proot_opts, &rootfs);
+            let options = Some(options).filter(|o| !o.is_empty());
+            options.into_iter().chain(proot_args).collect()
+        };
+
+        let emulation = qemu::emulation_args(&rootfs)?;
+        let backend_args = backend_args
+            .into_iter()
+            .chain(Some(emulation).filter(|e| !e.is_empty()));
+
+        let hostname_binds = match &hostname {
+            Some(name) => Self::hostname_args(&rootfs, name)?,
+            None => Vec::new(),
+        };
+        let backend_args = backend_args.chain(hostname_binds);
+
+        let home = PathBuf::from(
+            env::var("HOME").unwrap_or_else(|_| safe_home().to_string_lossy().into_owned()),
+        );
+        let home_bind = match &home_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir)?;
+                Some(bind_args(&fs::canonicalize(dir)?, &home))
+            }
+            None if no_home => Some(format!("--tmpfs {}", home.display())),
+            None => None,
+        };
+        let backend_args = backend_args.chain(home_bind);
+
+        let cwd = if bind_cwd { Self::bindable_cwd() } else { None };
+        let cwd_bind = cwd.as_deref().map(|dir| bind_args(dir, dir));
+        let backend_args = backend_args.chain(cwd_bind);
+
+        // The agent socket is only reachable if it is bound into the session.
+        let agent_bind = preserved
+            .iter()
+            .filter(|(key, _)| key == "SSH_AUTH_SOCK")
+            .map(|(_, sock)| Path::new(sock))
+            .filter(|sock| sock.exists())
+            .map(|sock| bind_args(sock, sock));
+        let backend_args = backend_args.chain(agent_bind.collect::<Vec<_>>());
+
+        let gui_session = gui.then(GuiSession::new).transpose()?;
+        let gui_binds = gui_session.iter().flat_map(|g| g.binds.iter().cloned());
+        let gpu_binds = if gpu { gpu_binds(&rootfs)? } else { Vec::new() };
+        let backend_args = backend_args.chain(gui_binds).chain(gpu_binds);
+        ignore_extra_bind |= profiles.iter().any(|p| p.ignore_extra_binds);
+        let extra_binds = Some(configured_binds()).filter(|_| !ignore_extra_bind);
+        let wsl_binds = wsl::default_binds().filter(|_| !ignore_extra_bind);
+        ignore_extra_bind |= wsl_binds.is_some();
+        let profile_binds = profiles.iter().map(|p| binds_args(&p.binds));
+        let backend_args = backend_args
+            .chain(extra_binds)
+            .chain(wsl_binds)
+            .chain(profile_binds)
+            .filter(|b| !b.is_empty());
+
+        for backend_arg in backend_args {
+            if !args_bind.is_empty() {
+                args_bind.push(' ');
+            }
+            args_bind.push_str(&backend_arg);
+        }
+
         let run_cmd = if cmd_args.is_empty() {
             String::new()
+        } else if self.exact_argv {
+            let argv: Vec<String> = cmd_args.iter().map(|arg| shell_quote(arg)).collect();
+            format!("exec {}", argv.join(" "))
         } else {
             cmd_args.join(" ")
         };
 
+        let mut env = terminal_env();
+        env.extend(gui_session.iter().flat_map(|g| g.env.iter().cloned()));
+        env.extend(hostname.map(|name| ("HOSTNAME".to_string(), name)));
+        let sh = shell_quote(shell.as_deref().unwrap_or(DEFAULT_SHELL));
+        let run_cmd = if login || shell.is_some() {
+            if login {
+                env.extend(Self::login_env(use_root));
+            }
+            let flag = if login { " -l" } else { "" };
+            if run_cmd.is_empty() {
+                format!("exec {sh}{flag}")
+            } else {
+                format!("exec {sh}{flag} -c {}", shell_quote(&run_cmd))
+            }
+        } else {
+            run_cmd
+        };
+        let run_cmd = match &cwd {
+            Some(dir) => {
+                let cmd = if run_cmd.is_empty() {
+                    format!("exec {sh}")
+                } else {
+                    run_cmd
+                };
+                format!(
+                    "cd {} || exit 1\n{cmd}",
+                    shell_quote(&dir.to_string_lossy())
+                )
+            }
+            None => run_cmd,
+        };
+        env.extend(settings_run_env());
+        for profile in &profiles {
+            env.extend(profile.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        env.extend(preserved);
+        env.extend(user_env);
+        let mut run_cmd = with_env(&env, run_cmd)?;
+
+        if let Some(secs) = timeout {
+            if run_cmd.is_empty() {
+                run_cmd = format!("exec {sh}");
+            }
+            run_cmd = with_timeout(&run_cmd, secs);
+        }
+
+        if let Some(log_file) = resolve_log_file(log_file, "run")? {
+            if run_cmd.is_empty() {
+                run_cmd = format!("exec {sh}");
+            }
+            let (script, bind) = with_log_file(&run_cmd, &log_file);
+            run_cmd = script;
+            if !args_bind.is_empty() {
+                args_bind.push(' ');
+            }
+            args_bind.push_str(&bind);
+        }
+
         let config = SandBoxConfig {
             rootfs,
             run_cmd,
@@ -102,7 +509,149 @@ impl Run {
             ..Default::default()
         };
 
-        map_result(SandBox::run(config))?;
-        Ok(())
+        let code = map_result(SandBox::run(config))?;
+
+        // The timeout wrapper exits with this code only when it killed the command.
+        if let Some(secs) = timeout.filter(|_| code == TIMEOUT_EXIT_CODE) {
+            return Err(TimeoutError(secs).into());
+        }
+
+        check_exit_status(code)
+    }
+
+    /// Checks an argument given with `--proot-arg` or `--bwrap-arg`.
+    ///
+    /// Backend arguments are passed to the sandbox as one string split on
+    /// whitespace, so a value containing spaces would reach the backend as
+    /// several arguments and is rejected instead.
+    ///
+    /// # Parameters
+    /// - `value`: The argument given on the command line.
+    ///
+    /// # Returns
+    /// - `Ok(String)` with the unchanged argument.
+    /// - `Err` if the argument is empty or contains whitespace.
+    fn backend_arg(value: String) -> Result<String, Box<dyn Error>> {
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            return Err(format!(
+                "Invalid backend argument '{value}': it must be a single word, repeat the option for each argument"
+            )
+            .into());
+        }
+        Ok(value)
+    }
+
+    /// Normalizes a capability name for bwrap.
+    ///
+    /// Names are accepted in any case, with or without the `CAP_` prefix,
+    /// and `ALL` selects every capability.
+    ///
+    /// # Parameters
+    /// - `name`: The capability given on the command line.
+    ///
+    /// # Returns
+    /// - `Ok(String)` with the canonical name, e.g. `CAP_NET_BIND_SERVICE`.
+    /// - `Err` if the name contains invalid characters.
+    fn capability(name: &str) -> Result<String, Box<dyn Error>> {
+        let name = name.to_uppercase();
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid capability '{name}'").into());
+        }
+
+        if name == "ALL" || name.starts_with("CAP_") {
+            Ok(name)
+        } else {
+            Ok(format!("CAP_{name}"))
+        }
+    }
+
+    /// Returns the backend arguments giving the session its own host name.
+    ///
+    /// The name is written to a `hostname` file of the environment, bound
+    /// over `/etc/hostname`. With bwrap the session also gets its own UTS
+    /// namespace, so `uname -n` reports the name too; proot cannot change
+    /// the kernel host name.
+    ///
+    /// # Parameters
+    /// - `rootfs`: The environment directory containing `rootfs`.
+    /// - `name`: The host name of the session.
+    ///
+    /// # Returns
+    /// - `Ok(Vec<String>)` with the backend arguments.
+    /// - `Err` if the name is not a valid host name or the file cannot be written.
+    fn hostname_args(rootfs: &Path, name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let valid = !name.is_empty()
+            && name.len() <= 64
+            && !name.starts_with(['-', '.'])
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !valid {
+            return Err(format!("Invalid hostname: {name}").into());
+        }
+
+        let file = rootfs.join(HOSTNAME_FILE);
+        fs::write(&file, format!("{name}\n"))?;
+
+        let mut args = Vec::new();
+        if settings_cmd() == "bwrap" {
+            args.push(format!("--unshare-uts --hostname {name}"));
+        }
+        args.push(ro_bind_args(&file, Path::new("/etc/hostname")));
+        Ok(args)
+    }
+
+    /// Returns the current directory if it can be bound into the session.
+    ///
+    /// The root directory is never bound over the rootfs, and neither is a
+    /// path containing whitespace, as bind arguments are split on it.
+    fn bindable_cwd() -> Option<PathBuf> {
+        match env::current_dir() {
+            Ok(dir) if dir == Path::new("/") => {
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: The current directory is /, it is not bound into the session."
+                );
+                None
+            }
+            Ok(dir) if dir.to_string_lossy().contains(char::is_whitespace) => {
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: The current directory {} contains whitespace, it is not bound into the session.",
+                    dir.display()
+                );
+                None
+            }
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                eprintln!("\x1b[1;33mWarning\x1b[0m: Cannot bind the current directory: {e}");
+                None
+            }
+        }
+    }
+
+    /// Returns the identity variables expected by a login shell.
+    ///
+    /// Root sessions, and every session started by the host root, use
+    /// Alpine's `root` account; otherwise the host user name and home
+    /// directory are kept, as they are mapped into the rootfs. Without
+    /// `USER`, the numeric effective user ID is used as name.
+    ///
+    /// # Parameters
+    /// - `use_root`: Whether the session runs as root inside the rootfs.
+    fn login_env(use_root: bool) -> Vec<(String, String)> {
+        let (user, home) = if use_root || current_uid() == 0 {
+            ("root".to_string(), "/root".to_string())
+        } else {
+            (
+                env::var("USER").unwrap_or_else(|_| current_uid().to_string()),
+                env::var("HOME").unwrap_or_else(|_| safe_home().to_string_lossy().into_owned()),
+            )
+        };
+
+        vec![
+            ("HOME".to_string(), home),
+            ("USER".to_string(), user.clone()),
+            ("LOGNAME".to_string(), user),
+        ]
     }
 }
@@ -0,0 +1,222 @@
+//! Package search module.
+//!
+//! By default `search` is forwarded to `apk search` inside the rootfs.
+//! With `--regex`, `--glob` or `--interactive`, results are read from the
+//! cached APKINDEX on the host instead, matching both package names and
+//! descriptions. Interactive mode presents them as a numbered list from
+//! which packages can be picked and installed in one go.
+
+use crate::apk::Apk;
+use crate::index::{load_index, IndexEntry};
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{check_rootfs_exists, is_json};
+use regex::Regex;
+use sandbox_utils::{get_cmd_box, invalid_arg, missing_arg, parse_value, SEPARATOR};
+use std::collections::{BTreeSet, VecDeque};
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Controller for the `search` subcommand.
+pub struct Search {
+    /// Arguments captured after the `search` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Search {
+    /// Creates a new `Search` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Search { remaining_args }
+    }
+
+    /// Parses search options and runs either `apk search` or the picker.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the search (and optional installation) succeeds.
+    /// - `Err` if arguments are invalid, nothing matches, or `apk` fails.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = None;
+        let mut interactive = false;
+        let mut terms = Vec::new();
+        let mut patterns = Vec::new();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                "-i" | "--interactive" => interactive = true,
+                a if a.starts_with("--regex=") => {
+                    patterns.push(Regex::new(&parse_value!("search", "pattern", arg)?)?);
+                }
+                "--regex" => {
+                    let pattern = parse_value!("search", "pattern", arg, args.pop_front())?;
+                    patterns.push(Regex::new(&pattern)?);
+                }
+                a if a.starts_with("--glob=") => {
+                    patterns.push(Self::glob_to_regex(&parse_value!(
+                        "search", "pattern", arg
+                    )?)?);
+                }
+                "--glob" => {
+                    let pattern = parse_value!("search", "pattern", arg, args.pop_front())?;
+                    patterns.push(Self::glob_to_regex(&pattern)?);
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = Some(parse_value!("search", "directory", arg)?.into());
+                }
+                "-R" | "--rootfs" => {
+                    rootfs =
+                        Some(parse_value!("search", "directory", arg, args.pop_front())?.into());
+                }
+                _ => terms.push(arg.to_string()),
+            }
+        }
+
+        if !interactive && patterns.is_empty() {
+            return Apk::new(Some("search".to_string()), terms, rootfs).run();
+        }
+
+        if terms.is_empty() && patterns.is_empty() {
+            return missing_arg!("search");
+        }
+
+        if interactive && (!io::stdin().is_terminal() || !io::stdout().is_terminal()) {
+            return Err("Interactive search requires a terminal.".into());
+        }
+
+        let root_dir = rootfs.clone().unwrap_or_else(settings_rootfs_dir);
+        check_rootfs_exists(root_dir.clone())?;
+
+        let terms: Vec<String> = terms.iter().map(|t| t.to_lowercase()).collect();
+        let results: Vec<IndexEntry> = load_index(&root_dir.join("rootfs"))?
+            .into_iter()
+            .filter(|e| {
+                let name = e.name.to_lowercase();
+                let desc = e.description.to_lowercase();
+                terms.iter().any(|t| name.contains(t) || desc.contains(t))
+                    || patterns
+                        .iter()
+                        .any(|re| re.is_match(&e.name) || re.is_match(&e.description))
+            })
+            .collect();
+
+        if results.is_empty() {
+            return Err(format!("{u}\nResult not found!\n{u}", u = SEPARATOR).into());
+        }
+
+        if is_json() && !interactive {
+            println!("{}", serde_json::to_string_pretty(&results)?);
+            return Ok(());
+        }
+
+        let width = results.iter().map(|e| e.name.len()).max().unwrap_or(0);
+
+        if !interactive {
+            let lines: Vec<String> = results
+                .iter()
+                .map(|e| format!("{:<width$}  {:<16}  {}", e.name, e.version, e.description))
+                .collect();
+
+            println!(
+                "{u}\n{}\n{}\n{u}",
+                get_cmd_box("SEARCH RESULT:", None, Some(18))?,
+                lines.join("\n"),
+                u = SEPARATOR
+            );
+            return Ok(());
+        }
+
+        for (i, e) in results.iter().enumerate() {
+            println!(
+                "{:>4}) {:<width$}  {:<16}  {}",
+                i + 1,
+                e.name,
+                e.version,
+                e.description
+            );
+        }
+
+        print!("\nSelect packages to install (e.g., 1 3 5-7), or press Enter to cancel: ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+
+        let picked = Self::parse_selection(&line, results.len())?;
+        if picked.is_empty() {
+            println!("Nothing selected.");
+            return Ok(());
+        }
+
+        let pkgs = picked
+            .into_iter()
+            .map(|i| results[i].name.clone())
+            .collect();
+        Apk::new(Some("add".to_string()), pkgs, rootfs).run()
+    }
+
+    /// Converts a shell-style glob into an anchored regular expression.
+    ///
+    /// Supports `*`, `?` and bracket expressions such as `[a-z]`; every
+    /// other character is matched literally.
+    ///
+    /// # Parameters
+    /// - `glob`: The glob pattern (e.g., `py3-*qt*`).
+    ///
+    /// # Returns
+    /// - `Ok(Regex)` matching whole names or descriptions.
+    /// - `Err` if the resulting expression is invalid.
+    fn glob_to_regex(glob: &str) -> Result<Regex, Box<dyn Error>> {
+        let mut pattern = String::from("^");
+        let mut in_class = false;
+
+        for c in glob.chars() {
+            match c {
+                '[' if !in_class => {
+                    in_class = true;
+                    pattern.push('[');
+                }
+                ']' if in_class => {
+                    in_class = false;
+                    pattern.push(']');
+                }
+                '!' if in_class && pattern.ends_with('[') => pattern.push('^'),
+                _ if in_class => pattern.push(c),
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                _ => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+
+        pattern.push('$');
+        Ok(Regex::new(&pattern)?)
+    }
+
+    /// Parses a selection such as `1 3,5-7` into zero-based indices.
+    ///
+    /// # Parameters
+    /// - `input`: The line typed by the user.
+    /// - `len`: The number of listed results.
+    ///
+    /// # Returns
+    /// - `Ok(BTreeSet<usize>)` with the selected indices.
+    /// - `Err` if a token is not a number or range within the list.
+    fn parse_selection(input: &str, len: usize) -> Result<BTreeSet<usize>, Box<dyn Error>> {
+        let mut picked = BTreeSet::new();
+
+        for token in input.split(|c: char| c.is_whitespace() || c == ',') {
+            if token.is_empty() {
+                continue;
+            }
+
+            let (start, end) = match token.split_once('-') {
+                Some((a, b)) => (a.parse::<usize>(), b.parse::<usize>()),
+                None => (token.parse::<usize>(), token.parse::<usize>()),
+            };
+
+            match (start, end) {
+                (Ok(a), Ok(b)) if a >= 1 && a <= b && b <= len => picked.extend(a - 1..b),
+                _ => return invalid_arg!("search", token),
+            }
+        }
+        Ok(picked)
+    }
+}
@@ -0,0 +1,409 @@
+//! Seccomp filters for bwrap sessions.
+//!
+//! `run --seccomp <strict|FILE>` loads a filter and hands it to bwrap with
+//! `--seccomp FD`. A `.bpf` file is used as it is (a compiled filter, e.g.
+//! exported with libseccomp); a `.json` profile and the bundled `strict`
+//! profile are compiled here. Profiles are deny lists: every syscall is
+//! allowed except the ones named, which fail with an errno or kill the
+//! process. Syscall numbers are known for x86_64 and aarch64 hosts.
+
+use crate::utils::set_inheritable;
+use serde::Deserialize;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+
+/// Syscalls denied by the bundled `strict` profile: kernel modules and
+/// keyrings, mounts and namespaces, tracing, clock and power management.
+const STRICT_SYSCALLS: [&str; 44] = [
+    "acct",
+    "add_key",
+    "bpf",
+    "chroot",
+    "clock_adjtime",
+    "clock_settime",
+    "delete_module",
+    "finit_module",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "fspick",
+    "init_module",
+    "io_uring_enter",
+    "io_uring_register",
+    "io_uring_setup",
+    "ioperm",
+    "iopl",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "mount",
+    "mount_setattr",
+    "move_mount",
+    "name_to_handle_at",
+    "open_by_handle_at",
+    "open_tree",
+    "perf_event_open",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "setns",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "syslog",
+    "umount2",
+    "unshare",
+    "userfaultfd",
+];
+
+/// Syscall numbers on x86_64.
+const X86_64_SYSCALLS: [(&str, u32); 44] = [
+    ("acct", 163),
+    ("add_key", 248),
+    ("bpf", 321),
+    ("chroot", 161),
+    ("clock_adjtime", 305),
+    ("clock_settime", 227),
+    ("delete_module", 176),
+    ("finit_module", 313),
+    ("fsconfig", 431),
+    ("fsmount", 432),
+    ("fsopen", 430),
+    ("fspick", 433),
+    ("init_module", 175),
+    ("io_uring_enter", 426),
+    ("io_uring_register", 427),
+    ("io_uring_setup", 425),
+    ("ioperm", 173),
+    ("iopl", 172),
+    ("kexec_file_load", 320),
+    ("kexec_load", 246),
+    ("keyctl", 250),
+    ("lookup_dcookie", 212),
+    ("mount", 165),
+    ("mount_setattr", 442),
+    ("move_mount", 429),
+    ("name_to_handle_at", 303),
+    ("open_by_handle_at", 304),
+    ("open_tree", 428),
+    ("perf_event_open", 298),
+    ("pivot_root", 155),
+    ("process_vm_readv", 310),
+    ("process_vm_writev", 311),
+    ("ptrace", 101),
+    ("quotactl", 179),
+    ("reboot", 169),
+    ("request_key", 249),
+    ("setns", 308),
+    ("settimeofday", 164),
+    ("swapoff", 168),
+    ("swapon", 167),
+    ("syslog", 103),
+    ("umount2", 166),
+    ("unshare", 272),
+    ("userfaultfd", 323),
+];
+
+/// Syscall numbers on aarch64 (which has no `iopl` and `ioperm`).
+const AARCH64_SYSCALLS: [(&str, u32); 42] = [
+    ("acct", 89),
+    ("add_key", 217),
+    ("bpf", 280),
+    ("chroot", 51),
+    ("clock_adjtime", 266),
+    ("clock_settime", 112),
+    ("delete_module", 106),
+    ("finit_module", 273),
+    ("fsconfig", 431),
+    ("fsmount", 432),
+    ("fsopen", 430),
+    ("fspick", 433),
+    ("init_module", 105),
+    ("io_uring_enter", 426),
+    ("io_uring_register", 427),
+    ("io_uring_setup", 425),
+    ("kexec_file_load", 294),
+    ("kexec_load", 104),
+    ("keyctl", 219),
+    ("lookup_dcookie", 18),
+    ("mount", 40),
+    ("mount_setattr", 442),
+    ("move_mount", 429),
+    ("name_to_handle_at", 264),
+    ("open_by_handle_at", 265),
+    ("open_tree", 428),
+    ("perf_event_open", 241),
+    ("pivot_root", 41),
+    ("process_vm_readv", 270),
+    ("process_vm_writev", 271),
+    ("ptrace", 117),
+    ("quotactl", 60),
+    ("reboot", 142),
+    ("request_key", 218),
+    ("setns", 268),
+    ("settimeofday", 170),
+    ("swapoff", 225),
+    ("swapon", 224),
+    ("syslog", 116),
+    ("umount2", 39),
+    ("unshare", 97),
+    ("userfaultfd", 282),
+];
+
+/// Syscall names with their number on one architecture.
+type SyscallTable = &'static [(&'static str, u32)];
+
+/// `AUDIT_ARCH_X86_64`, as reported in `seccomp_data.arch`.
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+
+/// `AUDIT_ARCH_AARCH64`, as reported in `seccomp_data.arch`.
+const AUDIT_ARCH_AARCH64: u32 = 0xc000_00b7;
+
+/// Bit set in the syscall numbers of the x32 ABI.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Filter return value running the syscall.
+const RET_ALLOW: u32 = 0x7fff_0000;
+
+/// Filter return value failing the syscall, or'ed with the errno.
+const RET_ERRNO: u32 = 0x0005_0000;
+
+/// Filter return value killing the whole process.
+const RET_KILL_PROCESS: u32 = 0x8000_0000;
+
+/// Filter return value killing the calling thread.
+const RET_KILL_THREAD: u32 = 0x0000_0000;
+
+/// Classic BPF opcode loading a 32-bit word of `seccomp_data`.
+const BPF_LD_W_ABS: u16 = 0x20;
+
+/// Classic BPF opcode jumping if the accumulator equals a constant.
+const BPF_JEQ_K: u16 = 0x15;
+
+/// Classic BPF opcode jumping if the accumulator is at least a constant.
+const BPF_JGE_K: u16 = 0x35;
+
+/// Classic BPF opcode returning a constant.
+const BPF_RET_K: u16 = 0x06;
+
+/// `EPERM`, returned by denied syscalls unless the profile sets another errno.
+const EPERM: u32 = 1;
+
+/// A seccomp profile in the JSON format used by Docker and Podman.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    /// Action for syscalls not listed; only `SCMP_ACT_ALLOW` is supported.
+    default_action: String,
+    /// Rules for the listed syscalls.
+    #[serde(default)]
+    syscalls: Vec<Rule>,
+}
+
+/// A rule of a JSON seccomp profile.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Rule {
+    /// Syscall names.
+    #[serde(default)]
+    names: Vec<String>,
+    /// Single syscall name, used by older profiles.
+    #[serde(default)]
+    name: Option<String>,
+    /// `SCMP_ACT_ALLOW`, `SCMP_ACT_ERRNO` or `SCMP_ACT_KILL[_PROCESS|_THREAD]`.
+    action: String,
+    /// Errno returned with `SCMP_ACT_ERRNO`.
+    #[serde(default)]
+    errno_ret: Option<u32>,
+    /// Argument conditions, which are not supported.
+    #[serde(default)]
+    args: Vec<serde_json::Value>,
+}
+
+/// A loaded seccomp filter.
+///
+/// The file descriptor is inherited by bwrap, so the filter must be kept
+/// alive until the session ends.
+pub struct SeccompFilter {
+    /// The compiled filter, opened without close-on-exec.
+    file: File,
+}
+
+impl SeccompFilter {
+    /// Loads the filter selected with `--seccomp`.
+    ///
+    /// # Parameters
+    /// - `spec`: `strict`, a `.json` profile or a compiled `.bpf` filter.
+    ///
+    /// # Returns
+    /// - `Ok(SeccompFilter)` ready to be passed to bwrap.
+    /// - `Err` if the profile is invalid or cannot be compiled for this host.
+    pub fn load(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let file = if spec == "strict" {
+            let (_, table) = syscall_table()?;
+            let denied: Vec<(String, u32)> = STRICT_SYSCALLS
+                .iter()
+                .filter(|name| table.iter().any(|(known, _)| known == *name))
+                .map(|name| (name.to_string(), RET_ERRNO | EPERM))
+                .collect();
+            Self::compiled(&compile(&denied)?)?
+        } else if spec.ends_with(".json") {
+            let content = fs::read_to_string(spec)
+                .map_err(|e| format!("Cannot read seccomp profile '{spec}': {e}"))?;
+            let profile: Profile = serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid seccomp profile '{spec}': {e}"))?;
+            Self::compiled(&compile(&profile.denied()?)?)?
+        } else {
+            let len = fs::metadata(spec)
+                .map_err(|e| format!("Cannot read seccomp filter '{spec}': {e}"))?
+                .len();
+            if len == 0 || len % 8 != 0 {
+                return Err(format!("'{spec}' is not a compiled seccomp BPF filter").into());
+            }
+            File::open(spec)?
+        };
+
+        set_inheritable(&file)
+            .map_err(|e| format!("Cannot pass the seccomp filter to bwrap: {e}"))?;
+        Ok(SeccompFilter { file })
+    }
+
+    /// Writes a compiled filter to a temporary file, unlinked once opened.
+    fn compiled(program: &[u8]) -> Result<File, Box<dyn Error>> {
+        let path = env::temp_dir().join(format!("alpack-seccomp-{}.bpf", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        file.write_all(program)?;
+        drop(file);
+
+        let file = File::open(&path);
+        let _ = fs::remove_file(&path);
+        Ok(file?)
+    }
+
+    /// Returns the bwrap arguments loading the filter.
+    pub fn bwrap_args(&self) -> String {
+        format!("--seccomp {}", self.file.as_raw_fd())
+    }
+}
+
+impl Profile {
+    /// Returns the denied syscalls with their filter return value.
+    ///
+    /// # Returns
+    /// - `Ok(Vec<(String, u32)>)` with every denied syscall.
+    /// - `Err` if the profile is not a deny list or uses argument conditions.
+    fn denied(&self) -> Result<Vec<(String, u32)>, Box<dyn Error>> {
+        if self.default_action != "SCMP_ACT_ALLOW" {
+            return Err(format!(
+                "Unsupported seccomp defaultAction '{}': only deny lists (SCMP_ACT_ALLOW) can be compiled, pass other profiles as a .bpf filter",
+                self.default_action
+            )
+            .into());
+        }
+
+        let mut denied = Vec::new();
+        for rule in &self.syscalls {
+            if !rule.args.is_empty() {
+                return Err("Seccomp rules with argument conditions are not supported".into());
+            }
+
+            let ret = match rule.action.as_str() {
+                "SCMP_ACT_ALLOW" => continue,
+                "SCMP_ACT_ERRNO" => RET_ERRNO | rule.errno_ret.unwrap_or(EPERM).min(0xffff),
+                "SCMP_ACT_KILL" | "SCMP_ACT_KILL_THREAD" => RET_KILL_THREAD,
+                "SCMP_ACT_KILL_PROCESS" => RET_KILL_PROCESS,
+                other => return Err(format!("Unsupported seccomp action '{other}'").into()),
+            };
+            let names = rule.names.iter().chain(rule.name.iter());
+            denied.extend(names.map(|name| (name.clone(), ret)));
+        }
+        Ok(denied)
+    }
+}
+
+/// Returns the audit architecture and the syscall numbers of this host.
+///
+/// # Returns
+/// - `Ok((u32, SyscallTable))` for x86_64 and aarch64.
+/// - `Err` for other architectures.
+fn syscall_table() -> Result<(u32, SyscallTable), Box<dyn Error>> {
+    match env::consts::ARCH {
+        "x86_64" => Ok((AUDIT_ARCH_X86_64, &X86_64_SYSCALLS)),
+        "aarch64" => Ok((AUDIT_ARCH_AARCH64, &AARCH64_SYSCALLS)),
+        arch => Err(format!(
+            "Seccomp profiles cannot be compiled on {arch}, pass a compiled .bpf filter instead"
+        )
+        .into()),
+    }
+}
+
+/// Compiles a deny list into a classic BPF seccomp program for this host.
+///
+/// Syscalls of a foreign ABI (32-bit or x32 calls) fail with `ENOSYS`, so
+/// they cannot be used to bypass the filter.
+///
+/// # Parameters
+/// - `denied`: Syscall names with their filter return value.
+///
+/// # Returns
+/// - `Ok(Vec<u8>)` with the `sock_filter` array in host byte order.
+/// - `Err` if the host architecture is not supported or a syscall is not
+///   in its table, as skipping it would silently weaken the filter.
+fn compile(denied: &[(String, u32)]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (audit_arch, table) = syscall_table()?;
+    let enosys = RET_ERRNO | libc::ENOSYS as u32;
+
+    let mut program = vec![
+        (BPF_LD_W_ABS, 0, 0, 4),
+        (BPF_JEQ_K, 1, 0, audit_arch),
+        (BPF_RET_K, 0, 0, enosys),
+        (BPF_LD_W_ABS, 0, 0, 0),
+    ];
+    if audit_arch == AUDIT_ARCH_X86_64 {
+        program.push((BPF_JGE_K, 0, 1, X32_SYSCALL_BIT));
+        program.push((BPF_RET_K, 0, 0, enosys));
+    }
+
+    let mut unknown = Vec::new();
+    for (name, ret) in denied {
+        match table.iter().find(|(known, _)| known == name) {
+            Some((_, nr)) => {
+                program.push((BPF_JEQ_K, 0, 1, *nr));
+                program.push((BPF_RET_K, 0, 0, *ret));
+            }
+            None => unknown.push(name.as_str()),
+        }
+    }
+    if !unknown.is_empty() {
+        return Err(format!(
+            "Cannot compile the seccomp profile, unknown syscalls: {}.\nOnly these can be denied from a .json profile: {}.\nPass other filters as a compiled .bpf file.",
+            unknown.join(", "),
+            table.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ")
+        )
+        .into());
+    }
+    program.push((BPF_RET_K, 0, 0, RET_ALLOW));
+
+    Ok(program
+        .into_iter()
+        .flat_map(|(code, jt, jf, k): (u16, u8, u8, u32)| {
+            let mut insn = Vec::with_capacity(8);
+            insn.extend(code.to_ne_bytes());
+            insn.extend([jt, jf]);
+            insn.extend(k.to_ne_bytes());
+            insn
+        })
+        .collect())
+}
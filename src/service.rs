@@ -0,0 +1,187 @@
+//! Host service integration module.
+//!
+//! Generates systemd user units that supervise daemons living in the
+//! rootfs. Each unit simply starts the appropriate `run` invocation, so the
+//! host's systemd handles restarts, logging and startup ordering.
+
+use crate::settings::settings_rootfs_dir;
+use sandbox_utils::{invalid_arg, missing_arg, parse_value};
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// Restart policies accepted by systemd's `Restart=` directive.
+const RESTART_POLICIES: [&str; 7] = [
+    "no",
+    "always",
+    "on-success",
+    "on-failure",
+    "on-abnormal",
+    "on-abort",
+    "on-watchdog",
+];
+
+/// Controller for the `service` subcommand.
+pub struct Service {
+    /// Arguments captured after the `service` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Service {
+    /// Creates a new `Service` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Service { remaining_args }
+    }
+
+    /// Parses the service action and its options and executes it.
+    ///
+    /// Currently supports `install <name> -- <command>`, which writes the
+    /// unit `alpack-<name>.service` to the systemd user configuration.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the unit was written.
+    /// - `Err` if an argument is invalid or the unit cannot be written.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+
+        match args.pop_front() {
+            Some("install") => {}
+            Some(other) => return invalid_arg!("service", other),
+            None => return missing_arg!("service"),
+        }
+
+        let Some(name) = args.pop_front() else {
+            return missing_arg!("service");
+        };
+
+        if name.starts_with('-') || name.contains('/') {
+            return invalid_arg!("service", name);
+        }
+
+        let mut rootfs = settings_rootfs_dir();
+        let mut restart = "on-failure".to_string();
+        let mut env_vars = Vec::new();
+        let mut cmd_args = Vec::new();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--restart=") => {
+                    restart = parse_value!("service", "policy", arg)?;
+                }
+                "--restart" => {
+                    restart = parse_value!("service", "policy", arg, args.pop_front())?;
+                }
+                a if a.starts_with("--env=") => {
+                    env_vars.push(parse_value!("service", "variable", arg)?);
+                }
+                "-e" | "--env" => {
+                    env_vars.push(parse_value!("service", "variable", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("service", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("service", "directory", arg, args.pop_front())?.into();
+                }
+                "--" => {
+                    cmd_args.extend(args.drain(..).map(|s| s.to_string()));
+                    break;
+                }
+                _ => return invalid_arg!("service", arg),
+            }
+        }
+
+        if cmd_args.is_empty() {
+            return missing_arg!("service", essential);
+        }
+
+        if !RESTART_POLICIES.contains(&restart.as_str()) {
+            return invalid_arg!("service", &restart);
+        }
+
+        if let Some(var) = env_vars.iter().find(|v| !v.contains('=')) {
+            return Err(format!("Invalid environment variable '{var}', expected KEY=VALUE").into());
+        }
+
+        let mut exec_start = vec![
+            env::current_exe()?.to_string_lossy().into_owned(),
+            "run".to_string(),
+            "-R".to_string(),
+            fs::canonicalize(&rootfs)
+                .unwrap_or(rootfs)
+                .to_string_lossy()
+                .into_owned(),
+            "--".to_string(),
+        ];
+        exec_start.extend(cmd_args);
+
+        let environment: String = env_vars
+            .iter()
+            .map(|v| format!("Environment={}\n", Self::unit_quote(v)))
+            .collect();
+
+        let unit = format!(
+            "[Unit]\n\
+            Description=ALPack service {name}\n\
+            After=network.target\n\
+            \n\
+            [Service]\n\
+            Type=simple\n\
+            {environment}\
+            ExecStart={exec}\n\
+            Restart={restart}\n\
+            RestartSec=5\n\
+            \n\
+            [Install]\n\
+            WantedBy=default.target\n",
+            exec = exec_start
+                .iter()
+                .map(|a| Self::unit_quote(a))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+
+        let unit_dir = Self::user_unit_dir()?;
+        fs::create_dir_all(&unit_dir)?;
+
+        let unit_path = unit_dir.join(format!("alpack-{name}.service"));
+        fs::write(&unit_path, unit)?;
+
+        println!(
+            "Service unit written to {}\nEnable it with:\n  systemctl --user daemon-reload\n  systemctl --user enable --now alpack-{name}",
+            unit_path.display()
+        );
+        Ok(())
+    }
+
+    /// Returns the systemd user unit directory.
+    ///
+    /// Honors `XDG_CONFIG_HOME` and falls back to `~/.config`.
+    fn user_unit_dir() -> Result<PathBuf, Box<dyn Error>> {
+        let config_home = match env::var("XDG_CONFIG_HOME") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var("HOME")?).join(".config"),
+        };
+        Ok(config_home.join("systemd/user"))
+    }
+
+    /// Quotes a value for a systemd unit file.
+    ///
+    /// Specifiers (`%`) and variable expansions (`$`) are escaped so the
+    /// value reaches the command literally.
+    fn unit_quote(value: &str) -> String {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+            .replace('$', "$$");
+
+        if escaped.is_empty() || escaped.contains(char::is_whitespace) || escaped != value {
+            format!("\"{escaped}\"")
+        } else {
+            escaped
+        }
+    }
+}
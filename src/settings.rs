@@ -3,21 +3,159 @@
 //! Handles loading, saving, and displaying configuration using a thread-safe
 //! global path and safe home directory fallbacks.
 
+use crate::tree::TreeSettings;
+use crate::utils::current_uid;
 use sandbox_utils::{
     config_file, default_cache, default_rootfs, get_config_diff, render_table, safe_home,
     InodeMode, OverlayAction, USE_PROOT,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::OnceLock;
 use std::{env, fs};
 
+/// Default CDN used when no mirror is configured for an architecture.
+const DEFAULT_MIRROR: &str = "https://dl-cdn.alpinelinux.org/alpine/";
+
+/// Configuration directory of the system-wide installation.
+const SYSTEM_CONFIG_DIR: &str = "/etc/alpack";
+
+/// Download cache of the system-wide installation.
+const SYSTEM_CACHE_DIR: &str = "/var/cache/alpack";
+
+/// Shared rootfs directory of the system-wide installation.
+const SYSTEM_ROOTFS_DIR: &str = "/var/lib/alpack";
+
+/// Group allowed to maintain the shared directories in system mode.
+const SYSTEM_GROUP: &str = "alpack";
+
+/// Mirror configuration, either one URL or a table keyed by architecture.
+///
+/// A per-arch table may contain a `default` key used for architectures
+/// without an explicit entry:
+///
+/// ```toml
+/// [default_mirror]
+/// x86_64 = "http://mirror.lan/alpine/"
+/// default = "https://dl-cdn.alpinelinux.org/alpine/"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MirrorSetting {
+    /// The same mirror for every architecture.
+    Single(String),
+    /// Mirrors keyed by architecture name.
+    PerArch(BTreeMap<String, String>),
+}
+
+impl MirrorSetting {
+    /// Resolves the mirror URL for the given architecture.
+    ///
+    /// # Parameters
+    /// - `arch`: The target architecture (e.g., "x86_64").
+    ///
+    /// # Returns
+    /// The matching URL, the table's `default` entry, or the Alpine CDN.
+    pub fn for_arch(&self, arch: &str) -> String {
+        match self {
+            MirrorSetting::Single(url) => url.clone(),
+            MirrorSetting::PerArch(table) => table
+                .get(arch)
+                .or_else(|| table.get("default"))
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_MIRROR.to_string()),
+        }
+    }
+}
+
+/// Settings applied to sandbox sessions started by `run`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RunSettings {
+    /// Environment variables exported in every session (`[run.env]`).
+    pub env: BTreeMap<String, String>,
+    /// Bind the current directory and start sessions in it (`run --bind-cwd`).
+    pub bind_cwd: bool,
+}
+
+/// Options specific to the proot backend (`[proot]`).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ProotSettings {
+    /// Kill the remaining processes when the main command exits.
+    pub kill_on_exit: bool,
+    /// Emulate System V IPC (shared memory, semaphores, message queues).
+    pub sysvipc: bool,
+    /// Use proot's `-S` mode: fake root with the recommended host bindings.
+    pub su_bindings: bool,
+}
+
+/// Host commands run by `setup` at defined stages (`[hooks]`).
+///
+/// Each hook is a shell command; an empty one is skipped.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct HookSettings {
+    /// Run before the rootfs is downloaded or extracted.
+    pub pre_download: String,
+    /// Run once the rootfs is extracted, before packages are installed.
+    pub post_extract: String,
+    /// Run once the initial packages are installed.
+    pub post_install: String,
+    /// Run when the setup completed successfully.
+    pub post_setup: String,
+}
+
+/// A host path bound into every sandbox session (`[binds."<host path>"]`).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct BindSettings {
+    /// Mount point inside the sandbox (empty = the host path).
+    pub dest: PathBuf,
+    /// Whether the path is bound read-only (bwrap only).
+    pub read_only: bool,
+}
+
+/// A named set of binds and variables selected with `run --bind-profile`
+/// (`[bind_profiles.<name>]`).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct BindProfile {
+    /// Host paths bound into the session, keyed by host path.
+    pub binds: BTreeMap<String, BindSettings>,
+    /// Environment variables exported in the session.
+    pub env: BTreeMap<String, String>,
+    /// Skip the binds declared under `[binds]`, as `run -i` does.
+    pub ignore_extra_binds: bool,
+}
+
+/// Credentials for a mirror that requires authentication.
+///
+/// Either `user` and `password` for HTTP basic auth or a bearer `token`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct MirrorAuth {
+    /// The user name for basic auth.
+    pub user: String,
+    /// The password for basic auth.
+    pub password: String,
+    /// A bearer token, used when no user is set.
+    pub token: String,
+}
+
 /// Application configuration settings.
+///
+/// Fields missing from an older configuration file fall back to their defaults.
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct Settings {
-    /// The default Alpine Linux mirror URL.
-    pub default_mirror: String,
+    /// The default Alpine Linux mirror URL, optionally per architecture.
+    pub default_mirror: MirrorSetting,
     /// Directory used for caching downloaded files.
     pub cache_dir: PathBuf,
     /// Directory where the rootfs will be extracted/managed.
@@ -34,24 +172,107 @@ pub struct Settings {
     pub overlay_inode_mode: InodeMode,
     /// The cleanup or preserve action to take on the overlay after execution.
     pub overlay_action: OverlayAction,
+    /// Maximum total size of the download cache in bytes (0 = unlimited).
+    pub max_cache_size: u64,
+    /// Days after which unused cache entries are evicted (0 = never).
+    pub cache_ttl_days: u64,
+    /// Connection and response timeout for HTTP requests, in seconds.
+    pub http_timeout: u64,
+    /// Proxy URL for HTTP requests (empty = use the environment).
+    pub http_proxy: String,
+    /// Number of concurrent ranged connections for large downloads (1 = off).
+    pub download_segments: u32,
+    /// Address family used for downloads (`any`, `ipv4` or `ipv6`).
+    pub ip_family: String,
+    /// Defaults for sandbox sessions.
+    pub run: RunSettings,
+    /// Additional package trees keyed by name (`[trees.<name>]`).
+    pub trees: BTreeMap<String, TreeSettings>,
+    /// Whether builds run in a discarded overlay by default.
+    pub builder_ephemeral: bool,
+    /// Directory receiving a log of every `run` and `builder` session (empty = disabled).
+    pub logs_dir: PathBuf,
+    /// Whether downloaded packages are kept in a host cache shared by every rootfs.
+    pub share_apk_cache: bool,
+    /// Whether documentation (man pages and `-doc` subpackages) is installed.
+    pub with_docs: bool,
+    /// Additional root certificates (PEM files) trusted for downloads.
+    pub ca_certs: Vec<PathBuf>,
+    /// Whether the additional certificates are also trusted inside new rootfs.
+    pub rootfs_ca_certs: bool,
+    /// Mirror credentials keyed by host, e.g. `[mirror_auth."mirror.corp.lan"]`.
+    pub mirror_auth: BTreeMap<String, MirrorAuth>,
+    /// Whether `run` sessions use the hardened backend options by default.
+    pub hardening: bool,
+    /// Options for the proot backend.
+    pub proot: ProotSettings,
+    /// Whether bwrap sessions map the user's subordinate UIDs and GIDs.
+    pub subid_mapping: bool,
+    /// Commands run at the stages of `setup`.
+    pub hooks: HookSettings,
+    /// Host paths bound into `run`, `apk` and `builder` sessions, keyed by host path.
+    pub binds: BTreeMap<String, BindSettings>,
+    /// Bind and environment profiles for `run --bind-profile`, keyed by name.
+    pub bind_profiles: BTreeMap<String, BindProfile>,
 }
 
 /// Global thread-safe storage for application settings.
 static SETTINGS: OnceLock<Settings> = OnceLock::new();
 
+/// Sandbox backend selected at runtime, overriding the configured one.
+static CMD_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Rootfs directory given with the global `--rootfs` option.
+static ROOTFS_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Configuration file selected with the global `--config` or `--profile` options.
+static CONFIG_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Whether the system-wide installation is used (global `--system` option).
+static SYSTEM_MODE: AtomicBool = AtomicBool::new(false);
+
 impl Default for Settings {
     /// Provides default settings based on the safe home directory.
     fn default() -> Self {
         Self {
-            default_mirror: "https://dl-cdn.alpinelinux.org/alpine/".to_string(),
-            cache_dir: default_cache(),
-            rootfs_dir: default_rootfs(),
+            default_mirror: MirrorSetting::Single(DEFAULT_MIRROR.to_string()),
+            cache_dir: if is_system_mode() {
+                PathBuf::from(SYSTEM_CACHE_DIR)
+            } else {
+                default_cache()
+            },
+            rootfs_dir: if is_system_mode() {
+                PathBuf::from(SYSTEM_ROOTFS_DIR)
+            } else {
+                default_rootfs()
+            },
             cmd_rootfs: USE_PROOT.to_string(),
             release: "latest-stable".to_string(),
             output_dir: PathBuf::new(),
             use_overlay: false,
             overlay_inode_mode: InodeMode::Virtual,
             overlay_action: OverlayAction::Preserve,
+            max_cache_size: 0,
+            cache_ttl_days: 0,
+            http_timeout: 30,
+            http_proxy: String::new(),
+            download_segments: 1,
+            ip_family: "any".to_string(),
+            run: RunSettings::default(),
+            trees: BTreeMap::new(),
+            builder_ephemeral: false,
+            logs_dir: PathBuf::new(),
+            share_apk_cache: false,
+            with_docs: false,
+            ca_certs: Vec::new(),
+            rootfs_ca_certs: false,
+            mirror_auth: BTreeMap::new(),
+            hardening: false,
+            proot: ProotSettings::default(),
+            subid_mapping: false,
+            hooks: HookSettings::default(),
+            binds: BTreeMap::new(),
+            bind_profiles: BTreeMap::new(),
         }
     }
 }
@@ -75,7 +296,7 @@ impl Settings {
     /// # Returns
     /// - A `Settings` struct populated from disk or defaults.
     pub fn load() -> Self {
-        let path = config_file();
+        let path = settings_config_file();
 
         match fs::read_to_string(path) {
             Ok(content) if content.is_empty() => Self::create(),
@@ -93,7 +314,10 @@ impl Settings {
     /// - A `Settings` struct containing default values.
     fn create() -> Self {
         let default = Settings::default();
-        let path = config_file();
+        let path = settings_config_file();
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
         let _ = fs::write(&path, toml::to_string_pretty(&default).unwrap_or_default());
         default
     }
@@ -105,7 +329,7 @@ impl Settings {
     /// - `Err` if serialization or the write operation fails.
     pub fn save(&self) -> Result<(), Box<dyn Error>> {
         let toml_data = toml::to_string_pretty(self)?;
-        fs::write(config_file(), toml_data)?;
+        fs::write(settings_config_file(), toml_data)?;
         Ok(())
     }
 
@@ -114,7 +338,7 @@ impl Settings {
     /// Fields that differ will be highlighted using ANSI color codes to show
     /// the transition from the old value to the new value.
     pub fn show_config_changes(&self) {
-        let disk_config = fs::read_to_string(config_file())
+        let disk_config = fs::read_to_string(settings_config_file())
             .ok()
             .and_then(|s| toml::from_str::<Settings>(&s).ok());
 
@@ -127,30 +351,162 @@ impl Settings {
     }
 }
 
-/// Returns the default Alpine Linux mirror URL.
+/// Returns the default Alpine Linux mirror URL for an architecture.
 ///
 /// This value is retrieved from the global settings initialized from the configuration file.
 ///
+/// # Parameters
+/// - `arch`: The effective target architecture.
+///
 /// # Returns
 /// A `String` containing the mirror URL (e.g., "https://dl-cdn.alpinelinux.org/alpine/").
-pub fn settings_mirror() -> String {
-    SETTINGS.wait().default_mirror.clone()
+pub fn settings_mirror(arch: &str) -> String {
+    SETTINGS.wait().default_mirror.for_arch(arch)
 }
 
 /// Returns the active root filesystem directory.
 ///
 /// Resolution priority:
-/// 1. `ALPACK_ROOTFS` environment variable.
-/// 2. `rootfs_dir` value from the configuration file.
+/// 1. The global `--rootfs` option.
+/// 2. `ALPACK_ROOTFS` environment variable.
+/// 3. `rootfs_dir` value from the configuration file.
 ///
 /// # Returns
 /// A `PathBuf` pointing to the directory where the rootfs is managed.
 pub fn settings_rootfs_dir() -> PathBuf {
+    if let Some(rootfs) = ROOTFS_OVERRIDE.get() {
+        return rootfs.clone();
+    }
+
     env::var("ALPACK_ROOTFS")
         .map(PathBuf::from)
         .unwrap_or_else(|_| SETTINGS.wait().rootfs_dir.clone())
 }
 
+/// Overrides the rootfs directory for this invocation.
+///
+/// # Parameters
+/// - `rootfs`: The rootfs directory given on the command line.
+pub fn set_rootfs_override(rootfs: PathBuf) {
+    let _ = ROOTFS_OVERRIDE.set(rootfs);
+}
+
+/// Returns the configuration file in use.
+///
+/// This is the file selected with `--config` or `--profile`, or the
+/// default configuration file otherwise.
+pub fn settings_config_file() -> PathBuf {
+    CONFIG_OVERRIDE.get().cloned().unwrap_or_else(config_file)
+}
+
+/// Selects the configuration file for this invocation.
+///
+/// Must be called before the settings are loaded.
+///
+/// # Parameters
+/// - `path`: The configuration file to read and write.
+pub fn set_config_file(path: PathBuf) {
+    let _ = CONFIG_OVERRIDE.set(path);
+}
+
+/// Switches to the system-wide installation for this invocation.
+///
+/// The configuration is read from `/etc/alpack/config.toml` and, unless
+/// configured otherwise, the cache and rootfs live in `/var/cache/alpack`
+/// and `/var/lib/alpack`. Must be called before the settings are loaded.
+pub fn set_system_mode() {
+    SYSTEM_MODE.store(true, Ordering::Relaxed);
+    set_config_file(Path::new(SYSTEM_CONFIG_DIR).join("config.toml"));
+}
+
+/// Returns whether the system-wide installation is in use.
+pub fn is_system_mode() -> bool {
+    SYSTEM_MODE.load(Ordering::Relaxed)
+}
+
+/// Prepares the shared directories of the system-wide installation.
+///
+/// When run as root, missing directories are created owned by the
+/// `alpack` group with the setgid bit, and a default ACL gives the group
+/// write access to everything created below them regardless of the
+/// umask of the member who created it. Other users only get a warning
+/// when the installation has not been set up yet.
+///
+/// # Returns
+/// - `Ok(())` once the directories are usable.
+/// - `Err` if a directory cannot be created as root.
+pub fn prepare_system_dirs() -> Result<(), Box<dyn Error>> {
+    let is_root = current_uid() == 0;
+
+    for dir in [settings_cache_dir(), settings_rootfs_dir()] {
+        if dir.is_dir() {
+            continue;
+        }
+
+        if !is_root {
+            eprintln!(
+                "\x1b[1;33mWarning\x1b[0m: {} does not exist. The system installation must be set up by root first.",
+                dir.display()
+            );
+            continue;
+        }
+
+        fs::create_dir_all(&dir)?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o2775))?;
+
+        let grouped = Command::new("chgrp")
+            .arg(SYSTEM_GROUP)
+            .arg(&dir)
+            .status()
+            .is_ok_and(|s| s.success());
+        let acl = Command::new("setfacl")
+            .args(["-m", "g::rwx,d:g::rwx,d:m::rwx"])
+            .arg(&dir)
+            .status()
+            .is_ok_and(|s| s.success());
+
+        if !grouped || !acl {
+            eprintln!(
+                "\x1b[1;33mWarning\x1b[0m: {} is not fully shared: create the '{SYSTEM_GROUP}' group and install setfacl so its members can maintain it.",
+                dir.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns the directory holding per-user runtime state.
+///
+/// This is the directory of the configuration file, except in system mode
+/// where the shared configuration directory is not writable by users and
+/// the user's own configuration directory is used instead.
+pub fn settings_state_dir() -> PathBuf {
+    let file = if is_system_mode() {
+        config_file()
+    } else {
+        settings_config_file()
+    };
+    file.parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(safe_home)
+}
+
+/// Selects a named configuration profile for this invocation.
+///
+/// Profiles are complete configuration files stored in the `profiles`
+/// directory next to the default one, so each profile can point to its
+/// own rootfs, cache and mirror.
+///
+/// # Parameters
+/// - `name`: The profile name.
+pub fn set_profile(name: &str) {
+    let dir = config_file()
+        .parent()
+        .map(|p| p.join("profiles"))
+        .unwrap_or_else(|| PathBuf::from("profiles"));
+    set_config_file(dir.join(format!("{name}.toml")));
+}
+
 /// Returns the active cache directory for downloads.
 ///
 /// Resolution priority:
@@ -172,7 +528,20 @@ pub fn settings_cache_dir() -> PathBuf {
 /// # Returns
 /// A `String` representing the binary name or command configured for the rootfs.
 pub fn settings_cmd() -> String {
-    SETTINGS.wait().cmd_rootfs.clone()
+    CMD_OVERRIDE
+        .get()
+        .cloned()
+        .unwrap_or_else(|| SETTINGS.wait().cmd_rootfs.clone())
+}
+
+/// Overrides the configured sandbox backend for this invocation.
+///
+/// Must be called before the backend is handed to `set_sandbox_tool`.
+///
+/// # Parameters
+/// - `cmd`: The backend to use, `"proot"` or `"bwrap"`.
+pub fn set_cmd_override(cmd: &str) {
+    let _ = CMD_OVERRIDE.set(cmd.to_string());
 }
 
 /// Returns the target Alpine Linux release version.
@@ -227,3 +596,306 @@ pub fn settings_overlay_action() -> OverlayAction {
 pub fn settings_overlay_inode_mode() -> InodeMode {
     SETTINGS.wait().overlay_inode_mode.clone()
 }
+
+/// Returns the maximum size of the download cache.
+///
+/// # Returns
+/// The limit in bytes, where `0` disables size-based eviction.
+pub fn settings_max_cache_size() -> u64 {
+    SETTINGS.wait().max_cache_size
+}
+
+/// Returns how long unused cache entries are kept.
+///
+/// # Returns
+/// The retention period in days, where `0` disables age-based eviction.
+pub fn settings_cache_ttl_days() -> u64 {
+    SETTINGS.wait().cache_ttl_days
+}
+
+/// Returns the timeout applied to HTTP connections and responses.
+///
+/// # Returns
+/// The timeout in seconds.
+pub fn settings_http_timeout() -> u64 {
+    SETTINGS.wait().http_timeout
+}
+
+/// Returns the proxy configured for HTTP requests.
+///
+/// # Returns
+/// A `String` with the proxy URL, or empty to rely on the environment.
+pub fn settings_http_proxy() -> String {
+    SETTINGS.wait().http_proxy.clone()
+}
+
+/// Returns how many ranged connections large downloads are split into.
+///
+/// # Returns
+/// The number of segments, where `1` downloads over a single connection.
+pub fn settings_download_segments() -> u32 {
+    SETTINGS.wait().download_segments.max(1)
+}
+
+/// Returns the address family used for network connections.
+///
+/// # Returns
+/// A `String` with `"any"`, `"ipv4"` or `"ipv6"`.
+pub fn settings_ip_family() -> String {
+    SETTINGS.wait().ip_family.clone()
+}
+
+/// Returns the environment variables configured in `[run.env]`.
+///
+/// These are exported inside sandbox sessions on top of the default
+/// environment, so they may also override `PATH`, `PS1` or `SHELL`.
+///
+/// # Returns
+/// A vector of `(name, value)` pairs sorted by name.
+pub fn settings_run_env() -> Vec<(String, String)> {
+    SETTINGS
+        .wait()
+        .run
+        .env
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Returns whether sessions bind and start in the current directory.
+///
+/// # Returns
+/// `true` if `run` behaves as with `--bind-cwd` by default.
+pub fn settings_bind_cwd() -> bool {
+    SETTINGS.wait().run.bind_cwd
+}
+
+/// Returns the package trees declared in the configuration file.
+///
+/// # Returns
+/// A map of tree names to their repository definitions.
+pub fn settings_trees() -> BTreeMap<String, TreeSettings> {
+    SETTINGS.wait().trees.clone()
+}
+
+/// Returns whether builds leave the base rootfs untouched by default.
+///
+/// # Returns
+/// `true` if `builder` should run every step in a discarded overlay.
+pub fn settings_builder_ephemeral() -> bool {
+    SETTINGS.wait().builder_ephemeral
+}
+
+/// Returns the directory where session logs are written.
+///
+/// # Returns
+/// A `PathBuf`, empty when automatic logging is disabled.
+pub fn settings_logs_dir() -> PathBuf {
+    SETTINGS.wait().logs_dir.clone()
+}
+
+/// Returns whether the apk package cache is shared with the host.
+///
+/// # Returns
+/// `true` if `/var/cache/apk` is bound to a persistent host directory.
+pub fn settings_share_apk_cache() -> bool {
+    SETTINGS.wait().share_apk_cache
+}
+
+/// Returns whether new rootfs environments include documentation.
+///
+/// # Returns
+/// `true` if `setup` installs the `docs` meta package and a man viewer.
+pub fn settings_with_docs() -> bool {
+    SETTINGS.wait().with_docs
+}
+
+/// Returns the additional root certificates configured for downloads.
+///
+/// # Returns
+/// A vector of PEM file paths, empty when only the default roots are used.
+pub fn settings_ca_certs() -> Vec<PathBuf> {
+    SETTINGS.wait().ca_certs.clone()
+}
+
+/// Returns whether additional certificates are installed into new rootfs.
+///
+/// # Returns
+/// `true` if `setup` adds them to the rootfs trust store.
+pub fn settings_rootfs_ca_certs() -> bool {
+    SETTINGS.wait().rootfs_ca_certs
+}
+
+/// Returns whether sandbox sessions are hardened by default.
+///
+/// # Returns
+/// `true` if `run` applies the hardening bundle without `--hardened`.
+pub fn settings_hardening() -> bool {
+    SETTINGS.wait().hardening
+}
+
+/// Returns the options configured for the proot backend.
+///
+/// # Returns
+/// A `ProotSettings` with the `[proot]` values.
+pub fn settings_proot() -> ProotSettings {
+    SETTINGS.wait().proot.clone()
+}
+
+/// Returns whether subordinate ID mapping is enabled for bwrap.
+///
+/// # Returns
+/// `true` if ALPack runs in a user namespace mapping `/etc/subuid` ranges.
+pub fn settings_subid_mapping() -> bool {
+    SETTINGS.wait().subid_mapping
+}
+
+/// Returns the commands run at the stages of `setup`.
+///
+/// # Returns
+/// A `HookSettings` with the `[hooks]` values.
+pub fn settings_hooks() -> HookSettings {
+    SETTINGS.wait().hooks.clone()
+}
+
+/// Returns the host paths bound into every sandbox session.
+///
+/// # Returns
+/// A map of host paths to their `[binds]` definitions.
+pub fn settings_binds() -> BTreeMap<String, BindSettings> {
+    SETTINGS.wait().binds.clone()
+}
+
+/// Returns a profile declared under `[bind_profiles]`.
+///
+/// # Parameters
+/// - `name`: The profile name given to `run --bind-profile`.
+///
+/// # Returns
+/// - `Ok(BindProfile)` with its binds and variables.
+/// - `Err` listing the declared profiles if none has this name.
+pub fn settings_bind_profile(name: &str) -> Result<BindProfile, Box<dyn Error>> {
+    let profiles = &SETTINGS.wait().bind_profiles;
+
+    profiles.get(name).cloned().ok_or_else(|| {
+        let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+        if known.is_empty() {
+            format!("Unknown bind profile '{name}': no [bind_profiles] are configured").into()
+        } else {
+            format!(
+                "Unknown bind profile '{name}', known bind profiles: {}",
+                known.join(", ")
+            )
+            .into()
+        }
+    })
+}
+
+/// Returns the credentials configured for a mirror host.
+///
+/// Resolution priority:
+/// 1. `ALPACK_MIRROR_USER` and `ALPACK_MIRROR_PASSWORD`, or
+///    `ALPACK_MIRROR_TOKEN`, when the host is the mirror in use.
+/// 2. The `[mirror_auth."<host>"]` entry from the configuration file.
+///
+/// # Parameters
+/// - `host`: The mirror host, with the port if not the default one.
+/// - `mirror_host`: The host of the mirror in use.
+///
+/// # Returns
+/// The credentials, if any are configured for the host.
+pub fn settings_mirror_auth(host: &str, mirror_host: &str) -> Option<MirrorAuth> {
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    let from_env = MirrorAuth {
+        user: var("ALPACK_MIRROR_USER"),
+        password: var("ALPACK_MIRROR_PASSWORD"),
+        token: var("ALPACK_MIRROR_TOKEN"),
+    };
+
+    if host == mirror_host && from_env != MirrorAuth::default() {
+        return Some(from_env);
+    }
+    SETTINGS.wait().mirror_auth.get(host).cloned()
+}
+
+/// Returns every setting with its effective value and where it came from.
+///
+/// The source is `default` for values equal to the built-in default and
+/// `file` for values read from the configuration file, unless an
+/// environment variable or a command-line option takes precedence.
+///
+/// # Returns
+/// A vector of `(setting, value, source)` triples, starting with the
+/// configuration file itself.
+pub fn effective_settings() -> Vec<(String, String, String)> {
+    let table = |settings: &Settings| {
+        toml::Value::try_from(settings)
+            .ok()
+            .and_then(|v| v.as_table().cloned())
+            .unwrap_or_default()
+    };
+    let render = |value: &toml::Value| match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    let defaults = table(&Settings::default());
+    let config_source = if CONFIG_OVERRIDE.get().is_some() {
+        "flag (--config/--profile)"
+    } else {
+        "default"
+    };
+
+    let mut rows = vec![(
+        "config_file".to_string(),
+        settings_config_file().display().to_string(),
+        config_source.to_string(),
+    )];
+
+    for (key, value) in table(SETTINGS.wait()) {
+        let mut source = if defaults.get(&key) == Some(&value) {
+            "default".to_string()
+        } else {
+            "file".to_string()
+        };
+        let mut value = match key.as_str() {
+            "mirror_auth" => value
+                .as_table()
+                .map(|hosts| hosts.keys().cloned().collect::<Vec<_>>().join(", "))
+                .unwrap_or_default(),
+            _ => render(&value),
+        };
+
+        let env_var = match key.as_str() {
+            "rootfs_dir" => Some("ALPACK_ROOTFS"),
+            "cache_dir" => Some("ALPACK_CACHE"),
+            _ => None,
+        };
+
+        if let Some(var) = env_var.filter(|var| env::var(var).is_ok()) {
+            source = format!("env ({var})");
+        }
+
+        match key.as_str() {
+            "rootfs_dir" => {
+                if ROOTFS_OVERRIDE.get().is_some() {
+                    source = "flag (--rootfs)".to_string();
+                }
+                value = settings_rootfs_dir().display().to_string();
+            }
+            "cache_dir" => value = settings_cache_dir().display().to_string(),
+            "cmd_rootfs" if CMD_OVERRIDE.get().is_some() => {
+                value = settings_cmd();
+                source = "runtime (backend fallback)".to_string();
+            }
+            "output_dir" if value.is_empty() => {
+                value = settings_output_dir().display().to_string();
+                source = "default (current directory)".to_string();
+            }
+            _ => {}
+        }
+
+        rows.push((key, value, source));
+    }
+    rows
+}
@@ -4,18 +4,36 @@
 //! including mirror selection, version discovery, rootfs extraction, and
 //! provisioning of default packages.
 
-use crate::mirror::Mirror;
-use crate::settings::{settings_cache_dir, settings_rootfs_dir};
-use crate::utils::map_result;
+use crate::adelie::{self, ADELIE_PACKAGES, ADELIE_RELEASE, DISTROS};
+use crate::cache::Cache;
+use crate::envs;
+use crate::http;
+use crate::http::{install_rootfs_ca_certs, set_ip_family, with_credentials};
+use crate::image;
+use crate::manifest::Manifest;
+use crate::mirror::{parse_release, write_release, Mirror};
+use crate::ownership::OwnershipDb;
+use crate::qemu;
+use crate::settings::{
+    settings_cache_dir, settings_hooks, settings_rootfs_ca_certs, settings_rootfs_dir,
+    settings_with_docs,
+};
+use crate::snapshot;
+use crate::utils::{
+    extract_tarball, is_json, is_offline, map_result, parse_on_failure, shell_quote,
+    stream_extract, with_apk_cache, with_failure_shell,
+};
+use crate::verify;
 use regex::Regex;
 use sandbox_utils::{
-    app_arch, app_name, invalid_arg, parse_value, success_finish_setup, temp_cache, SandBox,
-    SandBoxConfig,
+    app_name, invalid_arg, parse_value, success_finish_setup, temp_cache, SandBox, SandBoxConfig,
 };
 use scraper::{Html, Selector};
 use std::collections::VecDeque;
 use std::error::Error;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 /// Structured version components for semantic comparison.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -30,6 +48,10 @@ pub struct VersionKey {
 pub const DEF_PACKAGES: &str =
     "alpine-sdk autoconf automake cmake glib-dev glib-static libtool go xz";
 
+/// Packages providing documentation: `docs` pulls the `-doc` subpackage of
+/// everything installed, now and in the future.
+pub const DOC_PACKAGES: &str = "docs man-pages mandoc";
+
 /// Controller for setting up the Alpine Linux rootfs environment.
 pub struct Setup {
     /// Command line arguments not consumed by the main parser.
@@ -54,14 +76,33 @@ impl Setup {
     pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
         let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
         let mut use_mirror: Option<String> = None;
-        let (mut no_cache, mut reinstall, mut edge, mut minimal) = (false, false, false, false);
+        let (mut no_cache, mut reinstall, mut minimal) = (false, false, false);
+        let mut release: Option<String> = None;
+        let (mut stream, mut dry_run, mut fastest_mirror) = (false, false, false);
+        let mut snapshot_baseline = false;
+        let mut failure_shell = false;
+        let mut with_docs = settings_with_docs();
+        let (mut repos, mut extra_repos): (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
         let (mut cache_dir, mut rootfs) = (settings_cache_dir(), settings_rootfs_dir());
+        let (mut name, mut rootfs_given) = (None, false);
+        let mut arch = None;
+        let (mut from_image, mut from_tarball): (Option<String>, Option<String>) = (None, None);
+        let mut packages_file: Option<String> = None;
+        let mut distro = "alpine".to_string();
 
         while let Some(arg) = args.pop_front() {
             match arg {
-                "--edge" => edge = true,
+                "--edge" => release = Some("edge".to_string()),
                 "--no-cache" => no_cache = true,
+                "--stream" => stream = true,
+                "--dry-run" => dry_run = true,
+                "--snapshot-baseline" => snapshot_baseline = true,
+                "--fastest-mirror" => fastest_mirror = true,
+                "--ip4" => set_ip_family("ipv4"),
+                "--ip6" => set_ip_family("ipv6"),
                 "--minimal" => minimal = true,
+                "--with-docs" => with_docs = true,
+                "--no-docs" => with_docs = false,
                 "-r" | "--reinstall" => reinstall = true,
                 a if a.starts_with("--mirror=") => {
                     use_mirror = Some(parse_value!("setup", "url", arg)?);
@@ -69,22 +110,153 @@ impl Setup {
                 "--mirror" => {
                     use_mirror = Some(parse_value!("setup", "url", arg, args.pop_front())?);
                 }
+                a if a.starts_with("--release=") => {
+                    release = Some(parse_release(&parse_value!("setup", "release", arg)?)?);
+                }
+                "--release" => {
+                    let value = parse_value!("setup", "release", arg, args.pop_front())?;
+                    release = Some(parse_release(&value)?);
+                }
+                a if a.starts_with("--packages-file=") => {
+                    packages_file = Some(parse_value!("setup", "file", arg)?);
+                }
+                "--packages-file" => {
+                    packages_file = Some(parse_value!("setup", "file", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--repositories=") => {
+                    let file = parse_value!("setup", "file", arg)?;
+                    repos.extend(Self::read_repositories(&file)?);
+                }
+                "--repositories" => {
+                    let file = parse_value!("setup", "file", arg, args.pop_front())?;
+                    repos.extend(Self::read_repositories(&file)?);
+                }
+                a if a.starts_with("--repo=") => {
+                    extra_repos.push(parse_value!("setup", "url", arg)?);
+                }
+                "--repo" => {
+                    extra_repos.push(parse_value!("setup", "url", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--on-failure=") => {
+                    failure_shell = parse_on_failure(&parse_value!("setup", "action", arg)?)?;
+                }
+                "--on-failure" => {
+                    let value = parse_value!("setup", "action", arg, args.pop_front())?;
+                    failure_shell = parse_on_failure(&value)?;
+                }
                 a if a.starts_with("--cache=") => {
                     cache_dir = parse_value!("setup", "directory", arg)?.into();
                 }
                 "--cache" => {
                     cache_dir = parse_value!("setup", "directory", arg, args.pop_front())?.into();
                 }
+                a if a.starts_with("--arch=") => {
+                    arch = Some(qemu::parse_arch(&parse_value!("setup", "arch", arg)?)?);
+                }
+                "--arch" => {
+                    let value = parse_value!("setup", "arch", arg, args.pop_front())?;
+                    arch = Some(qemu::parse_arch(&value)?);
+                }
+                a if a.starts_with("--distro=") => {
+                    distro = parse_value!("setup", "distro", arg)?;
+                }
+                "--distro" => {
+                    distro = parse_value!("setup", "distro", arg, args.pop_front())?;
+                }
+                a if a.starts_with("--from-image=") => {
+                    from_image = Some(parse_value!("setup", "image", arg)?);
+                }
+                "--from-image" => {
+                    from_image = Some(parse_value!("setup", "image", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--from-tarball=") => {
+                    from_tarball = Some(parse_value!("setup", "file", arg)?);
+                }
+                "--from-tarball" => {
+                    from_tarball = Some(parse_value!("setup", "file", arg, args.pop_front())?);
+                }
+                a if a.starts_with("--name=") => {
+                    name = Some(parse_value!("setup", "name", arg)?);
+                }
+                "--name" => {
+                    name = Some(parse_value!("setup", "name", arg, args.pop_front())?);
+                }
                 a if a.starts_with("--rootfs=") => {
                     rootfs = parse_value!("setup", "directory", arg)?.into();
+                    rootfs_given = true;
                 }
                 "-R" | "--rootfs" => {
                     rootfs = parse_value!("setup", "directory", arg, args.pop_front())?.into();
+                    rootfs_given = true;
                 }
                 _ => return invalid_arg!("setup", arg),
             }
         }
 
+        if let (Some(name), false) = (&name, rootfs_given) {
+            rootfs = envs::setup_path(name)?;
+        }
+
+        if !DISTROS.contains(&distro.as_str()) {
+            return Err(format!(
+                "Unknown distribution '{distro}'. Available: {}",
+                DISTROS.join(", ")
+            )
+            .into());
+        }
+
+        let adelie = distro == "adelie";
+        if adelie {
+            if from_image.is_some() || from_tarball.is_some() {
+                return Err(
+                    "--distro adelie cannot be combined with --from-image or --from-tarball".into(),
+                );
+            }
+            if arch.is_some() || release.is_some() {
+                return Err(format!(
+                    "--distro adelie cannot be combined with --arch, --release or --edge: it installs Adélie {ADELIE_RELEASE} for the host architecture"
+                )
+                .into());
+            }
+            from_tarball = Some(adelie::rootfs_url()?);
+            if repos.is_empty() {
+                repos = adelie::repositories();
+            }
+        }
+
+        let file_packages = packages_file
+            .as_deref()
+            .map(Self::read_packages)
+            .transpose()?;
+
+        let mut packages = Vec::new();
+        match file_packages.as_deref() {
+            Some("") => {}
+            Some(list) => packages.push(list),
+            None if !minimal && adelie => packages.push(ADELIE_PACKAGES),
+            None if !minimal => packages.push(DEF_PACKAGES),
+            None => {}
+        }
+        if with_docs {
+            packages.push(DOC_PACKAGES);
+        }
+
+        if dry_run {
+            let source = match (&from_image, &from_tarball) {
+                (Some(image), _) => Some(format!("image {image}")),
+                (None, Some(tarball)) => Some(format!("tarball {tarball}")),
+                (None, None) => None,
+            };
+            let mut mirror = Mirror::new(use_mirror, release);
+            if let Some(arch) = &arch {
+                mirror.set_arch(arch.clone());
+            }
+            if fastest_mirror && source.is_none() {
+                mirror.use_fastest()?;
+            }
+            return Self::print_plan(source, mirror, &rootfs, &cache_dir, &packages);
+        }
+
         if !reinstall && rootfs.exists() && rootfs.is_dir() {
             return Err(format!(
                 "Rootfs directory '{}' is already available.\nUse [-r|--reinstall] to reinstall it.",
@@ -92,72 +264,253 @@ impl Setup {
             ).into());
         }
 
-        if reinstall && rootfs.exists() {
-            println!("Reinstalling directory '{}'", rootfs.display());
-            obliterate::ensure_removed(&rootfs)?;
-        }
+        let hooks = settings_hooks();
 
-        if no_cache {
-            cache_dir = temp_cache();
+        if fastest_mirror && (use_mirror.is_some() || is_offline()) {
+            return Err("--fastest-mirror cannot be combined with --mirror or --offline".into());
         }
 
-        let mut mirror = Mirror::new(use_mirror, edge.then_some("edge".to_string()));
-        mirror.run()?;
+        if from_image.is_some() && from_tarball.is_some() {
+            return Err("--from-image cannot be combined with --from-tarball".into());
+        }
 
-        let url = mirror.get_mirror();
-        let res = ureq::get(url.as_str())
-            .call()?
-            .body_mut()
-            .read_to_string()?;
+        if from_image.is_some() || from_tarball.is_some() {
+            if arch.is_some() || release.is_some() {
+                return Err(
+                    "--from-image and --from-tarball cannot be combined with --arch, --release or --edge"
+                        .into(),
+                );
+            }
+            if stream {
+                return Err(
+                    "--stream cannot be combined with --from-image or --from-tarball".into(),
+                );
+            }
+            let downloads = from_tarball.as_deref().is_some_and(|t| t.contains("://"));
+            if no_cache && !downloads {
+                return Err("--no-cache only applies to a --from-tarball URL".into());
+            }
+            if is_offline() && from_image.is_some() {
+                return Err("--offline cannot be combined with --from-image".into());
+            }
 
-        let document = Html::parse_document(&res);
-        let selector = Selector::parse("a").unwrap();
+            if reinstall && rootfs.exists() {
+                println!("Reinstalling directory '{}'", rootfs.display());
+                obliterate::ensure_removed(&rootfs)?;
+            }
 
-        let pattern = format!(r"^alpine-minirootfs-([\w.\-]+)-{}\.tar\.gz$", app_arch());
-        let re = Regex::new(&pattern).unwrap();
+            if no_cache {
+                cache_dir = temp_cache();
+            }
 
-        let mut matches = vec![];
-        for element in document.select(&selector) {
-            if let Some(href) = element.value().attr("href") {
-                if let Some(caps) = re.captures(href) {
-                    let version_str = &caps[1];
-                    if let Some(key) = Self::parse_version_key(version_str) {
-                        matches.push((key, version_str.to_string(), href.to_string()));
-                    }
+            run_hook("pre_download", &hooks.pre_download, &rootfs)?;
+            let mut owners = OwnershipDb::needed().then(|| OwnershipDb::open(&rootfs));
+            let dest = rootfs.join("rootfs");
+            match (&from_image, &from_tarball) {
+                (Some(image), _) => image::extract(image, &dest, owners.as_mut())?,
+                (None, Some(tarball)) => {
+                    let file = if tarball.contains("://") {
+                        let name = tarball.rsplit('/').next().unwrap_or("rootfs.tar");
+                        Cache::open(cache_dir.clone())?.fetch(tarball, name)?
+                    } else {
+                        PathBuf::from(tarball)
+                    };
+                    extract_tarball(&file, &dest, owners.as_mut())?;
                 }
+                _ => {}
+            }
+            if no_cache {
+                let _ = fs::remove_dir_all(&cache_dir);
+            }
+            if let Some(db) = &owners {
+                db.save()?;
+            }
+
+            if !repos.is_empty() || !extra_repos.is_empty() {
+                let repo_path = rootfs.join("rootfs/etc/apk/repositories");
+                let base = if repos.is_empty() {
+                    fs::read_to_string(&repo_path).unwrap_or_default()
+                } else {
+                    repos.join("\n")
+                };
+                let repo_list: Vec<String> = base
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(str::to_string)
+                    .chain(extra_repos)
+                    .map(|r| with_credentials(&r))
+                    .collect();
+                fs::write(&repo_path, repo_list.join("\n") + "\n")?;
+            }
+
+            run_hook("post_extract", &hooks.post_extract, &rootfs)?;
+            Self::provision(&rootfs, &packages, failure_shell)?;
+            run_hook("post_install", &hooks.post_install, &rootfs)?;
+
+            let source = match (&from_image, &from_tarball) {
+                (Some(image), _) => format!("image:{image}"),
+                (None, Some(tarball)) => tarball.clone(),
+                _ => String::new(),
+            };
+            let mut manifest = Manifest {
+                arch: qemu::env_arch(&rootfs),
+                version: fs::read_to_string(rootfs.join("rootfs/etc/alpine-release"))
+                    .map(|version| version.trim().to_string())
+                    .unwrap_or_default(),
+                ..Manifest::new(source, &packages)
+            };
+            if adelie {
+                manifest.distro = distro.clone();
+                manifest.release = ADELIE_RELEASE.to_string();
+            } else if let Some(release) = Self::repositories_release(&rootfs) {
+                manifest.release = release;
+            }
+            if !manifest.release.is_empty() {
+                write_release(&rootfs, &manifest.release)?;
+            }
+            manifest.write(&rootfs)?;
+
+            if let Some(name) = &name {
+                envs::register(name, &rootfs)?;
             }
+            run_hook("post_setup", &hooks.post_setup, &rootfs)?;
+            if snapshot_baseline {
+                snapshot::take_baseline(&rootfs)?;
+            }
+            return success_finish_setup(format!("{} run", app_name()).as_str());
         }
 
-        matches.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut mirror = Mirror::new(use_mirror, release);
+        if let Some(arch) = &arch {
+            mirror.set_arch(arch.clone());
+        }
+        if fastest_mirror {
+            mirror.use_fastest()?;
+        }
+        mirror.run()?;
+        if is_offline() {
+            if stream || no_cache {
+                return Err("--offline cannot be combined with --stream or --no-cache".into());
+            }
+        } else {
+            mirror.check()?;
+        }
 
-        if let Some((_, version, link)) = matches.last() {
+        let url = mirror.get_mirror();
+        let res = Self::release_listing(&url, &cache_dir)?;
+
+        if is_offline() && Self::find_latest(&res, &mirror.get_arch()).is_none() {
+            return Err(format!(
+                "No cached alpine-minirootfs tarball for {url} in {}.\nRun '{} setup' once with network access first.",
+                cache_dir.display(),
+                app_name()
+            )
+            .into());
+        }
+
+        if reinstall && rootfs.exists() {
+            println!("Reinstalling directory '{}'", rootfs.display());
+            obliterate::ensure_removed(&rootfs)?;
+        }
+
+        if no_cache {
+            cache_dir = temp_cache();
+        }
+
+        if let Some((version, link)) = Self::find_latest(&res, &mirror.get_arch()) {
             println!("Latest version found: {version}");
             println!("Link: {url}{link}");
-            sandbox_utils::download_file(&format!("{url}{link}"), cache_dir.clone(), link)?;
-            sandbox_utils::extract_bootstrap(cache_dir.join(link), rootfs.clone())?;
+
+            run_hook("pre_download", &hooks.pre_download, &rootfs)?;
+            let mut owners = OwnershipDb::needed().then(|| OwnershipDb::open(&rootfs));
+
+            let tarball_url = format!("{url}{link}");
+
+            if is_offline() {
+                // The tarball was verified when it entered the cache.
+                let tarball = Cache::open(cache_dir.clone())?.fetch(&tarball_url, &link)?;
+                extract_tarball(&tarball, &rootfs.join("rootfs"), owners.as_mut())?;
+            } else if stream {
+                let expected = verify::fetch_sha256(&tarball_url)?;
+                let digest = stream_extract(&tarball_url, &rootfs.join("rootfs"), owners.as_mut())?;
+                if let Err(e) = verify::check_sha256(&link, &expected, &digest) {
+                    obliterate::ensure_removed(&rootfs)?;
+                    return Err(e);
+                }
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: The signature of {link} is not verified with --stream, only its checksum."
+                );
+            } else {
+                let expected = verify::fetch_sha256(&tarball_url)?;
+                let mut cache = Cache::open(cache_dir.clone())?;
+                let tarball = cache.fetch(&tarball_url, &link)?;
+                let digest = cache.hash(&link).unwrap_or_default().to_string();
+                let checked = verify::check_sha256(&link, &expected, &digest)
+                    .and_then(|_| verify::verify_signature(&tarball_url, &tarball));
+                if let Err(e) = checked {
+                    cache.forget(&link)?;
+                    return Err(e);
+                }
+                extract_tarball(&tarball, &rootfs.join("rootfs"), owners.as_mut())?;
+            }
 
             if no_cache {
                 let _ = fs::remove_dir_all(&cache_dir);
             }
 
-            let repo_path = rootfs.join("rootfs/etc/apk/repositories");
-            fs::write(&repo_path, mirror.get_repository())?;
+            if settings_rootfs_ca_certs() {
+                install_rootfs_ca_certs(&rootfs.join("rootfs"))?;
+                if let Some(db) = owners.as_mut() {
+                    db.record_copied(
+                        &rootfs.join("rootfs"),
+                        Path::new("usr/local/share/ca-certificates"),
+                    )?;
+                }
+            }
+
+            if let Some(db) = &owners {
+                db.save()?;
+            }
 
-            let apk_command = if minimal {
-                "apk update".to_string()
+            let repo_path = rootfs.join("rootfs/etc/apk/repositories");
+            let repo_list = if repos.is_empty() {
+                mirror.get_repository()
             } else {
-                format!("apk update && apk add {DEF_PACKAGES}")
+                repos.join("\n")
             };
+            let repo_list: Vec<String> = repo_list
+                .lines()
+                .map(str::to_string)
+                .chain(extra_repos)
+                .map(|r| with_credentials(&r))
+                .collect();
+            fs::write(&repo_path, repo_list.join("\n") + "\n")?;
 
-            let config = SandBoxConfig {
-                rootfs,
-                run_cmd: apk_command,
-                use_root: true,
-                ignore_extra_bind: true,
-                ..Default::default()
-            };
+            if let Some(arch) = &arch {
+                qemu::write_arch(&rootfs, arch)?;
+            }
+            write_release(&rootfs, &mirror.get_release())?;
+            run_hook("post_extract", &hooks.post_extract, &rootfs)?;
+
+            Self::provision(&rootfs, &packages, failure_shell)?;
+            run_hook("post_install", &hooks.post_install, &rootfs)?;
+
+            Manifest {
+                version,
+                arch: mirror.get_arch(),
+                release: mirror.get_release(),
+                mirror: mirror.get_base_url(),
+                ..Manifest::new(tarball_url, &packages)
+            }
+            .write(&rootfs)?;
 
-            map_result(SandBox::run(config))?;
+            if let Some(name) = &name {
+                envs::register(name, &rootfs)?;
+            }
+            run_hook("post_setup", &hooks.post_setup, &rootfs)?;
+            if snapshot_baseline {
+                snapshot::take_baseline(&rootfs)?;
+            }
         } else {
             Err("No alpine-minirootfs files found")?;
         }
@@ -165,6 +518,242 @@ impl Setup {
         success_finish_setup(format!("{} run", app_name()).as_str())
     }
 
+    /// Prints what `setup --dry-run` would do, without downloading or writing anything.
+    ///
+    /// For mirror installs, the release index is fetched to resolve the
+    /// tarball version that would be extracted.
+    ///
+    /// # Parameters
+    /// - `source`: The image or tarball given instead of the mirror, if any.
+    /// - `mirror`: The mirror, release and architecture overrides.
+    /// - `rootfs`, `cache_dir`: The target directories.
+    /// - `packages`: The package lists that would be installed.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing the plan.
+    /// - `Err` if the mirror does not serve the release or has no matching tarball.
+    fn print_plan(
+        source: Option<String>,
+        mut mirror: Mirror,
+        rootfs: &Path,
+        cache_dir: &Path,
+        packages: &[&str],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut rows = vec![];
+
+        match source {
+            Some(source) => rows.push(("source", source)),
+            None => {
+                mirror.run()?;
+                if !is_offline() {
+                    mirror.check()?;
+                }
+
+                let url = mirror.get_mirror();
+                let index = Self::release_listing(&url, cache_dir)?;
+                let (version, link) = Self::find_latest(&index, &mirror.get_arch())
+                    .ok_or("No alpine-minirootfs files found")?;
+
+                rows.push(("mirror", url.clone()));
+                rows.push(("release", mirror.get_release()));
+                rows.push(("arch", mirror.get_arch()));
+                rows.push(("version", version));
+                rows.push(("tarball", format!("{url}{link}")));
+            }
+        }
+
+        let state = if rootfs.is_dir() { "exists" } else { "new" };
+        rows.push(("rootfs", format!("{} ({state})", rootfs.display())));
+        rows.push(("cache_dir", cache_dir.display().to_string()));
+        let packages = packages.join(" ");
+        rows.push((
+            "packages",
+            if packages.is_empty() {
+                "none".to_string()
+            } else {
+                packages
+            },
+        ));
+
+        if is_json() {
+            let map: serde_json::Map<String, serde_json::Value> = rows
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.into()))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&map)?);
+            return Ok(());
+        }
+
+        for (key, value) in rows {
+            println!("{key:<9} {value}");
+        }
+        Ok(())
+    }
+
+    /// Installs the initial packages into a freshly extracted rootfs.
+    ///
+    /// # Parameters
+    /// - `rootfs`: The environment directory containing `rootfs`.
+    /// - `packages`: Package lists to install; only the indexes are fetched if empty.
+    /// - `failure_shell`: Whether to open a shell in the rootfs if apk fails.
+    ///
+    /// # Returns
+    /// - `Ok(())` if apk succeeded.
+    /// - `Err` if the sandbox or apk fails.
+    fn provision(
+        rootfs: &Path,
+        packages: &[&str],
+        failure_shell: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let apk_command = match (packages.is_empty(), is_offline()) {
+            (true, false) => "apk update".to_string(),
+            (false, false) => format!("apk update && apk add {}", packages.join(" ")),
+            (true, true) => return Ok(()),
+            (false, true) => format!("apk add --no-network {}", packages.join(" ")),
+        };
+
+        let (mut run_cmd, mut args_bind) = with_apk_cache(apk_command, rootfs)?;
+        let emulation = qemu::emulation_args(rootfs)?;
+        if !emulation.is_empty() {
+            args_bind = format!("{args_bind} {emulation}").trim().to_string();
+        }
+        if failure_shell {
+            run_cmd = with_failure_shell(&run_cmd, Path::new("/"));
+        }
+
+        let config = SandBoxConfig {
+            rootfs: rootfs.to_path_buf(),
+            run_cmd,
+            args_bind,
+            use_root: true,
+            ignore_extra_bind: true,
+            ..Default::default()
+        };
+
+        map_result(SandBox::run(config))?;
+        Ok(())
+    }
+
+    /// Reads a package list file, one package per line.
+    ///
+    /// Blank lines and `#` comments, whole-line or trailing, are ignored.
+    /// Each entry is quoted for the shell, so version constraints such as
+    /// `python3>=3.12` are passed to apk as-is.
+    ///
+    /// # Parameters
+    /// - `file`: Path to the package list.
+    ///
+    /// # Returns
+    /// - `Ok(String)` with the quoted packages separated by spaces.
+    /// - `Err` if the file cannot be read.
+    fn read_packages(file: &str) -> Result<String, Box<dyn Error>> {
+        let packages: Vec<String> = fs::read_to_string(file)
+            .map_err(|e| format!("Cannot read packages file '{file}': {e}"))?
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(str::split_whitespace)
+            .map(shell_quote)
+            .collect();
+
+        Ok(packages.join(" "))
+    }
+
+    /// Reads a repositories file, skipping blank lines and comments.
+    ///
+    /// # Parameters
+    /// - `file`: Path to a file in `/etc/apk/repositories` format.
+    ///
+    /// # Returns
+    /// - `Ok(Vec<String>)` with one entry per repository.
+    /// - `Err` if the file cannot be read or lists no repository.
+    fn read_repositories(file: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let repos: Vec<String> = fs::read_to_string(file)
+            .map_err(|e| format!("Cannot read repositories file '{file}': {e}"))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+
+        if repos.is_empty() {
+            return Err(format!("No repositories listed in '{file}'").into());
+        }
+        Ok(repos)
+    }
+
+    /// Returns the directory listing of a mirror release directory.
+    ///
+    /// With `--offline`, a listing of the files cached from that directory
+    /// is built instead, so the newest cached tarball is selected.
+    ///
+    /// # Parameters
+    /// - `url`: The release directory, as returned by [`Mirror::get_mirror`].
+    /// - `cache_dir`: The download cache.
+    fn release_listing(url: &str, cache_dir: &Path) -> Result<String, Box<dyn Error>> {
+        if !is_offline() {
+            return Ok(http::get(url).call()?.body_mut().read_to_string()?);
+        }
+
+        let cache = Cache::open(cache_dir.to_path_buf())?;
+        Ok(cache
+            .names_from(url)
+            .iter()
+            .map(|name| format!("<a href=\"{name}\">{name}</a>\n"))
+            .collect())
+    }
+
+    /// Finds the release branch the repositories of an extracted rootfs point to.
+    ///
+    /// # Parameters
+    /// - `rootfs`: The environment directory containing `rootfs`.
+    ///
+    /// # Returns
+    /// - `Some(release)` for the first repository naming a branch, edge or latest-stable.
+    /// - `None` if the repositories file is missing or names none.
+    fn repositories_release(rootfs: &Path) -> Option<String> {
+        let repos = fs::read_to_string(rootfs.join("rootfs/etc/apk/repositories")).ok()?;
+        repos
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split('/'))
+            .find_map(|segment| parse_release(segment).ok())
+    }
+
+    /// Finds the latest minirootfs tarball in a mirror directory listing.
+    ///
+    /// # Parameters
+    /// - `listing`: The HTML listing of the release directory.
+    /// - `arch`: The target architecture of the rootfs.
+    ///
+    /// # Returns
+    /// - `Some((version, link))` for the highest version found.
+    /// - `None` if no minirootfs is published for this architecture.
+    fn find_latest(listing: &str, arch: &str) -> Option<(String, String)> {
+        let document = Html::parse_document(listing);
+        let selector = Selector::parse("a").ok()?;
+
+        let pattern = format!(
+            r"^alpine-minirootfs-([\w.\-]+)-{}\.tar\.gz$",
+            regex::escape(arch)
+        );
+        let re = Regex::new(&pattern).ok()?;
+
+        let mut matches = vec![];
+        for element in document.select(&selector) {
+            if let Some(href) = element.value().attr("href") {
+                if let Some(caps) = re.captures(href) {
+                    let version_str = &caps[1];
+                    if let Some(key) = Self::parse_version_key(version_str) {
+                        matches.push((key, version_str.to_string(), href.to_string()));
+                    }
+                }
+            }
+        }
+
+        matches.sort_by(|a, b| a.0.cmp(&b.0));
+        matches.pop().map(|(_, version, link)| (version, link))
+    }
+
     /// Parses a version string into a `VersionKey` struct.
     ///
     /// # Arguments
@@ -188,3 +777,37 @@ impl Setup {
         })
     }
 }
+
+/// Runs a setup hook on the host.
+///
+/// The command runs with `sh -c` and receives the stage in `ALPACK_HOOK`
+/// and the environment directory in `ALPACK_ROOTFS`.
+///
+/// # Parameters
+/// - `stage`: The hook name, e.g. `post_install`.
+/// - `command`: The configured command; nothing runs if it is empty.
+/// - `rootfs`: The environment directory being set up.
+///
+/// # Returns
+/// - `Ok(())` if the hook is not configured or exits successfully.
+/// - `Err` if it cannot be started or fails, aborting the setup.
+fn run_hook(stage: &str, command: &str, rootfs: &Path) -> Result<(), Box<dyn Error>> {
+    if command.trim().is_empty() {
+        return Ok(());
+    }
+
+    println!("Running {stage} hook");
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("ALPACK_HOOK", stage)
+        .env("ALPACK_ROOTFS", rootfs)
+        .status()
+        .map_err(|e| format!("Cannot run the {stage} hook: {e}"))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("The {stage} hook failed ({status})").into())
+    }
+}
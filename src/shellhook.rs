@@ -0,0 +1,229 @@
+//! Shell integration for project directories.
+//!
+//! `shell-hook <SHELL>` prints a hook to evaluate from the shell startup
+//! file. Whenever the working directory changes, the hook asks ALPack for
+//! the project context: a directory (or parent) holding `alpack.toml`
+//! selects that configuration file and one holding `.alpack` uses it as
+//! rootfs. Projects only print a hint until they are allowed with
+//! `shell-hook allow`; inside an allowed project, commands missing on the
+//! host are run with `alpack run` in the project rootfs.
+
+use crate::settings::settings_state_dir;
+use crate::utils::shell_quote;
+use sandbox_utils::{app_name, invalid_arg, missing_arg};
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File listing the allowed project directories, in the per-user state directory.
+const ALLOW_FILE: &str = "hook-allow";
+
+/// Controller for the `shell-hook` subcommand.
+pub struct ShellHook {
+    /// Arguments captured after the `shell-hook` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl ShellHook {
+    /// Creates a new `ShellHook` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        ShellHook { remaining_args }
+    }
+
+    /// Prints the hook for a shell or edits the allow-list.
+    ///
+    /// Supports `bash`, `zsh`, `fish`, `allow [DIR]`, `deny [DIR]` and the
+    /// internal `--env <SHELL>` used by the hook itself.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success.
+    /// - `Err` if the shell is unknown or the allow-list cannot be written.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let args: Vec<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+
+        match args.as_slice() {
+            [] => missing_arg!("shell-hook"),
+            ["--env", shell] => Self::print_env(shell),
+            ["allow", rest @ ..] | ["deny", rest @ ..] if rest.len() <= 1 => {
+                let dir = match rest.first() {
+                    Some(dir) => PathBuf::from(dir),
+                    None => Self::find_project(&env::current_dir()?)
+                        .map(|(dir, _)| dir)
+                        .ok_or(
+                            "No alpack.toml or .alpack found in this directory or its parents",
+                        )?,
+                };
+                Self::set_allowed(&dir.canonicalize()?, args[0] == "allow")
+            }
+            [shell] => Self::print_hook(shell),
+            [_, extra, ..] => invalid_arg!("shell-hook", extra),
+        }
+    }
+
+    /// Prints the hook script for a shell.
+    fn print_hook(shell: &str) -> Result<(), Box<dyn Error>> {
+        let exe = shell_quote(&env::current_exe()?.to_string_lossy());
+
+        let hook = match shell {
+            "bash" => format!(
+                r#"_alpack_hook() {{
+    if [ "$PWD" != "${{_ALPACK_HOOK_DIR-}}" ]; then
+        _ALPACK_HOOK_DIR=$PWD
+        eval "$({exe} shell-hook --env bash)"
+    fi
+}}
+case ";${{PROMPT_COMMAND-}};" in
+    *";_alpack_hook;"*) ;;
+    *) PROMPT_COMMAND="_alpack_hook${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}" ;;
+esac
+command_not_found_handle() {{
+    if [ -n "${{ALPACK_HOOK_ARGS-}}" ]; then
+        eval "{exe} $ALPACK_HOOK_ARGS run -- \"\$@\""
+        return
+    fi
+    printf 'bash: %s: command not found\n' "$1" >&2
+    return 127
+}}"#
+            ),
+            "zsh" => format!(
+                r#"_alpack_hook() {{
+    eval "$({exe} shell-hook --env zsh)"
+}}
+autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _alpack_hook
+_alpack_hook
+command_not_found_handler() {{
+    if [ -n "${{ALPACK_HOOK_ARGS-}}" ]; then
+        eval "{exe} $ALPACK_HOOK_ARGS run -- \"\$@\""
+        return
+    fi
+    print -u2 "zsh: command not found: $1"
+    return 127
+}}"#
+            ),
+            "fish" => format!(
+                r#"function __alpack_hook --on-variable PWD
+    {exe} shell-hook --env fish | source
+end
+__alpack_hook
+function fish_command_not_found
+    if set -q ALPACK_HOOK_ARGS
+        eval {exe} $ALPACK_HOOK_ARGS run -- (string escape -- $argv)
+    else
+        __fish_default_command_not_found_handler $argv
+    end
+end"#
+            ),
+            other => return invalid_arg!("shell-hook", other),
+        };
+
+        println!("{hook}");
+        Ok(())
+    }
+
+    /// Prints the shell code setting the context of the current directory.
+    ///
+    /// `ALPACK_HOOK_ARGS` holds the global options selecting the project
+    /// and is only set for allowed projects; other projects get a hint on
+    /// standard error.
+    fn print_env(shell: &str) -> Result<(), Box<dyn Error>> {
+        let project = Self::find_project(&env::current_dir()?);
+        let allowed = Self::allowed();
+
+        let context = match &project {
+            Some((dir, args)) if allowed.contains(dir) => Some(args.clone()),
+            Some((dir, _)) => {
+                eprintln!(
+                    "alpack: project found in {}. Run '{} shell-hook allow' to run its commands automatically.",
+                    dir.display(),
+                    app_name()
+                );
+                None
+            }
+            None => None,
+        };
+
+        let code = match (shell, context) {
+            ("fish", Some(args)) => format!("set -gx ALPACK_HOOK_ARGS {}", shell_quote(&args)),
+            ("fish", None) => "set -e ALPACK_HOOK_ARGS".to_string(),
+            ("bash" | "zsh", Some(args)) => {
+                format!("export ALPACK_HOOK_ARGS={}", shell_quote(&args))
+            }
+            ("bash" | "zsh", None) => "unset ALPACK_HOOK_ARGS".to_string(),
+            (other, _) => return invalid_arg!("shell-hook", other),
+        };
+
+        println!("{code}");
+        Ok(())
+    }
+
+    /// Finds the project enclosing a directory.
+    ///
+    /// # Returns
+    /// The project directory and the global options selecting its context.
+    fn find_project(start: &Path) -> Option<(PathBuf, String)> {
+        start.ancestors().find_map(|dir| {
+            let config = dir.join("alpack.toml");
+            let rootfs = dir.join(".alpack");
+
+            if config.is_file() {
+                Some((
+                    dir.to_path_buf(),
+                    format!("--config {}", shell_quote(&config.to_string_lossy())),
+                ))
+            } else if rootfs.is_dir() {
+                Some((
+                    dir.to_path_buf(),
+                    format!("--rootfs {}", shell_quote(&rootfs.to_string_lossy())),
+                ))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the location of the allow-list.
+    fn allow_file() -> PathBuf {
+        settings_state_dir().join(ALLOW_FILE)
+    }
+
+    /// Reads the allowed project directories.
+    fn allowed() -> BTreeSet<PathBuf> {
+        fs::read_to_string(Self::allow_file())
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Adds a project directory to the allow-list or removes it.
+    fn set_allowed(dir: &Path, allow: bool) -> Result<(), Box<dyn Error>> {
+        let mut allowed = Self::allowed();
+        if allow {
+            allowed.insert(dir.to_path_buf());
+        } else {
+            allowed.remove(dir);
+        }
+
+        let content: String = allowed
+            .iter()
+            .map(|d| format!("{}\n", d.display()))
+            .collect();
+        let file = Self::allow_file();
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, content)?;
+
+        let state = if allow {
+            "allowed"
+        } else {
+            "no longer allowed"
+        };
+        println!("{} is {state}", dir.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+//! User-mode networking for bwrap sessions.
+//!
+//! `run --publish [IP:]HOST:GUEST[/udp]` runs the session in its own
+//! network namespace, connected to the host by slirp4netns. bwrap reports
+//! the PID of the sandbox through `--info-fd`; slirp4netns then attaches a
+//! tap device to its namespace and the published ports are added through
+//! its API socket. slirp4netns is taken from the `PATH` or downloaded into
+//! the cache, and exits with the session.
+
+use crate::cache::Cache;
+use crate::settings::settings_cache_dir;
+use crate::utils::set_inheritable;
+use serde_json::{json, Value};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Release of the static slirp4netns binaries downloaded when missing.
+const SLIRP_URL: &str =
+    "https://github.com/rootless-containers/slirp4netns/releases/download/v1.3.1";
+
+/// DNS forwarder provided by slirp4netns inside the namespace.
+const SLIRP_DNS: &str = "10.0.2.3";
+
+/// A host port forwarded into the sandbox.
+#[derive(Debug, Clone)]
+pub struct PortMapping {
+    /// `tcp` or `udp`.
+    proto: String,
+    /// Host address to listen on.
+    host_addr: String,
+    /// Host port to listen on.
+    host_port: u16,
+    /// Port inside the sandbox.
+    guest_port: u16,
+}
+
+impl PortMapping {
+    /// Parses a `--publish` value.
+    ///
+    /// # Parameters
+    /// - `spec`: `[IP:]HOST:GUEST[/tcp|/udp]`, e.g. `8080:80` or `127.0.0.1:5353:53/udp`.
+    ///
+    /// # Returns
+    /// - `Ok(PortMapping)` if the value is valid.
+    /// - `Err` describing the expected format otherwise.
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid =
+            || format!("Invalid port mapping '{spec}', expected [IP:]HOST:GUEST[/tcp|/udp]");
+
+        let (ports, proto) = match spec.rsplit_once('/') {
+            Some((ports, proto @ ("tcp" | "udp"))) => (ports, proto),
+            Some(_) => return Err(invalid().into()),
+            None => (spec, "tcp"),
+        };
+
+        let parts: Vec<&str> = ports.rsplitn(3, ':').collect();
+        let (guest, host, addr) = match parts.as_slice() {
+            [guest, host] => (*guest, *host, "0.0.0.0"),
+            [guest, host, addr] => (*guest, *host, *addr),
+            _ => return Err(invalid().into()),
+        };
+
+        Ok(PortMapping {
+            proto: proto.to_string(),
+            host_addr: addr.trim_matches(['[', ']']).to_string(),
+            host_port: host.parse().map_err(|_| invalid())?,
+            guest_port: guest.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Networking of a session with published ports.
+///
+/// Dropping it stops slirp4netns and removes its temporary files.
+pub struct SlirpSession {
+    /// Backend arguments isolating the network and reporting the sandbox PID.
+    pub bwrap_args: String,
+    /// Write end of the pipe bwrap reports the sandbox PID to.
+    _info: OwnedFd,
+    /// Write end of the pipe slirp4netns exits on when closed.
+    _exit: OwnedFd,
+    /// The resolv.conf bound into the sandbox.
+    resolv: PathBuf,
+    /// The API socket of slirp4netns.
+    api_socket: PathBuf,
+}
+
+impl SlirpSession {
+    /// Prepares the network namespace of a session and its port forwarding.
+    ///
+    /// slirp4netns is started in the background once bwrap reports the
+    /// sandbox PID; forwarding errors are reported as warnings.
+    ///
+    /// # Parameters
+    /// - `ports`: The ports to publish.
+    ///
+    /// # Returns
+    /// - `Ok(SlirpSession)` with the bwrap arguments.
+    /// - `Err` if slirp4netns cannot be found or downloaded.
+    pub fn start(ports: Vec<PortMapping>) -> Result<Self, Box<dyn Error>> {
+        let slirp = find_slirp()?;
+        let (info_read, info_write) = pipe()?;
+        let (exit_read, exit_write) = pipe()?;
+        set_inheritable(&info_write)?;
+
+        let tag = std::process::id();
+        let resolv = env::temp_dir().join(format!("alpack-resolv-{tag}"));
+        let api_socket = env::temp_dir().join(format!("alpack-slirp-{tag}.sock"));
+        fs::write(&resolv, format!("nameserver {SLIRP_DNS}\n"))?;
+        let _ = fs::remove_file(&api_socket);
+
+        let api = api_socket.clone();
+        thread::spawn(move || {
+            if let Err(e) = attach(File::from(info_read), exit_read, &slirp, &api, &ports) {
+                eprintln!("\x1b[1;33mWarning\x1b[0m: Port forwarding is not available: {e}");
+            }
+        });
+
+        Ok(SlirpSession {
+            bwrap_args: format!(
+                "--unshare-net --info-fd {} --ro-bind {} /etc/resolv.conf",
+                info_write.as_raw_fd(),
+                resolv.display()
+            ),
+            _info: info_write,
+            _exit: exit_write,
+            resolv,
+            api_socket,
+        })
+    }
+}
+
+impl Drop for SlirpSession {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.resolv);
+        let _ = fs::remove_file(&self.api_socket);
+    }
+}
+
+/// Connects the sandbox namespace once bwrap reports its PID.
+fn attach(
+    info: File,
+    exit: OwnedFd,
+    slirp: &Path,
+    api: &Path,
+    ports: &[PortMapping],
+) -> Result<(), Box<dyn Error>> {
+    let mut report = Vec::new();
+    BufReader::new(info).read_until(b'}', &mut report)?;
+    let report: Value =
+        serde_json::from_slice(&report).map_err(|_| "bwrap did not report the sandbox PID")?;
+    let pid = report["child-pid"]
+        .as_u64()
+        .ok_or("bwrap did not report the sandbox PID")?;
+
+    let (ready_read, ready_write) = pipe()?;
+    set_inheritable(&ready_write)?;
+    set_inheritable(&exit)?;
+
+    let mut child = Command::new(slirp)
+        .args(["--configure", "--mtu=65520", "--disable-host-loopback"])
+        .arg(format!("--ready-fd={}", ready_write.as_raw_fd()))
+        .arg(format!("--exit-fd={}", exit.as_raw_fd()))
+        .arg(format!("--api-socket={}", api.display()))
+        .arg(pid.to_string())
+        .arg("tap0")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    drop(ready_write);
+    drop(exit);
+
+    let mut ready = [0; 1];
+    if File::from(ready_read).read_exact(&mut ready).is_err() || ready[0] != b'1' {
+        child.wait()?;
+        return Err("slirp4netns could not attach to the sandbox".into());
+    }
+
+    for port in ports {
+        add_hostfwd(api, port)?;
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Forwards a host port through the slirp4netns API.
+fn add_hostfwd(api: &Path, port: &PortMapping) -> Result<(), Box<dyn Error>> {
+    let request = json!({
+        "execute": "add_hostfwd",
+        "arguments": {
+            "proto": port.proto,
+            "host_addr": port.host_addr,
+            "host_port": port.host_port,
+            "guest_port": port.guest_port,
+        }
+    });
+
+    let mut stream = UnixStream::connect(api)?;
+    stream.write_all(request.to_string().as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let response: Value = serde_json::from_str(&response)?;
+
+    match response.get("error") {
+        Some(error) => Err(format!(
+            "cannot publish {}:{}: {}",
+            port.host_addr,
+            port.host_port,
+            error["desc"].as_str().unwrap_or("unknown error")
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Creates a close-on-exec pipe.
+///
+/// # Returns
+/// The read and write ends.
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: fds is a writable array of two descriptors, as pipe2 expects.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 succeeded, so both descriptors are open and owned by us.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Finds slirp4netns, downloading the static binary if needed.
+fn find_slirp() -> Result<PathBuf, Box<dyn Error>> {
+    let in_path = env::var_os("PATH").and_then(|path| {
+        env::split_paths(&path)
+            .map(|dir| dir.join("slirp4netns"))
+            .find(|bin| bin.is_file())
+    });
+    if let Some(bin) = in_path {
+        return Ok(bin);
+    }
+
+    let dir = settings_cache_dir().join("slirp4netns");
+    let bin = dir.join("slirp4netns");
+    if bin.is_file() {
+        return Ok(bin);
+    }
+
+    let arch = match env::consts::ARCH {
+        "x86_64" => "x86_64",
+        "aarch64" => "aarch64",
+        "arm" => "armv7l",
+        "powerpc64" => "ppc64le",
+        "riscv64" => "riscv64",
+        "s390x" => "s390x",
+        other => return Err(format!("No slirp4netns binary is published for {other}").into()),
+    };
+
+    let name = format!("slirp4netns-{arch}");
+    let cached = Cache::open(settings_cache_dir())?.fetch(&format!("{SLIRP_URL}/{name}"), &name)?;
+    fs::create_dir_all(&dir)?;
+    fs::copy(&cached, &bin)?;
+    fs::set_permissions(&bin, fs::Permissions::from_mode(0o755))?;
+    Ok(bin)
+}
@@ -0,0 +1,236 @@
+//! Rootfs snapshot module.
+//!
+//! Snapshots are full copies of an environment's `rootfs` directory kept
+//! under `snapshots/<id>` in the environment. They are taken with
+//! `cp -a --reflink=auto`, so on copy-on-write filesystems (Btrfs, XFS)
+//! they share blocks with the live rootfs and are nearly free.
+//!
+//! `setup --snapshot-baseline` also keeps a zstd-compressed archive of the
+//! fresh rootfs, which `restore --baseline` unpacks to reset the
+//! environment to its pristine state without downloading anything.
+
+use crate::envs;
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{check_rootfs_exists, extract_tarball, new_spinner};
+use sandbox_utils::{invalid_arg, parse_value};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tar::Builder;
+
+/// Directory holding the snapshots inside the environment directory.
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Archive of the rootfs as it was right after `setup`.
+const BASELINE_FILE: &str = "baseline.tar.zst";
+
+/// Environment files stored in the baseline next to the rootfs.
+const BASELINE_EXTRAS: [&str; 4] = ["ownership.db", "arch", "release", ".alpack.toml"];
+
+/// Copies the rootfs of an environment into a new snapshot.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+/// - `label`: Prefix of the snapshot ID, e.g. `pre-update`.
+///
+/// # Returns
+/// - `Ok(PathBuf)` with the snapshot directory.
+/// - `Err` if the copy fails.
+pub fn take(rootfs: &Path, label: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let snapshot = rootfs.join(SNAPSHOTS_DIR).join(format!("{label}-{secs}"));
+    fs::create_dir_all(&snapshot)?;
+
+    let spinner = new_spinner("Taking a snapshot of the rootfs")?;
+    let status = Command::new("cp")
+        .arg("-a")
+        .arg("--reflink=auto")
+        .arg(rootfs.join("rootfs"))
+        .arg(&snapshot)
+        .status()?;
+
+    if !status.success() {
+        spinner.finish_with_message("Taking a snapshot of the rootfs: failed");
+        let _ = obliterate::ensure_removed(&snapshot);
+        return Err(format!("Failed to copy the rootfs to {}", snapshot.display()).into());
+    }
+
+    if rootfs.join("ownership.db").is_file() {
+        fs::copy(rootfs.join("ownership.db"), snapshot.join("ownership.db"))?;
+    }
+
+    spinner.finish_with_message("Taking a snapshot of the rootfs: done");
+    Ok(snapshot)
+}
+
+/// Replaces the rootfs of an environment with a snapshot.
+///
+/// The snapshot is moved into place, so it is consumed. The replaced
+/// rootfs is deleted once the swap succeeded.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+/// - `snapshot`: The snapshot directory returned by [`take`].
+///
+/// # Returns
+/// - `Ok(())` once the snapshot is the live rootfs.
+/// - `Err` if the directories cannot be swapped.
+pub fn restore(rootfs: &Path, snapshot: &Path) -> Result<(), Box<dyn Error>> {
+    let live = rootfs.join("rootfs");
+    let replaced = rootfs.join("rootfs.replaced");
+
+    fs::rename(&live, &replaced)?;
+    if let Err(e) = fs::rename(snapshot.join("rootfs"), &live) {
+        fs::rename(&replaced, &live)?;
+        return Err(format!("Failed to restore {}: {e}", snapshot.display()).into());
+    }
+
+    if snapshot.join("ownership.db").is_file() {
+        fs::rename(snapshot.join("ownership.db"), rootfs.join("ownership.db"))?;
+    }
+
+    obliterate::ensure_removed(&replaced)?;
+    remove(snapshot)
+}
+
+/// Deletes a snapshot.
+///
+/// # Parameters
+/// - `snapshot`: The snapshot directory returned by [`take`].
+pub fn remove(snapshot: &Path) -> Result<(), Box<dyn Error>> {
+    obliterate::ensure_removed(snapshot)?;
+    Ok(())
+}
+
+/// Archives the rootfs of an environment as its baseline.
+///
+/// A previous baseline is replaced once the new archive is complete.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+///
+/// # Returns
+/// - `Ok(PathBuf)` with the baseline archive.
+/// - `Err` if the rootfs cannot be archived.
+pub fn take_baseline(rootfs: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let baseline = rootfs.join(BASELINE_FILE);
+    let partial = rootfs.join(format!("{BASELINE_FILE}.part"));
+
+    let spinner = new_spinner("Archiving the baseline rootfs")?;
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        let encoder = zstd::Encoder::new(File::create(&partial)?, 0)?;
+        let mut archive = Builder::new(encoder);
+        archive.follow_symlinks(false);
+        archive.append_dir_all("rootfs", rootfs.join("rootfs"))?;
+
+        for extra in BASELINE_EXTRAS {
+            if rootfs.join(extra).is_file() {
+                archive.append_path_with_name(rootfs.join(extra), extra)?;
+            }
+        }
+        archive.into_inner()?.finish()?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        spinner.finish_with_message("Archiving the baseline rootfs: failed");
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    fs::rename(&partial, &baseline)?;
+    spinner.finish_with_message("Archiving the baseline rootfs: done");
+    Ok(baseline)
+}
+
+/// Replaces the rootfs of an environment with its baseline.
+///
+/// The baseline archive is kept, so it can be restored again.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+///
+/// # Returns
+/// - `Ok(())` once the baseline is the live rootfs.
+/// - `Err` if there is no baseline or it cannot be unpacked.
+pub fn restore_baseline(rootfs: &Path) -> Result<(), Box<dyn Error>> {
+    let baseline = rootfs.join(BASELINE_FILE);
+    if !baseline.is_file() {
+        return Err(format!(
+            "No baseline found in {}. Create one with 'setup --snapshot-baseline'.",
+            rootfs.display()
+        )
+        .into());
+    }
+
+    let unpacked = rootfs.join(SNAPSHOTS_DIR).join("baseline");
+    let _ = obliterate::ensure_removed(&unpacked);
+
+    if let Err(e) = extract_tarball(&baseline, &unpacked, None) {
+        let _ = obliterate::ensure_removed(&unpacked);
+        return Err(e);
+    }
+
+    for extra in BASELINE_EXTRAS.iter().filter(|e| **e != "ownership.db") {
+        if unpacked.join(extra).is_file() {
+            fs::rename(unpacked.join(extra), rootfs.join(extra))?;
+        }
+    }
+    restore(rootfs, &unpacked)
+}
+
+/// Controller for the `restore` subcommand.
+pub struct Restore {
+    /// Arguments captured after the `restore` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Restore {
+    /// Creates a new `Restore` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Restore { remaining_args }
+    }
+
+    /// Resets the rootfs to its baseline.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the rootfs is restored.
+    /// - `Err` if an argument is invalid or there is no baseline.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+        let mut baseline = false;
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                "--baseline" => baseline = true,
+                a if a.starts_with("--name=") => {
+                    rootfs = envs::lookup(&parse_value!("restore", "name", arg)?)?;
+                }
+                "--name" => {
+                    let value = parse_value!("restore", "name", arg, args.pop_front())?;
+                    rootfs = envs::lookup(&value)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("restore", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("restore", "directory", arg, args.pop_front())?.into();
+                }
+                _ => return invalid_arg!("restore", arg),
+            }
+        }
+
+        if !baseline {
+            return Err("Nothing to restore: use 'restore --baseline'".into());
+        }
+
+        check_rootfs_exists(rootfs.clone())?;
+        restore_baseline(&rootfs)?;
+        println!("Rootfs restored to its baseline");
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! Environment statistics module.
+//!
+//! `stats` summarizes a rootfs from the host: how many packages are
+//! installed and why, how much disk the rootfs and the download cache use,
+//! how many packages were built in it and when it was last changed by apk.
+
+use crate::index::load_installed;
+use crate::settings::{settings_cache_dir, settings_rootfs_dir};
+use crate::utils::{check_rootfs_exists, is_json};
+use indicatif::{HumanBytes, HumanDuration};
+use sandbox_utils::{invalid_arg, parse_value};
+use serde_json::json;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Controller for the `stats` subcommand.
+pub struct Stats {
+    /// Arguments captured after the `stats` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Stats {
+    /// Creates a new `Stats` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Stats { remaining_args }
+    }
+
+    /// Collects and prints the statistics of a rootfs.
+    ///
+    /// # Returns
+    /// - `Ok(())` after printing.
+    /// - `Err` if an argument is invalid or the rootfs is missing.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("stats", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("stats", "directory", arg, args.pop_front())?.into();
+                }
+                _ => return invalid_arg!("stats", arg),
+            }
+        }
+
+        check_rootfs_exists(rootfs.clone())?;
+        let root = rootfs.join("rootfs");
+
+        let installed: Vec<String> = load_installed(&root)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+
+        let world: HashSet<String> = fs::read_to_string(root.join("etc/apk/world"))
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|atom| atom.split(['<', '>', '=', '~', '@']).next().unwrap_or(atom))
+            .map(str::to_string)
+            .collect();
+
+        let explicit = installed.iter().filter(|p| world.contains(*p)).count();
+        let rootfs_size = Self::dir_size(&root);
+        let cache_size = Self::dir_size(&settings_cache_dir());
+        let built = Self::count_apks(&rootfs.join("build/packages"));
+
+        let updated = fs::metadata(root.join("lib/apk/db/installed"))
+            .and_then(|m| m.modified())
+            .ok();
+
+        if is_json() {
+            let updated = updated
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            let stats = json!({
+                "rootfs": rootfs,
+                "installed": installed.len(),
+                "explicit": explicit,
+                "dependencies": installed.len() - explicit,
+                "rootfs_size": rootfs_size,
+                "cache_size": cache_size,
+                "built_packages": built,
+                "last_update": updated,
+            });
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+            return Ok(());
+        }
+
+        let updated = match updated.and_then(|t| SystemTime::now().duration_since(t).ok()) {
+            Some(age) => format!("{} ago", HumanDuration(age)),
+            None => "never".to_string(),
+        };
+
+        let rows = [
+            ("rootfs", rootfs.display().to_string()),
+            ("installed", installed.len().to_string()),
+            ("explicit", explicit.to_string()),
+            ("dependencies", (installed.len() - explicit).to_string()),
+            ("rootfs_size", HumanBytes(rootfs_size).to_string()),
+            ("cache_size", HumanBytes(cache_size).to_string()),
+            ("built_packages", built.to_string()),
+            ("last_update", updated),
+        ];
+
+        for (key, value) in rows {
+            println!("{key:<15} {value}");
+        }
+        Ok(())
+    }
+
+    /// Sums the disk usage of a directory tree.
+    ///
+    /// Symbolic links are not followed and hard-linked files (common with
+    /// BusyBox) are counted once.
+    fn dir_size(dir: &Path) -> u64 {
+        let mut seen = HashSet::new();
+        let mut pending = vec![dir.to_path_buf()];
+        let mut total = 0;
+
+        while let Some(path) = pending.pop() {
+            let Ok(meta) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+
+            if meta.is_dir() {
+                pending.extend(
+                    fs::read_dir(&path)
+                        .into_iter()
+                        .flatten()
+                        .flatten()
+                        .map(|e| e.path()),
+                );
+            } else if seen.insert((meta.dev(), meta.ino())) {
+                total += meta.blocks() * 512;
+            }
+        }
+        total
+    }
+
+    /// Counts the `.apk` files below a directory.
+    fn count_apks(dir: &Path) -> usize {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return 0;
+        };
+
+        entries
+            .flatten()
+            .map(|e| e.path())
+            .map(|path| {
+                if path.is_dir() {
+                    Self::count_apks(&path)
+                } else {
+                    usize::from(path.extension().is_some_and(|ext| ext == "apk"))
+                }
+            })
+            .sum()
+    }
+}
@@ -0,0 +1,373 @@
+//! Package tree management module.
+//!
+//! A package tree is a git repository of APKBUILD recipes, such as the
+//! Alpine aports, the Adélie Package Tree or the postmarketOS pmaports.
+//! This module provides the shared `--update/--search/--get` interface for
+//! every tree: the built-in `aports`, `aptree` and `pmaports` presets and
+//! any repository declared under `[trees.<name>]` in the configuration file.
+
+use crate::settings::{settings_output_dir, settings_rootfs_dir, settings_trees};
+use crate::utils;
+use crate::utils::{collect_args, is_json};
+use sandbox_utils::{app_name, invalid_arg, missing_arg, parse_value};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Definition of a package tree repository.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TreeSettings {
+    /// The remote git repository URL.
+    pub url: String,
+    /// Top-level directories (repositories) indexed in the database.
+    pub branches: Vec<String>,
+}
+
+/// Returns the built-in package tree presets.
+///
+/// # Returns
+/// A map of tree names to their definitions.
+pub fn builtin_trees() -> BTreeMap<String, TreeSettings> {
+    let tree = |url: &str, branches: &[&str]| TreeSettings {
+        url: url.to_string(),
+        branches: branches.iter().map(|b| b.to_string()).collect(),
+    };
+
+    BTreeMap::from([
+        (
+            "aports".to_string(),
+            tree(
+                "https://github.com/alpinelinux/aports.git",
+                &["main", "community", "testing"],
+            ),
+        ),
+        (
+            "aptree".to_string(),
+            tree(
+                "https://git.adelielinux.org/adelie/packages.git",
+                &["bootstrap", "experimental", "legacy", "system", "user"],
+            ),
+        ),
+        (
+            "pmaports".to_string(),
+            tree(
+                "https://gitlab.com/postmarketOS/pmaports.git",
+                &[
+                    "cross",
+                    "device",
+                    "extra-repos",
+                    "hybris",
+                    "kde",
+                    "main",
+                    "modem",
+                    "temp",
+                ],
+            ),
+        ),
+    ])
+}
+
+/// Returns every known package tree.
+///
+/// Trees from the configuration file override built-in presets of the
+/// same name, which allows pointing `aports` at an internal fork.
+///
+/// # Returns
+/// A map of tree names to their definitions.
+pub fn all_trees() -> BTreeMap<String, TreeSettings> {
+    let mut trees = builtin_trees();
+    trees.extend(settings_trees());
+    trees
+}
+
+/// Controller for package tree operations.
+pub struct Tree {
+    /// The name of the tree (e.g., "aports").
+    name: String,
+    /// Arguments passed from the CLI for processing.
+    remaining_args: Vec<String>,
+}
+
+impl Tree {
+    /// Creates a new `Tree` instance for the named tree.
+    pub fn new(name: String, remaining_args: Vec<String>) -> Self {
+        Tree {
+            name,
+            remaining_args,
+        }
+    }
+
+    /// Dispatches the generic `tree` subcommand.
+    ///
+    /// The first argument is either `--list`, which prints the known trees,
+    /// or the name of the tree followed by its options.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success.
+    /// - `Err` if no tree is given or the tree operation fails.
+    pub fn dispatch(remaining_args: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let mut args = remaining_args.into_iter();
+
+        match args.next().as_deref() {
+            Some("-l") | Some("--list") => {
+                if is_json() {
+                    println!("{}", serde_json::to_string_pretty(&all_trees())?);
+                    return Ok(());
+                }
+                for (name, tree) in all_trees() {
+                    println!("{name:<12} {} ({})", tree.url, tree.branches.join(", "));
+                }
+                Ok(())
+            }
+            Some(name) => Tree::new(name.to_string(), args.collect()).run(),
+            None => missing_arg!("tree"),
+        }
+    }
+
+    /// Searches every known tree with a local database at once.
+    ///
+    /// Implements `search-tree <PKG...>`: results are labeled with the tree
+    /// they come from. Trees that were never synchronized are skipped with
+    /// a note.
+    ///
+    /// # Returns
+    /// - `Ok(())` if matches were found.
+    /// - `Err` if no package is given, no tree is synchronized, or nothing matches.
+    pub fn search_all(remaining_args: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs_dir = settings_rootfs_dir();
+        let (mut generic, mut pager) = (true, true);
+        let mut pkgs = Vec::new();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                "-S" | "--strict" => generic = false,
+                "--no-pager" => pager = false,
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_value!("search-tree", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir =
+                        parse_value!("search-tree", "directory", arg, args.pop_front())?.into();
+                }
+                a if a.starts_with('-') => return invalid_arg!("search-tree", arg),
+                _ => pkgs.push(arg.to_string()),
+            }
+        }
+
+        if pkgs.is_empty() {
+            return missing_arg!("search-tree");
+        }
+
+        let mut trees = Vec::new();
+        for name in all_trees().into_keys() {
+            migrate_legacy_tree(&rootfs_dir, &name);
+            let db_path = utils::trees_dir().join(format!("{name}-database"));
+            match fs::read_to_string(&db_path) {
+                Ok(content) => trees.push((name, content)),
+                Err(_) if !is_json() => {
+                    eprintln!(
+                        "Skipping '{name}': not synchronized (run '{} {name} -u')",
+                        app_name()
+                    );
+                }
+                Err(_) => {}
+            }
+        }
+
+        if trees.is_empty() {
+            return Err(
+                "No package tree database found. Synchronize one with '<tree> -u' first.".into(),
+            );
+        }
+
+        utils::print_result(&pkgs, &trees, generic, &rootfs_dir.join("rootfs"), pager)
+    }
+
+    /// Executes the tree command logic based on the provided arguments.
+    ///
+    /// The flow includes parsing arguments, optionally updating the local
+    /// repository index, and performing search or fetch operations.
+    ///
+    /// # Performance
+    /// - Uses `VecDeque<&str>` to avoid heap allocations during argument parsing.
+    /// - Implements lazy loading for the database content.
+    ///
+    /// # Returns
+    /// - `Ok(())` on success.
+    /// - `Err` if argument validation, repository setup, or file operations fail.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let name = self.name.as_str();
+        let tree = all_trees().remove(name).ok_or(format!(
+            "Unknown package tree '{name}'.\nDefine it under [trees.{name}] in the configuration file."
+        ))?;
+
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+
+        if args.is_empty() {
+            return missing_arg!(name);
+        }
+
+        let mut rootfs_dir = settings_rootfs_dir();
+        let mut output_dir = settings_output_dir();
+        let (mut s_pkg, mut get_pkg) = (Vec::new(), Vec::new());
+        let (mut update, mut search, mut get, mut generic) = (false, false, false, false);
+        let mut bk = false;
+        let mut pager = true;
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                "-u" | "--update" => (update, bk) = (true, true),
+                "--no-pager" => pager = false,
+                a if a.starts_with("--output=") => {
+                    output_dir = parse_value!(name, "directory", arg)?.into();
+                }
+                "-o" | "--output" => {
+                    output_dir = parse_value!(name, "directory", arg, args.pop_front())?.into();
+                }
+                a if a.starts_with("--search=") => {
+                    (search, bk) = (true, true);
+                    s_pkg.push(parse_value!(name, "package", arg)?);
+                    collect_args(&mut args, &mut s_pkg);
+                }
+                "-s" | "--search" => {
+                    (search, bk, generic) = (true, true, true);
+                    s_pkg.push(parse_value!(name, "package", arg, args.pop_front())?);
+                    collect_args(&mut args, &mut s_pkg);
+                }
+                "-S" | "--strict-search" => {
+                    (search, bk) = (true, true);
+                    s_pkg.push(parse_value!(name, "package", arg, args.pop_front())?);
+                    collect_args(&mut args, &mut s_pkg);
+                }
+                a if a.starts_with("--get=") => {
+                    (get, bk) = (true, true);
+                    get_pkg.push(parse_value!(name, "package", arg)?);
+                    collect_args(&mut args, &mut get_pkg);
+                }
+                "-g" | "--get" => {
+                    (get, bk) = (true, true);
+                    get_pkg.push(parse_value!(name, "package", arg, args.pop_front())?);
+                    collect_args(&mut args, &mut get_pkg);
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs_dir = parse_value!(name, "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs_dir = parse_value!(name, "directory", arg, args.pop_front())?.into();
+                }
+                other => return invalid_arg!(name, other),
+            }
+        }
+
+        if !bk {
+            return missing_arg!(name, essential);
+        }
+
+        migrate_legacy_tree(&rootfs_dir, name);
+
+        if update {
+            let branches: Vec<&str> = tree.branches.iter().map(|b| b.as_str()).collect();
+            utils::update_git_repository(rootfs_dir.clone(), &tree.url, name, &branches)?;
+
+            if !search && !get {
+                return Ok(());
+            }
+        }
+
+        let content = read_database(name)?;
+
+        if search {
+            let root = rootfs_dir.join("rootfs");
+            let trees = [(name.to_string(), content.clone())];
+            utils::print_result(&s_pkg, &trees, generic, &root, pager)?;
+            if !get {
+                return Ok(());
+            }
+        }
+
+        if get {
+            utils::check_rootfs_exists(rootfs_dir.clone())?;
+            utils::download_git_sources_files(rootfs_dir, name, &get_pkg, &content, output_dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Moves a tree synchronized by an older version out of `<rootfs>/build`.
+///
+/// Trees used to be cloned inside each rootfs. A clone or database found
+/// there is moved to the trees directory when that one has none yet;
+/// otherwise, or if the move fails (e.g. across filesystems), a warning
+/// points at the old copy so it can be removed.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory that may hold the old clone.
+/// - `name`: The tree name (e.g., "aports").
+fn migrate_legacy_tree(rootfs: &Path, name: &str) {
+    let trees_dir = utils::trees_dir();
+
+    for entry in [name.to_string(), format!("{name}-database")] {
+        let old = rootfs.join("build").join(&entry);
+        if !old.exists() {
+            continue;
+        }
+
+        let new = trees_dir.join(&entry);
+        let moved = !new.exists()
+            && fs::create_dir_all(&trees_dir)
+                .and_then(|_| fs::rename(&old, &new))
+                .is_ok();
+        if moved {
+            eprintln!("Moved {} to {}", old.display(), new.display());
+        } else {
+            eprintln!(
+                "\x1b[1;33mWarning\x1b[0m: {} is no longer used, package trees are now kept in {}. Remove it to free space.",
+                old.display(),
+                trees_dir.display()
+            );
+        }
+    }
+}
+
+/// Reads the synchronized database of a tree.
+///
+/// # Returns
+/// - `Ok(String)` with the database content.
+/// - `Err` if the tree was never synchronized.
+fn read_database(name: &str) -> Result<String, Box<dyn Error>> {
+    let db_path = utils::trees_dir().join(format!("{name}-database"));
+
+    if !db_path.exists() {
+        return Err(format!(
+            "The {name} database was not found at: {}\nPlease run '{} {name} -u' first to initialize the repository.",
+            db_path.display(), app_name()
+        ).into());
+    }
+
+    Ok(fs::read_to_string(&db_path)?)
+}
+
+/// Checks out packages of a tree for the builder.
+///
+/// # Parameters
+/// - `name`: The tree name (e.g., "pmaports").
+/// - `rootfs`: Path to the root filesystem used to run git.
+/// - `pkgs`: The package names to check out.
+///
+/// # Returns
+/// - `Ok(Vec<PathBuf>)` with the package directories, each holding an APKBUILD.
+/// - `Err` if the tree was never synchronized or a package is not found.
+pub fn checkout(
+    name: &str,
+    rootfs: PathBuf,
+    pkgs: &[String],
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    migrate_legacy_tree(&rootfs, name);
+    let content = read_database(name)?;
+    utils::check_rootfs_exists(rootfs.clone())?;
+    utils::checkout_tree_packages(rootfs, name, pkgs, &content)
+}
@@ -0,0 +1,162 @@
+//! In-place release upgrade of a rootfs.
+//!
+//! `upgrade-rootfs [--to <RELEASE>]` moves an environment to another
+//! Alpine release without reinstalling it: the release in every mirror
+//! line of `/etc/apk/repositories` is replaced, `apk upgrade --available`
+//! brings the packages to the new release and the `release` file of the
+//! environment and its manifest are updated. The rootfs is snapshotted first and restored
+//! if the upgrade fails.
+
+use crate::apk::Apk;
+use crate::envs;
+use crate::manifest::Manifest;
+use crate::mirror::{env_release, parse_release, write_release, Mirror};
+use crate::qemu;
+use crate::settings::{settings_release, settings_rootfs_dir};
+use crate::snapshot;
+use crate::utils::check_rootfs_exists;
+use regex::Regex;
+use sandbox_utils::{invalid_arg, parse_value};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Location of the repositories file inside the rootfs.
+const REPOSITORIES_FILE: &str = "etc/apk/repositories";
+
+/// Controller for the `upgrade-rootfs` subcommand.
+pub struct UpgradeRootfs {
+    /// Arguments captured after the `upgrade-rootfs` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl UpgradeRootfs {
+    /// Creates a new `UpgradeRootfs` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        UpgradeRootfs { remaining_args }
+    }
+
+    /// Upgrades the rootfs to the requested release.
+    ///
+    /// Without `--to`, the configured release (`latest-stable` by default)
+    /// is used.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the rootfs runs the new release.
+    /// - `Err` if the mirror does not serve the release or apk fails, after
+    ///   restoring the previous rootfs.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+        let mut target: Option<String> = None;
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--to=") => {
+                    let value = parse_value!("upgrade-rootfs", "release", arg)?;
+                    target = Some(parse_release(&value)?);
+                }
+                "--to" => {
+                    let value = parse_value!("upgrade-rootfs", "release", arg, args.pop_front())?;
+                    target = Some(parse_release(&value)?);
+                }
+                a if a.starts_with("--name=") => {
+                    rootfs = envs::lookup(&parse_value!("upgrade-rootfs", "name", arg)?)?;
+                }
+                "--name" => {
+                    let value = parse_value!("upgrade-rootfs", "name", arg, args.pop_front())?;
+                    rootfs = envs::lookup(&value)?;
+                }
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("upgrade-rootfs", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs =
+                        parse_value!("upgrade-rootfs", "directory", arg, args.pop_front())?.into();
+                }
+                _ => return invalid_arg!("upgrade-rootfs", arg),
+            }
+        }
+
+        check_rootfs_exists(rootfs.clone())?;
+        if Manifest::read(&rootfs).is_some_and(|m| !m.distro.is_empty()) {
+            return Err("upgrade-rootfs only supports Alpine rootfs".into());
+        }
+
+        let current = env_release(&rootfs);
+        let target = target.unwrap_or_else(settings_release);
+
+        let mut mirror = Mirror::new(None, Some(target.clone()));
+        mirror.set_arch(qemu::env_arch(&rootfs));
+        mirror.run()?;
+        mirror.check()?;
+
+        println!("Upgrading rootfs from {current} to {target}");
+
+        let repo_path = rootfs.join("rootfs").join(REPOSITORIES_FILE);
+        let repos = Self::rewrite_repositories(
+            &fs::read_to_string(&repo_path).unwrap_or_default(),
+            &target,
+        )
+        .unwrap_or_else(|| mirror.get_repository() + "\n");
+
+        let snap = snapshot::take(&rootfs, "pre-upgrade")?;
+        let result = fs::write(&repo_path, &repos)
+            .map_err(|e| e.into())
+            .and_then(|_| Self::upgrade(&rootfs));
+
+        match result {
+            Ok(()) => {
+                snapshot::remove(&snap)?;
+                write_release(&rootfs, &target)?;
+                if let Some(mut manifest) = Manifest::read(&rootfs) {
+                    manifest.release = target.clone();
+                    manifest.write(&rootfs)?;
+                }
+                println!("Rootfs upgraded to {target}");
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: Upgrade to {target} failed, restoring the previous rootfs"
+                );
+                snapshot::restore(&rootfs, &snap)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Replaces the release of every mirror line of a repositories file.
+    ///
+    /// Lines without a release component (local or custom repositories) are
+    /// kept as they are.
+    ///
+    /// # Returns
+    /// - `Some(String)` with the new content.
+    /// - `None` if no line names a release, so the file must be regenerated.
+    fn rewrite_repositories(content: &str, target: &str) -> Option<String> {
+        let re = Regex::new(r"/(v\d+\.\d+|edge|latest-stable)/").ok()?;
+        let mut replaced = false;
+
+        let lines: Vec<String> = content
+            .lines()
+            .map(|line| {
+                if line.trim_start().starts_with('#') || !re.is_match(line) {
+                    return line.to_string();
+                }
+                replaced = true;
+                re.replace(line, format!("/{target}/").as_str())
+                    .into_owned()
+            })
+            .collect();
+
+        replaced.then(|| lines.join("\n") + "\n")
+    }
+
+    /// Upgrades every package to the version of the new repositories.
+    fn upgrade(rootfs: &Path) -> Result<(), Box<dyn Error>> {
+        Apk::new(None, Vec::new(), Some(rootfs.to_path_buf()))
+            .run_apk("apk update && apk upgrade --available && apk --version > /dev/null")
+    }
+}
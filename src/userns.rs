@@ -0,0 +1,93 @@
+//! Subordinate ID mapping for the bwrap backend.
+//!
+//! An unprivileged bwrap sandbox maps a single ID, so every file in the
+//! rootfs ends up owned by the invoking user and changing ownership fails.
+//! With subordinate ID mapping enabled, ALPack re-executes itself inside a
+//! user namespace created by `unshare --map-users=auto --map-groups=auto`,
+//! which maps root to the invoking user and the IDs from 1 up to the
+//! user's `/etc/subuid` and `/etc/subgid` ranges through the setuid
+//! `newuidmap` and `newgidmap` helpers. Files created as other users inside
+//! the rootfs then keep stable, distinct owners on the host.
+
+use crate::utils::{current_uid, in_path};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::process::Command;
+
+/// Variable marking a process that already runs in the mapped namespace.
+const USERNS_VAR: &str = "ALPACK_USERNS";
+
+/// Helpers required for the mapping, shipped with shadow's `uidmap`.
+const HELPERS: [&str; 3] = ["unshare", "newuidmap", "newgidmap"];
+
+/// Returns whether this process already runs in the mapped namespace.
+pub fn in_mapped_namespace() -> bool {
+    env::var_os(USERNS_VAR).is_some()
+}
+
+/// Re-executes ALPack with the given arguments in a mapped user namespace.
+///
+/// # Parameters
+/// - `args`: The original command-line arguments, without the program name.
+///
+/// # Returns
+/// - `Ok(i32)` with the exit code of the re-executed process.
+/// - `Err` if a helper is missing, the user has no subordinate IDs, or
+///   the namespace cannot be created.
+pub fn run_with_subids(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    if let Some(helper) = HELPERS.iter().find(|h| !in_path(h)) {
+        return Err(format!(
+            "Subordinate ID mapping requires '{helper}' (packages util-linux and uidmap/shadow)."
+        )
+        .into());
+    }
+
+    for file in ["/etc/subuid", "/etc/subgid"] {
+        if subid_range(file).is_none() {
+            return Err(format!(
+                "No subordinate ID range for the current user in {file}.\nAdd one with 'usermod --add-subuids 100000-165535 --add-subgids 100000-165535 $USER'."
+            )
+            .into());
+        }
+    }
+
+    let status = Command::new("unshare")
+        .args([
+            "--user",
+            "--map-root-user",
+            "--map-users=auto",
+            "--map-groups=auto",
+            "--",
+        ])
+        .arg(env::current_exe()?)
+        .args(args)
+        .env(USERNS_VAR, "1")
+        .status()
+        .map_err(|e| format!("Failed to run 'unshare': {e}"))?;
+
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Finds the subordinate ID range of the current user in a subid file.
+///
+/// Entries may name the user or its numeric ID.
+///
+/// # Parameters
+/// - `file`: `/etc/subuid` or `/etc/subgid`.
+///
+/// # Returns
+/// The first `(start, count)` range assigned to the user, if any.
+fn subid_range(file: &str) -> Option<(u64, u64)> {
+    let user = env::var("USER").unwrap_or_default();
+    let uid = current_uid().to_string();
+
+    fs::read_to_string(file).ok()?.lines().find_map(|line| {
+        let mut fields = line.trim().split(':');
+        let owner = fields.next()?;
+        let start = fields.next()?.parse().ok()?;
+        let count = fields.next()?.parse().ok()?;
+
+        (owner == user || owner == uid).then_some((start, count))
+    })
+}
@@ -3,22 +3,45 @@
 //! Provides helper methods for path manipulation, environment discovery,
 //! file downloads, and stylized terminal output.
 
-use recursive_copy::{copy_recursive, CopyOptions};
+use crate::http;
+use crate::index::load_index;
+use crate::ownership::OwnershipDb;
+use crate::qemu;
+use crate::settings::{
+    settings_binds, settings_cache_dir, settings_cmd, settings_logs_dir, settings_share_apk_cache,
+    BindSettings, ProotSettings,
+};
+use crate::verify::HashingReader;
+use flate2::read::GzDecoder;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use sandbox_utils::{
     app_name, failed_exist_rootfs, get_cmd_box, RootfsNotFoundError, SandBox, SandBoxConfig,
     SEPARATOR,
 };
+use serde_json::json;
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
 use std::error::Error;
-use std::fs;
-use std::path::PathBuf;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tar::Archive;
+use xz2::read::XzDecoder;
 
 /// Collects positional arguments from the queue until a new flag (starting with '-') is encountered.
 ///
 /// This function is useful for commands that accept multiple values, such as
 /// `aports --get pkg1 pkg2 pkg3 --output /tmp`
-///rootfs.join("build").join(repo_name).display()
 /// # Parameters
 /// * `args`: A mutable reference to the remaining CLI arguments queue.
 /// * `target`: A mutable reference to the `Vec<String>` where collected arguments will be stored.
@@ -33,6 +56,636 @@ pub fn collect_args(args: &mut VecDeque<&str>, target: &mut Vec<String>) {
     }
 }
 
+/// Whether progress output is suppressed (global `--quiet` option).
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Whether results are printed as JSON (global `--json` option).
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Whether network access is forbidden (global `--offline` option).
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Suppresses spinners and progress bars for this invocation.
+pub fn set_quiet() {
+    QUIET.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether progress output is suppressed.
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Switches result output to JSON for this invocation.
+pub fn set_json() {
+    JSON.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether results should be printed as JSON.
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Forbids network access for this invocation, using cached data only.
+pub fn set_offline() {
+    OFFLINE.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether only cached data may be used.
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Creates a progress bar that honors the global `--quiet` option.
+///
+/// # Parameters
+/// - `total`: The length of the bar.
+pub fn new_progress_bar(total: u64) -> ProgressBar {
+    if is_quiet() {
+        ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::hidden())
+    } else {
+        ProgressBar::new(total)
+    }
+}
+
+/// Parses a human-readable size such as `512M`, `4G` or `1024`.
+///
+/// Suffixes `K`, `M`, `G` and `T` are binary multiples and may be followed
+/// by `B` or `iB`. A plain number is interpreted as bytes.
+///
+/// # Parameters
+/// - `value`: The size string to parse.
+///
+/// # Returns
+/// - `Some(u64)` with the size in bytes.
+/// - `None` if the value is not a valid size.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("iB")
+        .or_else(|| value.strip_suffix('B'))
+        .unwrap_or(value);
+
+    let (number, shift) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 10),
+        'M' => (&value[..value.len() - 1], 20),
+        'G' => (&value[..value.len() - 1], 30),
+        'T' => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+
+    number.trim().parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// Parses a human-readable duration such as `90`, `30m` or `2h`.
+///
+/// Suffixes `s`, `m`, `h` and `d` are supported. A plain number is
+/// interpreted as seconds.
+///
+/// # Parameters
+/// - `value`: The duration string to parse.
+///
+/// # Returns
+/// - `Some(u64)` with the duration in seconds.
+/// - `None` if the value is not a valid duration.
+pub fn parse_duration(value: &str) -> Option<u64> {
+    let value = value.trim();
+
+    let (number, unit) = match value.chars().last()?.to_ascii_lowercase() {
+        's' => (&value[..value.len() - 1], 1),
+        'm' => (&value[..value.len() - 1], 60),
+        'h' => (&value[..value.len() - 1], 3600),
+        'd' => (&value[..value.len() - 1], 86400),
+        _ => (value, 1),
+    };
+
+    number.trim().parse::<u64>().ok()?.checked_mul(unit)
+}
+
+/// Quotes a value for safe inclusion in a POSIX shell script.
+///
+/// # Parameters
+/// - `value`: The raw string.
+///
+/// # Returns
+/// The value wrapped in single quotes, with embedded quotes escaped.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Variable marking the ALPack process re-executed in its own process group.
+const PROCESS_GROUP_VAR: &str = "ALPACK_PROCESS_GROUP";
+
+/// Process group receiving the signals relayed by [`relay_signal`].
+static RELAY_PGID: AtomicI32 = AtomicI32::new(0);
+
+/// Signal handler relaying a signal to the whole [`RELAY_PGID`] group.
+extern "C" fn relay_signal(signal: libc::c_int) {
+    let pgid = RELAY_PGID.load(Ordering::SeqCst);
+    if pgid > 0 {
+        // SAFETY: kill is async-signal-safe; the negative PID targets the group.
+        unsafe {
+            libc::kill(-pgid, signal);
+        }
+    }
+}
+
+/// Re-executes ALPack in its own process group, relaying termination signals to it.
+///
+/// The backend started by the re-executed process inherits the group, so
+/// a `SIGINT`, `SIGTERM` or `SIGHUP` received by this process, from the
+/// terminal or from `kill`, reaches proot or bwrap and everything running
+/// in the rootfs. This way an interrupted build does not leave orphaned
+/// compilers behind.
+///
+/// The group is not in the terminal foreground, so this is not suitable
+/// for commands reading from the terminal.
+///
+/// # Returns
+/// - `Ok(None)` in the re-executed process, which carries on with the command.
+/// - `Ok(Some(code))` in the original process, with the exit code of the group leader.
+/// - `Err` if the process cannot be started.
+pub fn in_process_group() -> Result<Option<i32>, Box<dyn Error>> {
+    if env::var_os(PROCESS_GROUP_VAR).is_some() {
+        return Ok(None);
+    }
+
+    let mut child = Command::new(env::current_exe()?)
+        .args(env::args_os().skip(1))
+        .env(PROCESS_GROUP_VAR, "1")
+        .process_group(0)
+        .spawn()?;
+    RELAY_PGID.store(child.id() as i32, Ordering::SeqCst);
+
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: the handler only performs async-signal-safe operations.
+        unsafe {
+            libc::signal(signal, relay_signal as *const () as libc::sighandler_t);
+        }
+    }
+
+    let status = child.wait()?;
+    Ok(Some(status.code().unwrap_or_else(|| {
+        128 + status.signal().unwrap_or_default()
+    })))
+}
+
+/// Parses the value of `--on-failure`.
+///
+/// # Parameters
+/// - `value`: `shell` to open a debug shell, or `abort` (the default).
+///
+/// # Returns
+/// - `Ok(true)` if a debug shell should be opened when the script fails.
+/// - `Err` if the value is unknown.
+pub fn parse_on_failure(value: &str) -> Result<bool, Box<dyn Error>> {
+    match value {
+        "shell" => Ok(true),
+        "abort" => Ok(false),
+        other => {
+            Err(format!("Invalid --on-failure value '{other}' (expected shell or abort)").into())
+        }
+    }
+}
+
+/// Wraps a sandbox script so an interactive shell opens if it fails.
+///
+/// The shell starts in the same sandbox session, with the environment
+/// and working directory the script used, reading from the terminal even
+/// when the script ran as a background job or through `tee`. The original
+/// exit status is kept once the shell is left.
+///
+/// # Parameters
+/// - `run_cmd`: The script to run inside the sandbox.
+/// - `cwd`: The directory the shell starts in.
+///
+/// # Returns
+/// The wrapped script.
+pub fn with_failure_shell(run_cmd: &str, cwd: &Path) -> String {
+    format!(
+        "{{ {run_cmd}
+        }}
+        status=$?
+        if [ $status -ne 0 ]; then
+            trap - INT TERM HUP
+            echo \"Script failed with status $status, opening a debug shell (exit to leave)\" >&2
+            cd {} 2>/dev/null
+            /bin/sh -i < /dev/tty > /dev/tty 2>&1
+        fi
+        exit $status",
+        shell_quote(&cwd.to_string_lossy())
+    )
+}
+
+/// Resolves the log file of a sandboxed session.
+///
+/// An explicit `--log-file` takes precedence; otherwise, when `logs_dir`
+/// is configured, a timestamped file named after the subcommand is used.
+/// The parent directory is created if needed.
+///
+/// # Parameters
+/// - `explicit`: The path given on the command line, if any.
+/// - `name`: The subcommand name used for automatic log files.
+///
+/// # Returns
+/// - `Ok(Some(PathBuf))` with the absolute log path.
+/// - `Ok(None)` if logging is disabled.
+/// - `Err` if the directory cannot be created.
+pub fn resolve_log_file(
+    explicit: Option<PathBuf>,
+    name: &str,
+) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let path = match explicit {
+        Some(path) => path,
+        None => {
+            let dir = settings_logs_dir();
+            if dir.as_os_str().is_empty() {
+                return Ok(None);
+            }
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            dir.join(format!("{name}-{now}.log"))
+        }
+    };
+
+    let path = std::path::absolute(path)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(Some(path))
+}
+
+/// Wraps a sandbox script so its output is also appended to a log file.
+///
+/// Standard output and error are merged and duplicated with `tee`, so the
+/// session still streams to the terminal. The log directory is bound at
+/// the same path inside the sandbox.
+///
+/// # Parameters
+/// - `run_cmd`: The script to run inside the sandbox.
+/// - `log_file`: The absolute host path of the log file.
+///
+/// # Returns
+/// The wrapped script and the backend arguments binding the log directory.
+pub fn with_log_file(run_cmd: &str, log_file: &Path) -> (String, String) {
+    let dir = log_file.parent().unwrap_or(Path::new("/"));
+    let script = format!(
+        "set -o pipefail 2>/dev/null
+        {{ {run_cmd}
+        }} 2>&1 | tee -a {}",
+        shell_quote(&log_file.to_string_lossy())
+    );
+    (script, bind_args(dir, dir))
+}
+
+/// Exit code used when a command exceeds its time limit, as in coreutils `timeout`.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Error returned when a sandboxed command exceeds its time limit.
+#[derive(Debug)]
+pub struct TimeoutError(pub u64);
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Command timed out after {} seconds.", self.0)
+    }
+}
+
+impl Error for TimeoutError {}
+
+/// Error carrying the non-zero exit status of a sandboxed command.
+///
+/// The command has already reported its own failure, so `main` exits with
+/// the status without printing anything else.
+#[derive(Debug)]
+pub struct ExitStatusError(pub i32);
+
+impl fmt::Display for ExitStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Command exited with status {}.", self.0)
+    }
+}
+
+impl Error for ExitStatusError {}
+
+/// Turns the exit status of a sandboxed command into a result.
+///
+/// # Parameters
+/// - `code`: The exit status returned by the sandbox.
+///
+/// # Returns
+/// - `Ok(())` if the command succeeded.
+/// - `Err(ExitStatusError)` with the status otherwise.
+pub fn check_exit_status(code: i32) -> Result<(), Box<dyn Error>> {
+    match code {
+        0 => Ok(()),
+        code => Err(ExitStatusError(code).into()),
+    }
+}
+
+/// Wraps a sandbox script so it is killed after the given number of seconds.
+///
+/// A watchdog polls the script once per second and, when the limit is
+/// reached, kills it together with all of its descendants and exits with
+/// [`TIMEOUT_EXIT_CODE`]; the wrapper then exits with that code, so a
+/// timeout is told apart from the sandbox startup time. The watchdog
+/// ignores `SIGTERM` once it starts killing, so it always reports a kill.
+/// Standard input is kept attached to the script even though it runs as
+/// a background job. Background jobs start with `SIGINT` and `SIGQUIT`
+/// ignored, so the wrapper traps them and stops the script itself.
+///
+/// # Parameters
+/// - `run_cmd`: The script to run inside the sandbox.
+/// - `secs`: The time limit in seconds.
+///
+/// # Returns
+/// The wrapped script, exiting with the status of the original one, or
+/// with [`TIMEOUT_EXIT_CODE`] if it was killed.
+pub fn with_timeout(run_cmd: &str, secs: u64) -> String {
+    format!(
+        "exec 3<&0
+        killtree() {{
+            for c in $(pgrep -P \"$2\"); do killtree \"$1\" \"$c\"; done
+            kill -\"$1\" \"$2\" 2>/dev/null
+        }}
+        ( {run_cmd}
+        ) 0<&3 &
+        pid=$!
+        trap 'killtree TERM $pid; kill $watchdog 2>/dev/null; exit 130' INT
+        trap 'killtree TERM $pid; kill $watchdog 2>/dev/null; exit 131' QUIT
+        (
+            i=0
+            while kill -0 $pid 2>/dev/null; do
+                if [ $i -ge {secs} ]; then
+                    trap '' TERM
+                    killtree KILL $pid
+                    exit {TIMEOUT_EXIT_CODE}
+                fi
+                sleep 1
+                i=$((i + 1))
+            done
+        ) 0</dev/null &
+        watchdog=$!
+        wait $pid
+        status=$?
+        kill $watchdog 2>/dev/null
+        wait $watchdog 2>/dev/null
+        [ $? -eq {TIMEOUT_EXIT_CODE} ] && exit {TIMEOUT_EXIT_CODE}
+        exit $status"
+    )
+}
+
+/// Host terminal variables forwarded into sandbox sessions.
+const TERMINAL_VARS: [&str; 2] = ["TERM", "COLORTERM"];
+
+/// Returns the host terminal variables to forward into the sandbox.
+///
+/// Without `TERM`, full-screen programs such as vim, htop or less fall back
+/// to a dumb terminal. The window size needs no forwarding: the session
+/// shares the host controlling terminal, so size queries and `SIGWINCH`
+/// reach the sandboxed programs directly.
+///
+/// # Returns
+/// The `(name, value)` pairs of the variables set on the host.
+pub fn terminal_env() -> Vec<(String, String)> {
+    TERMINAL_VARS
+        .iter()
+        .filter_map(|key| Some((key.to_string(), env::var(key).ok()?)))
+        .collect()
+}
+
+/// Host variables forwarded by `run --preserve-env` without a list, in
+/// addition to every `LC_*` locale variable.
+const PRESERVED_VARS: [&str; 13] = [
+    "LANG",
+    "LANGUAGE",
+    "TZ",
+    "SSH_AUTH_SOCK",
+    "http_proxy",
+    "https_proxy",
+    "ftp_proxy",
+    "no_proxy",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "FTP_PROXY",
+    "NO_PROXY",
+    "ALL_PROXY",
+];
+
+/// Returns the host variables to forward for `run --preserve-env`.
+///
+/// # Parameters
+/// - `names`: The comma-separated variable names, or `None` for the
+///   locale, time zone, SSH agent and proxy variables.
+///
+/// # Returns
+/// The `(name, value)` pairs of the variables set on the host.
+pub fn preserved_env(names: Option<&str>) -> Vec<(String, String)> {
+    match names {
+        Some(names) => names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| Some((name.to_string(), env::var(name).ok()?)))
+            .collect(),
+        None => env::vars()
+            .filter(|(key, _)| key.starts_with("LC_") || PRESERVED_VARS.contains(&key.as_str()))
+            .collect(),
+    }
+}
+
+/// Parses a `KEY=VALUE` variable given on the command line.
+///
+/// A bare `KEY` forwards the host value of the variable, and is skipped if
+/// the host does not set it.
+///
+/// # Parameters
+/// - `spec`: The variable as given by the user.
+///
+/// # Returns
+/// The `(name, value)` pair, or `None` for an unset host variable.
+pub fn parse_env_var(spec: &str) -> Option<(String, String)> {
+    match spec.split_once('=') {
+        Some((key, value)) => Some((key.to_string(), value.to_string())),
+        None => Some((spec.to_string(), env::var(spec).ok()?)),
+    }
+}
+
+/// Reads the variables of an env file.
+///
+/// Each line holds a `KEY=VALUE` pair, optionally prefixed with `export`;
+/// blank lines and `#` comments are ignored and a value wrapped in single
+/// or double quotes is unquoted.
+///
+/// # Parameters
+/// - `path`: The env file.
+///
+/// # Returns
+/// - `Ok(Vec<(String, String)>)` with the variables in file order.
+/// - `Err` if the file cannot be read or a line is not a `KEY=VALUE` pair.
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read env file '{}': {e}", path.display()))?;
+    let mut vars = Vec::new();
+
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, value) = line.split_once('=').ok_or(format!(
+            "{}:{}: expected KEY=VALUE",
+            path.display(),
+            n + 1
+        ))?;
+
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q))
+            .unwrap_or(value);
+        vars.push((key.trim().to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Prefixes a sandbox command with `export` statements for the given variables.
+///
+/// When `run_cmd` is empty the session would start the default shell, so
+/// an explicit `exec /bin/sh` is used to keep the exported variables.
+///
+/// # Parameters
+/// - `env`: The `(name, value)` pairs to export.
+/// - `run_cmd`: The command to run inside the sandbox.
+///
+/// # Returns
+/// - `Ok(String)` with the resulting script.
+/// - `Err` if a variable name is not a valid shell identifier.
+pub fn with_env(env: &[(String, String)], run_cmd: String) -> Result<String, Box<dyn Error>> {
+    if env.is_empty() {
+        return Ok(run_cmd);
+    }
+
+    let mut script = String::new();
+    for (key, value) in env {
+        let valid = key
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !valid {
+            return Err(format!("Invalid environment variable name: '{key}'").into());
+        }
+        script.push_str(&format!("export {key}={}\n", shell_quote(value)));
+    }
+
+    if run_cmd.is_empty() {
+        script.push_str("exec /bin/sh");
+    } else {
+        script.push_str(&run_cmd);
+    }
+    Ok(script)
+}
+
+/// Checks whether a program is available in the host `PATH`.
+pub fn in_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Lets a file descriptor be inherited by spawned programs.
+///
+/// Descriptors opened by the standard library are close-on-exec; this
+/// clears the flag so a backend such as bwrap can be given the descriptor
+/// number.
+///
+/// # Parameters
+/// - `fd`: The descriptor, which must stay open while it is used.
+pub fn set_inheritable(fd: &impl AsRawFd) -> io::Result<()> {
+    let fd = fd.as_raw_fd();
+    // SAFETY: fcntl on an open descriptor only reads or changes its flags.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    // SAFETY: as above; only the close-on-exec flag is cleared.
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns the effective user ID of this process.
+pub fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() }
+}
+
+/// Expansion assumed for archives whose unpacked size is not recorded.
+const UNPACK_RATIO: u64 = 4;
+
+/// Free space kept beyond the unpacked archive, for the packages installed afterwards.
+const SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+
+/// Returns the space available to unprivileged users on the filesystem of a path.
+///
+/// The nearest existing ancestor is queried, so the path itself may not exist yet.
+pub fn free_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let c_path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+
+    // SAFETY: statvfs is plain old data, for which all-zero bytes are valid.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL-terminated string and stat a writable statvfs.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Estimates the unpacked size of a compressed archive.
+///
+/// gzip records the uncompressed size (modulo 4 GiB) in its trailer; for
+/// other formats the archive size is multiplied by a typical ratio.
+///
+/// # Parameters
+/// - `tarball`: The archive file.
+pub fn unpacked_size(tarball: &Path) -> io::Result<u64> {
+    let mut file = File::open(tarball)?;
+    let len = file.metadata()?.len();
+
+    let mut magic = [0; 2];
+    if len > 18 && file.read_exact(&mut magic).is_ok() && magic == [0x1f, 0x8b] {
+        let mut isize = [0; 4];
+        file.seek(SeekFrom::End(-4))?;
+        file.read_exact(&mut isize)?;
+        return Ok(u32::from_le_bytes(isize) as u64);
+    }
+    Ok(len * UNPACK_RATIO)
+}
+
+/// Aborts before extraction when the target filesystem is too small.
+///
+/// # Parameters
+/// - `dest`: The extraction directory.
+/// - `needed`: The expected unpacked size, in bytes.
+///
+/// # Returns
+/// - `Ok(())` if enough space is available or it cannot be queried.
+/// - `Err` stating the needed and available space otherwise.
+pub fn check_free_space(dest: &Path, needed: u64) -> Result<(), Box<dyn Error>> {
+    let needed = needed + SPACE_MARGIN;
+    match free_space(dest) {
+        Some(available) if available < needed => Err(format!(
+            "Not enough disk space to extract the rootfs into '{}':\n  needed    {}\n  available {}\nFree some space or use --rootfs to pick another filesystem.",
+            dest.display(),
+            HumanBytes(needed),
+            HumanBytes(available)
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
 /// Verifies that the specified rootfs directory exists and is accessible.
 ///
 /// # Parameters
@@ -68,58 +721,357 @@ pub fn map_result<T>(result: Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Err
     })
 }
 
-/// Matches packages against the database content and prints a standardized result box.
+/// Matches packages against tree databases and prints them as a table.
 ///
-/// This function internalizes the search logic by invoking the `collect_matches!` macro.
-/// It aggregates results from the provided database content based on the given package keys.
+/// Matching paths (`<repo>/<package>/<file>`) are grouped by package and
+/// printed in aligned columns with the version from the rootfs package
+/// index when it is available. When several trees are searched, a column
+/// labels the tree of each result. On a terminal the result is shown
+/// through `$PAGER` (`less -FRX` by default), which exits at once for
+/// short lists.
 ///
 /// # Parameters
 /// - `pkgs`: A slice of strings containing the package names or patterns to search for.
-/// - `content`: The raw string content of the database file to be scanned.
+/// - `trees`: `(tree name, database content)` pairs to scan.
+/// - `generic`: Match any path containing a term instead of package names.
+/// - `root`: The extracted rootfs whose package index provides versions.
+/// - `pager`: Whether the output may be paged.
 ///
 /// # Returns
 /// - `Ok(())` if matches were found and successfully printed to stdout.
 /// - `Err` if the search result is empty or if the UI box generation fails.
-pub fn print_result(pkgs: &[String], content: &str, generic: bool) -> Result<(), Box<dyn Error>> {
-    let mut all_matches = Vec::new();
+pub fn print_result(
+    pkgs: &[String],
+    trees: &[(String, String)],
+    generic: bool,
+    root: &Path,
+    pager: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut rows: BTreeSet<(&str, &str, &str)> = BTreeSet::new();
 
-    if generic {
-        for term in pkgs {
-            let matches = collect_generic_matches(term, content);
-            all_matches.extend(matches);
+    for (tree, content) in trees {
+        let mut matches = Vec::new();
+        if generic {
+            for term in pkgs {
+                matches.extend(collect_generic_matches(term, content));
+            }
+        } else {
+            matches.extend(collect_unique_pkgs(pkgs, content));
         }
-    } else {
-        let matches = collect_unique_pkgs(pkgs, content);
-        all_matches.extend(matches);
+
+        rows.extend(matches.into_iter().map(
+            |line| match line.split('/').collect::<Vec<_>>()[..] {
+                [repo, pkg, _, ..] => (tree.as_str(), repo, pkg),
+                _ => (tree.as_str(), "", line),
+            },
+        ));
     }
 
-    if all_matches.is_empty() {
+    if rows.is_empty() {
         return Err(format!("{u}\nResult not found!\n{u}", u = SEPARATOR).into());
     }
 
-    let mut sorted_matches: Vec<&str> = all_matches.into_iter().collect();
-    sorted_matches.sort();
-    sorted_matches.dedup();
+    let versions: HashMap<String, String> = load_index(root)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|e| (e.name, e.version))
+        .collect();
+
+    if is_json() {
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|(tree, repo, pkg)| {
+                json!({ "tree": tree, "repo": repo, "package": pkg, "version": versions.get(*pkg) })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
 
-    let result_output = sorted_matches.join("\n");
+    let labeled = trees.len() > 1;
+    let tree_width = rows
+        .iter()
+        .map(|(t, _, _)| t.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let repo_width = rows
+        .iter()
+        .map(|(_, r, _)| r.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let pkg_width = rows
+        .iter()
+        .map(|(_, _, p)| p.len())
+        .max()
+        .unwrap_or(0)
+        .max(7);
+    let tree_col = |tree: &str| {
+        if labeled {
+            format!("{tree:<tree_width$}  ")
+        } else {
+            String::new()
+        }
+    };
 
-    println!(
-        "{u}\n{}\n{result_output}\n{u}",
+    let mut output = format!(
+        "{u}\n{}\n{}{:<repo_width$}  {:<pkg_width$}  VERSION\n",
         get_cmd_box("SEARCH RESULT:", None, Some(18))?,
+        tree_col("TREE"),
+        "REPO",
+        "PACKAGE",
         u = SEPARATOR
     );
+    for (tree, repo, pkg) in &rows {
+        let version = versions.get(*pkg).map(String::as_str).unwrap_or("-");
+        output.push_str(&format!(
+            "{}{repo:<repo_width$}  {pkg:<pkg_width$}  {version}\n",
+            tree_col(tree)
+        ));
+    }
+    output.push_str(&format!("{} package(s)\n{SEPARATOR}", rows.len()));
 
+    if pager && io::stdout().is_terminal() {
+        page_output(&output)
+    } else {
+        println!("{output}");
+        Ok(())
+    }
+}
+
+/// Shows text through the user's pager.
+///
+/// `$PAGER` is run through the shell; without it `less -FRX` is used,
+/// which prints short texts directly. The text is printed as is if the
+/// pager cannot be started.
+///
+/// # Parameters
+/// - `text`: The text to display.
+pub fn page_output(text: &str) -> Result<(), Box<dyn Error>> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| "less -FRX".to_string());
+
+    let child = Command::new("sh")
+        .args(["-c", &pager])
+        .stdin(Stdio::piped())
+        .spawn();
+
+    let Ok(mut child) = child else {
+        println!("{text}");
+        return Ok(());
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may exit before reading everything (e.g. 'q' in less).
+        let _ = writeln!(stdin, "{text}");
+    }
+    child.wait()?;
     Ok(())
 }
 
-/// Sets up a local repository database within the rootfs.
+/// Returns the directory holding package tree clones and databases.
+///
+/// Trees live in the cache directory so they survive rootfs reinstalls
+/// and can be shared by several rootfs environments.
+///
+/// # Returns
+/// A `PathBuf` to `<cache_dir>/trees`.
+pub fn trees_dir() -> PathBuf {
+    settings_cache_dir().join("trees")
+}
+
+/// Returns the host directory holding the shared apk package cache.
+///
+/// Packages are kept per architecture, since each one has its own set of
+/// repositories and binaries.
+///
+/// # Parameters
+/// - `rootfs`: The environment directory containing `rootfs`.
+///
+/// # Returns
+/// A `PathBuf` to `<cache_dir>/apk/<arch>`, for the architecture of the rootfs.
+pub fn apk_cache_dir(rootfs: &Path) -> PathBuf {
+    settings_cache_dir()
+        .join("apk")
+        .join(qemu::env_arch(rootfs))
+}
+
+/// Prepares an apk command to use the shared host package cache.
+///
+/// When `share_apk_cache` is enabled, the host cache directory is bound to
+/// `/var/cache/apk` and the `/etc/apk/cache` link that turns on apk's
+/// package caching is created if missing. Otherwise the command is
+/// returned unchanged.
+///
+/// # Parameters
+/// - `run_cmd`: The apk script to run inside the sandbox.
+/// - `rootfs`: The environment directory containing `rootfs`.
+///
+/// # Returns
+/// - `Ok((script, bind))` with the script and the backend bind arguments.
+/// - `Err` if the cache directory cannot be created.
+pub fn with_apk_cache(run_cmd: String, rootfs: &Path) -> Result<(String, String), Box<dyn Error>> {
+    if !settings_share_apk_cache() {
+        return Ok((run_cmd, String::new()));
+    }
+
+    let dir = apk_cache_dir(rootfs);
+    fs::create_dir_all(&dir)?;
+
+    let script = format!(
+        "[ -e /etc/apk/cache ] || ln -s /var/cache/apk /etc/apk/cache
+{run_cmd}"
+    );
+    Ok((script, bind_args(&dir, Path::new("/var/cache/apk"))))
+}
+
+/// Builds the proot arguments for the `[proot]` options.
+///
+/// Returns nothing for bwrap, which already kills the sandbox with its
+/// parent and shares the host's IPC namespace.
+///
+/// # Parameters
+/// - `opts`: The proot options to apply.
+/// - `rootfs`: The rootfs directory, required by the `-S` option.
+///
+/// # Returns
+/// The proot arguments, separated by spaces.
+pub fn proot_options(opts: &ProotSettings, rootfs: &Path) -> String {
+    if settings_cmd() == "bwrap" {
+        return String::new();
+    }
+
+    let mut args = Vec::new();
+    if opts.kill_on_exit {
+        args.push("--kill-on-exit".to_string());
+    }
+    if opts.sysvipc {
+        args.push("--sysvipc".to_string());
+    }
+    if opts.su_bindings {
+        args.push(format!("-S {}", rootfs.join("rootfs").display()));
+    }
+    args.join(" ")
+}
+
+/// Builds the backend arguments binding a host path into the sandbox.
+///
+/// # Parameters
+/// - `src`: The host path.
+/// - `dst`: The mount point inside the sandbox.
+///
+/// # Returns
+/// A `String` with `--bind src dst` for bwrap or `-b src:dst` for proot.
+pub fn bind_args(src: &Path, dst: &Path) -> String {
+    if settings_cmd() == "bwrap" {
+        format!("--bind {} {}", src.display(), dst.display())
+    } else {
+        format!("-b {}:{}", src.display(), dst.display())
+    }
+}
+
+/// Builds the backend arguments binding a host path read-only.
+///
+/// proot has no read-only binds, so the path stays writable with it.
+///
+/// # Parameters
+/// - `src`: The host path.
+/// - `dst`: The mount point inside the sandbox.
+pub fn ro_bind_args(src: &Path, dst: &Path) -> String {
+    if settings_cmd() == "bwrap" {
+        format!("--ro-bind {} {}", src.display(), dst.display())
+    } else {
+        format!("-b {}:{}", src.display(), dst.display())
+    }
+}
+
+/// Builds the backend arguments for the binds declared under `[binds]`.
+///
+/// # Returns
+/// A `String` with the bind arguments, empty if none are configured.
+pub fn configured_binds() -> String {
+    binds_args(&settings_binds())
+}
+
+/// Builds the backend arguments for a set of configured binds.
+///
+/// Host paths that do not exist are skipped with a warning.
+///
+/// # Parameters
+/// - `binds`: The bind definitions keyed by host path.
+pub fn binds_args(binds: &BTreeMap<String, BindSettings>) -> String {
+    binds
+        .iter()
+        .filter_map(|(src, bind)| {
+            let src = Path::new(src);
+            if !src.exists() {
+                eprintln!(
+                    "\x1b[1;33mWarning\x1b[0m: Configured bind {} does not exist, skipping it.",
+                    src.display()
+                );
+                return None;
+            }
+
+            let dest = if bind.dest.as_os_str().is_empty() {
+                src
+            } else {
+                bind.dest.as_path()
+            };
+            Some(if bind.read_only {
+                ro_bind_args(src, dest)
+            } else {
+                bind_args(src, dest)
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Builds the backend arguments binding a host device node or directory.
+///
+/// # Parameters
+/// - `dev`: The device path, bound at the same place in the sandbox.
+pub fn dev_bind_args(dev: &Path) -> String {
+    if settings_cmd() == "bwrap" {
+        format!("--dev-bind {} {}", dev.display(), dev.display())
+    } else {
+        format!("-b {}", dev.display())
+    }
+}
+
+/// Number of attempts made for each network git operation.
+const GIT_ATTEMPTS: u32 = 4;
+
+/// Shell function retrying a command with exponential backoff (2s, 4s, 8s...).
+///
+/// Used by the in-sandbox git scripts, so transient network failures do
+/// not abort a synchronization or leave a partial clone behind.
+fn git_retry_function() -> String {
+    format!(
+        "retry() {{
+            n=1; delay=2
+            until \"$@\"; do
+                [ $n -ge {GIT_ATTEMPTS} ] && return 1
+                echo \"git failed (attempt $n/{GIT_ATTEMPTS}), retrying in ${{delay}}s\" >&2
+                sleep $delay; n=$((n + 1)); delay=$((delay * 2))
+            done
+        }}"
+    )
+}
+
+/// Sets up a local repository database in the trees directory.
 ///
-/// This function ensures the build directory exists, clones the remote
+/// This function ensures the trees directory exists, clones the remote
 /// repository using a blobless filter (`tree:0`) to save bandwidth, and
 /// generates a flattened database file by filtering specific branches.
+/// Network operations are retried with backoff, and the database must
+/// not be empty for the synchronization to succeed.
 ///
 /// # Parameters
-/// - `rootfs_dir`: Path to the root filesystem host directory.
+/// - `rootfs_dir`: Path to the root filesystem used to run git.
 /// - `url`: The remote Git repository URL.
 /// - `repo`: The local name for the repository (e.g., "aports").
 /// - `branches`: A list of branch names or paths to include in the database.
@@ -133,7 +1085,7 @@ pub fn update_git_repository(
     repo: &str,
     branches: &[&str],
 ) -> Result<(), Box<dyn Error>> {
-    let build_dir = rootfs_dir.join("build");
+    let build_dir = trees_dir();
     let build_path = build_dir.join(repo);
     let database_path = build_dir.join(format!("{repo}-database"));
 
@@ -145,38 +1097,53 @@ pub fn update_git_repository(
     let filter = branches.join("|");
     let cmd_script = format!(
         "type git > /dev/null || apk add git
+        {retry}
+        clone() {{ rm -rf {repo} && git clone -q --depth=1 --filter=tree:0 --no-checkout {url} {repo}; }}
         cd {}
-        git clone --depth=1 --filter=tree:0 --no-checkout {url} {repo} && \
+        retry clone && \
         cd {repo} && \
-        git fetch --depth=1 --filter=tree:0 && \
+        retry git fetch -q --depth=1 --filter=tree:0 && \
         git ls-tree -r HEAD --name-only | grep -E \"({filter})\" > ../{repo}-database",
         build_dir.display(),
+        retry = git_retry_function(),
     );
 
     let config = SandBoxConfig {
-        rootfs: rootfs_dir.into(),
+        rootfs: rootfs_dir,
         run_cmd: cmd_script,
+        args_bind: bind_args(&build_dir, &build_dir),
         use_root: true,
         ignore_extra_bind: true,
         ..Default::default()
     };
 
-    map_result(SandBox::run(config))?;
-    Ok(())
+    run_with_spinner(&format!("Synchronizing the {repo} repository"), config)?;
+
+    match fs::metadata(&database_path) {
+        Ok(meta) if meta.len() > 0 => Ok(()),
+        _ => {
+            let _ = fs::remove_file(&database_path);
+            Err(format!(
+                "The {repo} database is empty: no file of {url} matches the branches ({filter})"
+            )
+            .into())
+        }
+    }
 }
 
 /// Orchestrates the selective retrieval of package sources from a git repository.
 ///
 /// It processes match results to identify relevant package directories,
 /// configures Git's sparse-checkout to download only those specific paths,
-/// and copies the resulting files to the final output destination.
+/// and copies each package directory into its own subdirectory of the
+/// final output destination.
 ///
 /// # Parameters
-/// - `rootfs`: Path to the root filesystem host directory.
-/// - `repo_name`: The subdirectory name within `/build/` (e.g., "aports").
+/// - `rootfs`: Path to the root filesystem used to run git.
+/// - `repo_name`: The subdirectory name within the trees directory (e.g., "aports").
 /// - `pkgs`: A slice of strings containing the package names to be retrieved.
 /// - `content`: The raw string content of the database file.
-/// - `output`: The destination directory for the retrieved files.
+/// - `output`: The directory receiving one subdirectory per package.
 ///
 /// # Returns
 /// - `Ok(())` if all package files were retrieved and copied.
@@ -188,6 +1155,30 @@ pub fn download_git_sources_files(
     content: &str,
     output: PathBuf,
 ) -> Result<(), Box<dyn Error>> {
+    for dir in checkout_tree_packages(rootfs, repo_name, pkgs, content)? {
+        let name = dir.file_name().ok_or("Invalid package directory")?;
+        copy_dir_recursive(&dir, &output.join(name))?;
+    }
+    Ok(())
+}
+
+/// Checks out the package directories of a tree with a sparse checkout.
+///
+/// # Parameters
+/// - `rootfs`: Path to the root filesystem used to run git.
+/// - `repo_name`: The subdirectory name within the trees directory (e.g., "aports").
+/// - `pkgs`: A slice of strings containing the package names to be retrieved.
+/// - `content`: The raw string content of the database file.
+///
+/// # Returns
+/// - `Ok(Vec<PathBuf>)` with the checked-out package directories.
+/// - `Err` if no matches are found or if the sparse-checkout process fails.
+pub fn checkout_tree_packages(
+    rootfs: PathBuf,
+    repo_name: &str,
+    pkgs: &[String],
+    content: &str,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
     let matches = collect_unique_pkgs(pkgs, content);
 
     if matches.is_empty() {
@@ -201,39 +1192,265 @@ pub fn download_git_sources_files(
         .collect();
 
     let pkg_dirs_vec: Vec<&str> = pkg_dirs.into_iter().collect();
+    let repo_dir = trees_dir().join(repo_name);
 
     let run_cmd = format!(
-        "cd {}
+        "{}
+        cd {}
         git sparse-checkout init --cone && \
         git sparse-checkout set {} && \
-        git checkout",
-        rootfs.join("build").join(repo_name).display(),
+        retry git checkout",
+        git_retry_function(),
+        repo_dir.display(),
         pkg_dirs_vec.join(" "),
     );
 
     let config = SandBoxConfig {
-        rootfs: rootfs.clone(),
+        rootfs,
         run_cmd,
+        args_bind: bind_args(&trees_dir(), &trees_dir()),
         use_root: true,
         ignore_extra_bind: true,
         ..Default::default()
     };
 
-    map_result(SandBox::run(config))?;
+    run_with_spinner("Checking out package sources", config)?;
 
-    let options = CopyOptions {
-        overwrite: true,
-        follow_symlinks: true,
-        ..Default::default()
-    };
+    Ok(pkg_dirs_vec.iter().map(|dir| repo_dir.join(dir)).collect())
+}
 
-    for dir in pkg_dirs_vec {
-        copy_recursive(
-            &rootfs.join("build").join(repo_name).join(dir),
-            &output,
-            &options,
-        )?;
+/// Runs a sandboxed operation while displaying a phase spinner.
+///
+/// Long-running silent steps such as key generation or git synchronization
+/// would otherwise give no feedback for minutes. Commands streaming their
+/// own output must not use it, as the spinner redraws over their lines.
+/// The spinner ticks in the background and is finished with the outcome of
+/// the operation.
+///
+/// # Parameters
+/// - `message`: Description of the phase shown next to the spinner.
+/// - `config`: The sandbox configuration to execute.
+///
+/// # Returns
+/// - `Ok(())` if the sandboxed command succeeds.
+/// - `Err` if the execution fails.
+pub fn run_with_spinner(message: &str, config: SandBoxConfig) -> Result<(), Box<dyn Error>> {
+    let spinner = new_spinner(message)?;
+    let result = map_result(SandBox::run(config));
+
+    match result {
+        Ok(_) => spinner.finish_with_message(format!("{message}: done")),
+        Err(_) => spinner.finish_with_message(format!("{message}: failed")),
+    }
+    result.map(|_| ())
+}
+
+/// Creates a ticking spinner displaying the given message.
+///
+/// # Parameters
+/// - `message`: Description of the phase shown next to the spinner.
+///
+/// # Returns
+/// - `Ok(ProgressBar)` already ticking in the background.
+/// - `Err` if the progress template is invalid.
+pub fn new_spinner(message: &str) -> Result<ProgressBar, Box<dyn Error>> {
+    if is_quiet() {
+        return Ok(ProgressBar::hidden());
+    }
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(ProgressStyle::with_template(
+        "{spinner:.cyan} {msg} [{elapsed}]",
+    )?);
+    spinner.set_message(message.to_string());
+    spinner.enable_steady_tick(Duration::from_millis(120));
+    Ok(spinner)
+}
+
+/// Recursively copies a directory tree while reporting progress.
+///
+/// All entries are collected first so the progress bar can show the total
+/// number of files and bytes. Symbolic links are followed and existing
+/// files in the destination are overwritten.
+///
+/// # Parameters
+/// - `src`: The source directory.
+/// - `dst`: The destination directory, created if missing.
+///
+/// # Returns
+/// - `Ok(())` if every entry was copied.
+/// - `Err` if reading the source or writing the destination fails.
+pub fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<(), Box<dyn Error>> {
+    let mut entries = Vec::new();
+    collect_entries(src, Path::new(""), &mut entries)?;
+
+    let total_files = entries.iter().filter(|(_, len)| len.is_some()).count();
+    let total_bytes: u64 = entries.iter().filter_map(|(_, len)| *len).sum();
+
+    let bar = new_progress_bar(total_bytes);
+    bar.set_style(
+        ProgressStyle::with_template("{msg} [{bar:30.cyan/blue}] {bytes}/{total_bytes}")?
+            .progress_chars("=> "),
+    );
+
+    fs::create_dir_all(dst)?;
+
+    let mut copied = 0;
+    for (rel, len) in &entries {
+        let target = dst.join(rel);
+        match len {
+            None => fs::create_dir_all(&target)?,
+            Some(len) => {
+                copied += 1;
+                bar.set_message(format!("Copying {copied}/{total_files} files"));
+                fs::copy(src.join(rel), &target)?;
+                bar.inc(*len);
+            }
+        }
     }
+
+    bar.finish_and_clear();
+    Ok(())
+}
+
+/// Walks a directory tree collecting relative paths and file sizes.
+///
+/// Directories are recorded with `None` before their contents so they can
+/// be created in order.
+///
+/// # Parameters
+/// - `root`: The directory being walked.
+/// - `rel`: The current path relative to `root`.
+/// - `entries`: Accumulator of `(relative path, size)` pairs.
+fn collect_entries(
+    root: &Path,
+    rel: &Path,
+    entries: &mut Vec<(PathBuf, Option<u64>)>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(root.join(rel))? {
+        let path = rel.join(entry?.file_name());
+        let meta = fs::metadata(root.join(&path))?;
+
+        if meta.is_dir() {
+            entries.push((path.clone(), None));
+            collect_entries(root, &path, entries)?;
+        } else {
+            entries.push((path, Some(meta.len())));
+        }
+    }
+    Ok(())
+}
+
+/// Downloads a rootfs archive and unpacks it on the fly.
+///
+/// The HTTP body is piped through the matching decompressor straight into the tar
+/// unpacker, so no tarball is written to the cache and extraction
+/// overlaps with the transfer.
+///
+/// # Parameters
+/// - `url`: The archive URL.
+/// - `dest`: The directory where the archive contents are unpacked.
+/// - `owners`: Database recording the archived ownership, if tracked.
+///
+/// # Returns
+/// - `Ok(String)` with the SHA-256 digest of the archive once it was fully
+///   downloaded and unpacked.
+/// - `Err` if the request, decompression, or unpacking fails.
+pub fn stream_extract(
+    url: &str,
+    dest: &Path,
+    owners: Option<&mut OwnershipDb>,
+) -> Result<String, Box<dyn Error>> {
+    let response = http::get(url).call()?;
+    if let Some(len) = response.body().content_length() {
+        check_free_space(dest, len * UNPACK_RATIO)?;
+    }
+    fs::create_dir_all(dest)?;
+
+    let spinner = new_spinner("Downloading and extracting rootfs")?;
+    let body = response.into_body().into_reader();
+    let mut body = HashingReader::new(body);
+    let mut archive = Archive::new(decompressor(&mut body)?);
+    archive.set_preserve_permissions(true);
+    archive.set_overwrite(true);
+
+    match owners {
+        Some(db) => db.unpack(&mut archive, dest)?,
+        None => archive.unpack(dest)?,
+    }
+
+    // Drain the end of the stream (compression trailer, tar padding) before hashing.
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    io::copy(&mut body, &mut io::sink())?;
+
+    spinner.finish_with_message("Downloading and extracting rootfs: done");
+    Ok(body.digest())
+}
+
+/// Wraps a tar stream with the decompressor matching its magic bytes.
+///
+/// gzip, xz and zstd are recognized; anything else is read as a plain tar.
+///
+/// # Parameters
+/// - `reader`: The possibly compressed archive.
+///
+/// # Returns
+/// - `Ok(Box<dyn Read>)` yielding the tar stream.
+/// - `Err` if the header cannot be read or the decoder fails to start.
+pub fn decompressor<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>, Box<dyn Error>> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+
+    Ok(if magic.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(reader))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(XzDecoder::new_multi_decoder(reader))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Extracts a cached rootfs archive (`.tar.gz`, `.tar.xz`, `.tar.zst`) into a directory.
+///
+/// The file is decompressed and unpacked as a stream, so memory use stays
+/// constant whatever the archive size. Progress is reported from the
+/// compressed bytes read.
+///
+/// # Parameters
+/// - `tarball`: The archive to extract.
+/// - `dest`: The directory where the archive contents are unpacked.
+/// - `owners`: Database recording the archived ownership, if tracked.
+///
+/// # Returns
+/// - `Ok(())` if the archive was fully unpacked.
+/// - `Err` if reading, decompression, or unpacking fails.
+pub fn extract_tarball(
+    tarball: &Path,
+    dest: &Path,
+    owners: Option<&mut OwnershipDb>,
+) -> Result<(), Box<dyn Error>> {
+    check_free_space(dest, unpacked_size(tarball)?)?;
+    fs::create_dir_all(dest)?;
+
+    let file = File::open(tarball)?;
+    let bar = new_progress_bar(file.metadata()?.len());
+    bar.set_style(
+        ProgressStyle::with_template("Extracting [{bar:30.cyan/blue}] {bytes}/{total_bytes}")?
+            .progress_chars("=> "),
+    );
+
+    let mut archive = Archive::new(decompressor(bar.wrap_read(file))?);
+    archive.set_preserve_permissions(true);
+    archive.set_overwrite(true);
+
+    match owners {
+        Some(db) => db.unpack(&mut archive, dest)?,
+        None => archive.unpack(dest)?,
+    }
+
+    bar.finish_and_clear();
     Ok(())
 }
 
@@ -0,0 +1,184 @@
+//! Integrity checks for release downloads.
+//!
+//! Alpine publishes a `.sha256` checksum and a `.asc` detached signature
+//! next to every release archive. The checksum is always verified; the
+//! signature is verified with `gpg` when it is installed, against the
+//! Alpine release key pinned by fingerprint, in a throwaway keyring so
+//! the user's own keyring is left untouched.
+
+use crate::cache::hex_digest;
+use crate::http;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Location of the key signing Alpine releases.
+const ALPINE_KEY_URL: &str = "https://alpinelinux.org/keys/ncopa.asc";
+
+/// Fingerprint of the key signing Alpine releases.
+const ALPINE_KEY_FPR: &str = "0482D84022F52DF1C4E7CD43293ACD0907D9495A";
+
+/// Reader computing the SHA-256 digest of everything read through it.
+pub struct HashingReader<R> {
+    /// The wrapped reader.
+    inner: R,
+    /// Digest of the bytes read so far.
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    /// Wraps a reader.
+    pub fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Returns the hex digest of the bytes read so far.
+    pub fn digest(&self) -> String {
+        hex_digest(&self.hasher.clone().finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Fetches the published SHA-256 checksum of a release file.
+///
+/// # Parameters
+/// - `url`: The URL of the release file; `.sha256` is appended.
+///
+/// # Returns
+/// - `Ok(String)` with the lowercase hex digest.
+/// - `Err` if the checksum file is missing or malformed.
+pub fn fetch_sha256(url: &str) -> Result<String, Box<dyn Error>> {
+    let content = http::get(&format!("{url}.sha256"))
+        .call()
+        .map_err(|e| format!("Cannot fetch the checksum of {url}: {e}"))?
+        .body_mut()
+        .read_to_string()?;
+
+    content
+        .split_whitespace()
+        .next()
+        .filter(|hash| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase)
+        .ok_or_else(|| format!("Malformed checksum file for {url}").into())
+}
+
+/// Compares a digest with the published one.
+///
+/// # Parameters
+/// - `name`: The file name, for the error message.
+/// - `expected`: The published digest.
+/// - `actual`: The digest of the downloaded data.
+///
+/// # Returns
+/// - `Ok(())` if both digests are equal.
+/// - `Err` describing the mismatch otherwise.
+pub fn check_sha256(name: &str, expected: &str, actual: &str) -> Result<(), Box<dyn Error>> {
+    if expected == actual {
+        return Ok(());
+    }
+
+    Err(format!(
+        "Checksum mismatch for {name}:\n  expected {expected}\n  got      {actual}\nThe download is corrupted or was tampered with."
+    )
+    .into())
+}
+
+/// Verifies the detached signature of a release file.
+///
+/// Skipped with a warning when `gpg` is not installed, as the checksum
+/// was already verified.
+///
+/// # Parameters
+/// - `url`: The URL of the release file; `.asc` is appended.
+/// - `file`: The downloaded release file.
+///
+/// # Returns
+/// - `Ok(())` if the file is signed by the Alpine release key.
+/// - `Err` if the signature or the key cannot be fetched, or does not match.
+pub fn verify_signature(url: &str, file: &Path) -> Result<(), Box<dyn Error>> {
+    let has_gpg = Command::new("gpg")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success());
+
+    if !has_gpg {
+        eprintln!(
+            "\x1b[1;33mWarning\x1b[0m: gpg is not installed, the signature of {} was not verified.",
+            file_name(url)
+        );
+        return Ok(());
+    }
+
+    let home = env::temp_dir().join(format!("alpack-gpg-{}", std::process::id()));
+    fs::create_dir_all(&home)?;
+    fs::set_permissions(&home, fs::Permissions::from_mode(0o700))?;
+    let result = gpg_verify(url, file, &home);
+    let _ = fs::remove_dir_all(&home);
+    result
+}
+
+/// Imports the release key into a keyring at `home` and checks the signature.
+fn gpg_verify(url: &str, file: &Path, home: &Path) -> Result<(), Box<dyn Error>> {
+    let fetch = |url: &str| -> Result<String, Box<dyn Error>> {
+        Ok(http::get(url)
+            .call()
+            .map_err(|e| format!("Cannot fetch {url}: {e}"))?
+            .body_mut()
+            .read_to_string()?)
+    };
+
+    let key = home.join("alpine.asc");
+    let sig = home.join("release.asc");
+    fs::write(&key, fetch(ALPINE_KEY_URL)?)?;
+    fs::write(&sig, fetch(&format!("{url}.asc"))?)?;
+
+    let gpg = |args: &[&OsStr]| {
+        Command::new("gpg")
+            .arg("--homedir")
+            .arg(home)
+            .args(["--batch", "--quiet", "--status-fd", "1"])
+            .args(args)
+            .stderr(Stdio::null())
+            .output()
+    };
+
+    gpg(&[OsStr::new("--import"), key.as_os_str()])?;
+    let output = gpg(&[OsStr::new("--verify"), sig.as_os_str(), file.as_os_str()])?;
+
+    let valid = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with("[GNUPG:] VALIDSIG "))
+        .any(|line| line.split_whitespace().any(|fpr| fpr == ALPINE_KEY_FPR));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Signature verification failed for {}: it is not signed by the Alpine release key ({ALPINE_KEY_FPR}).",
+            file_name(url)
+        )
+        .into())
+    }
+}
+
+/// Returns the last path segment of a URL.
+fn file_name(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
@@ -0,0 +1,214 @@
+//! Command lookup inside the rootfs.
+//!
+//! `which` resolves commands against the default Alpine `PATH` directly
+//! from the host, following symbolic links inside the rootfs (such as
+//! BusyBox applets), and reports the owning package from the apk database
+//! without starting a sandbox.
+
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{check_rootfs_exists, is_json};
+use sandbox_utils::{invalid_arg, missing_arg, parse_value};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// The default `PATH` of an Alpine root login shell.
+const ALPINE_PATH: [&str; 6] = [
+    "/usr/local/sbin",
+    "/usr/local/bin",
+    "/usr/sbin",
+    "/usr/bin",
+    "/sbin",
+    "/bin",
+];
+
+/// A resolved command.
+#[derive(Serialize, Debug)]
+struct Resolved {
+    /// The command name as given.
+    command: String,
+    /// The location found in `PATH`, as seen inside the rootfs.
+    path: String,
+    /// The final target when `path` is a symbolic link.
+    target: Option<String>,
+    /// The package owning the file, as `name-version`.
+    package: Option<String>,
+}
+
+/// Controller for the `which` subcommand.
+pub struct Which {
+    /// Arguments captured after the `which` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl Which {
+    /// Creates a new `Which` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        Which { remaining_args }
+    }
+
+    /// Resolves every given command and prints where it comes from.
+    ///
+    /// # Returns
+    /// - `Ok(())` if all commands were found.
+    /// - `Err` if the rootfs is missing or a command is not found.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let mut rootfs = settings_rootfs_dir();
+        let mut commands = Vec::new();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("which", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("which", "directory", arg, args.pop_front())?.into();
+                }
+                a if a.starts_with('-') => return invalid_arg!("which", arg),
+                _ => commands.push(arg),
+            }
+        }
+
+        if commands.is_empty() {
+            return missing_arg!("which");
+        }
+
+        check_rootfs_exists(rootfs.clone())?;
+        let root = rootfs.join("rootfs");
+        let owners = fs::read_to_string(root.join("lib/apk/db/installed")).unwrap_or_default();
+
+        let mut resolved = Vec::new();
+        let mut missing = Vec::new();
+
+        for cmd in commands {
+            let Some(path) = Self::find(&root, cmd) else {
+                missing.push(cmd);
+                continue;
+            };
+
+            let target = Self::resolve(&root, &path)
+                .and_then(|p| {
+                    p.strip_prefix(&root)
+                        .ok()
+                        .map(|p| format!("/{}", p.display()))
+                })
+                .filter(|t| *t != path);
+
+            let package = Self::owner(&owners, &path)
+                .or_else(|| target.as_deref().and_then(|t| Self::owner(&owners, t)));
+
+            resolved.push(Resolved {
+                command: cmd.to_string(),
+                path,
+                target,
+                package,
+            });
+        }
+
+        if is_json() {
+            println!("{}", serde_json::to_string_pretty(&resolved)?);
+        } else {
+            for r in &resolved {
+                let target = r.target.as_ref().map(|t| format!(" -> {t}"));
+                let package = r.package.as_deref().unwrap_or("not owned by any package");
+                println!("{}{} ({package})", r.path, target.unwrap_or_default());
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(format!("Command not found in rootfs: {}", missing.join(", ")).into());
+        }
+        Ok(())
+    }
+
+    /// Finds a command in the default Alpine `PATH` of the rootfs.
+    ///
+    /// # Parameters
+    /// - `root`: The extracted rootfs on the host.
+    /// - `cmd`: The command name.
+    ///
+    /// # Returns
+    /// The location of the command as seen inside the rootfs, if found.
+    pub fn find(root: &Path, cmd: &str) -> Option<String> {
+        ALPINE_PATH
+            .iter()
+            .map(|dir| format!("{dir}/{cmd}"))
+            .find(|path| Self::resolve(root, path).is_some_and(|p| p.is_file()))
+    }
+
+    /// Resolves a path inside the rootfs, following symbolic links.
+    ///
+    /// Absolute link targets are interpreted relative to the rootfs, so
+    /// links never escape to the host filesystem.
+    ///
+    /// # Parameters
+    /// - `root`: The extracted rootfs on the host.
+    /// - `path`: An absolute path as seen inside the rootfs.
+    ///
+    /// # Returns
+    /// - `Some(PathBuf)` with the host path of the final target.
+    /// - `None` if the path does not exist or links loop.
+    pub fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+        let mut current = PathBuf::from(path);
+
+        for _ in 0..40 {
+            let host = root.join(current.strip_prefix("/").ok()?);
+            let meta = fs::symlink_metadata(&host).ok()?;
+
+            if !meta.file_type().is_symlink() {
+                return Some(host);
+            }
+
+            let link = fs::read_link(&host).ok()?;
+            let joined = if link.is_absolute() {
+                link
+            } else {
+                current.parent()?.join(link)
+            };
+
+            current = joined.components().fold(PathBuf::from("/"), |mut acc, c| {
+                match c {
+                    Component::ParentDir => {
+                        acc.pop();
+                    }
+                    Component::Normal(part) => acc.push(part),
+                    _ => {}
+                }
+                acc
+            });
+        }
+        None
+    }
+
+    /// Finds the package owning a file in the apk installed database.
+    ///
+    /// # Parameters
+    /// - `db`: The contents of `lib/apk/db/installed`.
+    /// - `path`: An absolute path as seen inside the rootfs.
+    ///
+    /// # Returns
+    /// The owning package as `name-version`, if any.
+    fn owner(db: &str, path: &str) -> Option<String> {
+        let path = path.trim_start_matches('/');
+
+        db.split("\n\n").find_map(|record| {
+            let (mut name, mut version, mut dir) = ("", "", "");
+            let mut owned = false;
+
+            for line in record.lines() {
+                match line.split_once(':') {
+                    Some(("P", v)) => name = v,
+                    Some(("V", v)) => version = v,
+                    Some(("F", v)) => dir = v,
+                    Some(("R", v)) if format!("{dir}/{v}") == path => owned = true,
+                    _ => {}
+                }
+            }
+
+            owned.then(|| format!("{name}-{version}"))
+        })
+    }
+}
@@ -0,0 +1,200 @@
+//! World file editing module.
+//!
+//! `/etc/apk/world` holds the package set the user asked for. This module
+//! edits it from the host without installing anything, so several changes
+//! can be batched and reviewed before `world commit` applies them in a
+//! single apk transaction.
+
+use crate::apk::Apk;
+use crate::index::{load_index, load_installed};
+use crate::settings::settings_rootfs_dir;
+use crate::utils::{check_rootfs_exists, is_json};
+use regex::Regex;
+use sandbox_utils::{invalid_arg, missing_arg, parse_value};
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Location of the world file inside the rootfs.
+const WORLD_FILE: &str = "etc/apk/world";
+
+/// Controller for the `world` subcommand.
+pub struct World {
+    /// Arguments captured after the `world` keyword.
+    remaining_args: Vec<String>,
+}
+
+impl World {
+    /// Creates a new `World` instance with the provided arguments.
+    pub fn new(remaining_args: Vec<String>) -> Self {
+        World { remaining_args }
+    }
+
+    /// Parses the world action and executes it.
+    ///
+    /// Supports `list` (the default), `add <pkg...>`, `remove <pkg...>` and
+    /// `commit`. Only `commit` starts a sandbox.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the action succeeds.
+    /// - `Err` if an argument is invalid, a package is unknown, or apk fails.
+    pub fn run(&self) -> Result<(), Box<dyn Error>> {
+        let mut args: VecDeque<&str> = self.remaining_args.iter().map(|s| s.as_str()).collect();
+        let action = match args.front() {
+            Some(a) if !a.starts_with('-') => args.pop_front(),
+            _ => None,
+        };
+
+        let mut rootfs = settings_rootfs_dir();
+        let mut pkgs = Vec::new();
+        let mut apk_args = Vec::new();
+
+        while let Some(arg) = args.pop_front() {
+            match arg {
+                a if a.starts_with("--rootfs=") => {
+                    rootfs = parse_value!("world", "directory", arg)?.into();
+                }
+                "-R" | "--rootfs" => {
+                    rootfs = parse_value!("world", "directory", arg, args.pop_front())?.into();
+                }
+                "-s" | "--simulate" => apk_args.push("--simulate".to_string()),
+                a if a.starts_with('-') => return invalid_arg!("world", arg),
+                _ => pkgs.push(arg),
+            }
+        }
+
+        check_rootfs_exists(rootfs.clone())?;
+        let root = rootfs.join("rootfs");
+        let mut world = Self::read_world(&root)?;
+
+        match action {
+            None | Some("list") => {
+                if !pkgs.is_empty() {
+                    return invalid_arg!("world", pkgs[0]);
+                }
+                Self::print_world(&root, &world)
+            }
+            Some("add") => {
+                if pkgs.is_empty() {
+                    return missing_arg!("world", essential);
+                }
+                Self::validate(&root, &pkgs)?;
+
+                for pkg in pkgs {
+                    world.retain(|atom| Self::atom_name(atom) != Self::atom_name(pkg));
+                    world.insert(pkg.to_string());
+                    println!("Added '{pkg}' to the world file");
+                }
+                Self::write_world(&root, &world)
+            }
+            Some("del") | Some("remove") => {
+                if pkgs.is_empty() {
+                    return missing_arg!("world", essential);
+                }
+
+                for pkg in pkgs {
+                    let before = world.len();
+                    world.retain(|atom| atom != pkg && Self::atom_name(atom) != pkg);
+                    if world.len() == before {
+                        return Err(format!("'{pkg}' is not in the world file").into());
+                    }
+                    println!("Removed '{pkg}' from the world file");
+                }
+                Self::write_world(&root, &world)
+            }
+            Some("commit") => {
+                if !pkgs.is_empty() {
+                    return invalid_arg!("world", pkgs[0]);
+                }
+
+                Apk::new(Some("fix".into()), apk_args.clone(), Some(rootfs.clone())).run()?;
+
+                let mut add_args = vec!["--upgrade".to_string()];
+                add_args.extend(apk_args);
+                add_args.extend(world);
+                Apk::new(Some("add".into()), add_args, Some(rootfs)).run()
+            }
+            Some(other) => invalid_arg!("world", other),
+        }
+    }
+
+    /// Reads the world file of a rootfs.
+    fn read_world(root: &Path) -> Result<BTreeSet<String>, Box<dyn Error>> {
+        let content = fs::read_to_string(root.join(WORLD_FILE))
+            .map_err(|e| format!("Failed to read {}: {e}", root.join(WORLD_FILE).display()))?;
+        Ok(content.split_whitespace().map(str::to_string).collect())
+    }
+
+    /// Writes the world file in apk's format: one sorted atom per line.
+    fn write_world(root: &Path, world: &BTreeSet<String>) -> Result<(), Box<dyn Error>> {
+        let content: String = world.iter().map(|atom| format!("{atom}\n")).collect();
+        fs::write(root.join(WORLD_FILE), content)?;
+        println!("Run 'world commit' to apply the changes.");
+        Ok(())
+    }
+
+    /// Prints the world entries, marking the ones not installed yet.
+    fn print_world(root: &Path, world: &BTreeSet<String>) -> Result<(), Box<dyn Error>> {
+        let installed: HashSet<String> = load_installed(root)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+
+        if is_json() {
+            println!("{}", serde_json::to_string_pretty(world)?);
+            return Ok(());
+        }
+
+        for atom in world {
+            let pending = !installed.contains(Self::atom_name(atom));
+            println!("{} {atom}", if pending { "+" } else { " " });
+        }
+        Ok(())
+    }
+
+    /// Checks that every atom is well formed and names a known package.
+    ///
+    /// Virtual providers (`so:`, `cmd:`, `pc:`) and tagged atoms cannot be
+    /// checked against the index and are only validated syntactically.
+    /// The index check is skipped with a warning if no index is cached.
+    ///
+    /// # Parameters
+    /// - `root`: The extracted rootfs on the host.
+    /// - `pkgs`: The atoms to validate, e.g. `curl` or `python3>=3.11`.
+    fn validate(root: &Path, pkgs: &[&str]) -> Result<(), Box<dyn Error>> {
+        let re = Regex::new(
+            r"^((so|cmd|pc):)?[A-Za-z0-9_][A-Za-z0-9+_.:-]*(@[A-Za-z0-9_-]+)?([<>=~]{1,2}\S+)?$",
+        )?;
+
+        if let Some(pkg) = pkgs.iter().find(|p| !re.is_match(p)) {
+            return Err(format!("Invalid package atom '{pkg}'").into());
+        }
+
+        let available: HashSet<String> = match load_index(root) {
+            Ok(entries) => entries.into_iter().map(|e| e.name).collect(),
+            Err(e) => {
+                eprintln!("\x1b[1;33mWarning\x1b[0m: Package names not verified. {e}");
+                return Ok(());
+            }
+        };
+
+        let unknown: Vec<&str> = pkgs
+            .iter()
+            .filter(|p| !p.contains(':') && !p.contains('@'))
+            .filter(|p| !available.contains(Self::atom_name(p)))
+            .copied()
+            .collect();
+
+        if !unknown.is_empty() {
+            return Err(format!("Unknown packages: {}", unknown.join(", ")).into());
+        }
+        Ok(())
+    }
+
+    /// Returns the package name of a world atom, without tag or constraint.
+    fn atom_name(atom: &str) -> &str {
+        atom.split(['<', '>', '=', '~', '@']).next().unwrap_or(atom)
+    }
+}
@@ -0,0 +1,161 @@
+//! Windows Subsystem for Linux compatibility.
+//!
+//! Under WSL, ALPack is commonly used as a lightweight Alpine environment,
+//! but some WSL kernels do not allow bwrap to create user namespaces, the
+//! host paths bound by default are often missing, and users naturally pass
+//! Windows paths such as `C:\Users\me\src`. This module detects WSL and
+//! adapts the invocation to these situations.
+
+use crate::utils::bind_args;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// Cached result of the WSL detection.
+static IS_WSL: OnceLock<bool> = OnceLock::new();
+
+/// Host paths the sandbox backend binds unless extra binds are ignored.
+const DEFAULT_BINDS: [&str; 2] = ["/media", "/run/dbus/system_bus_socket"];
+
+/// Returns whether ALPack is running under WSL.
+///
+/// Detection relies on the kernel version string, which mentions
+/// Microsoft on both WSL1 and WSL2 kernels.
+pub fn is_wsl() -> bool {
+    *IS_WSL.get_or_init(|| {
+        fs::read_to_string("/proc/version")
+            .map(|v| {
+                let v = v.to_lowercase();
+                v.contains("microsoft") || v.contains("wsl")
+            })
+            .unwrap_or(false)
+    })
+}
+
+/// Checks whether bwrap can create an unprivileged user namespace.
+///
+/// # Returns
+/// `true` if a trivial sandbox starts and exits successfully.
+pub fn bwrap_usable() -> bool {
+    Command::new("bwrap")
+        .args(["--unshare-user", "--ro-bind", "/", "/", "true"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Returns the binds replacing the backend defaults when some are missing.
+///
+/// The backend binds [`DEFAULT_BINDS`] unconditionally and fails when one
+/// does not exist, which is common under WSL. In that case the defaults
+/// are disabled with `ignore_extra_bind` and the existing ones are bound
+/// through the returned arguments instead.
+///
+/// # Returns
+/// - `Some(String)` with the bind arguments of the existing defaults.
+/// - `None` outside WSL or when every default exists.
+pub fn default_binds() -> Option<String> {
+    let missing = DEFAULT_BINDS.iter().any(|src| !Path::new(src).exists());
+    if !is_wsl() || !missing {
+        return None;
+    }
+
+    Some(
+        DEFAULT_BINDS
+            .iter()
+            .map(Path::new)
+            .filter(|src| src.exists())
+            .map(|src| bind_args(src, src))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Translates the Windows paths among the arguments of an ALPack command.
+///
+/// The argv of a program run in the rootfs is passed unchanged: translation
+/// stops at `--`, and for commands running a program (`run`, `exec`) also
+/// at the first argument that is neither an option nor the value following
+/// one. The `-c`/`--command` value of these commands is kept as well.
+///
+/// # Parameters
+/// - `args`: The arguments following the command.
+/// - `runs_program`: Whether the command takes the argv of a program.
+///
+/// # Returns
+/// The arguments with the Windows paths of ALPack options translated.
+pub fn translate_args(args: Vec<String>, runs_program: bool) -> Vec<String> {
+    if !is_wsl() {
+        return args;
+    }
+
+    let mut translated = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    let mut value_expected = false;
+
+    while let Some(arg) = args.next() {
+        let is_option = arg.starts_with('-');
+        if arg == "--" || (runs_program && !is_option && !value_expected) {
+            translated.push(arg);
+            break;
+        }
+
+        let is_command = matches!(arg.as_str(), "-c" | "--command");
+        if runs_program && (is_command || arg.starts_with("--command=")) {
+            translated.push(arg);
+            if is_command {
+                translated.extend(args.next());
+            }
+            value_expected = false;
+            continue;
+        }
+
+        value_expected = is_option && !arg.contains('=');
+        translated.push(translate_path(arg));
+    }
+
+    translated.extend(args);
+    translated
+}
+
+/// Translates a Windows path argument into its WSL mount point.
+///
+/// Both bare paths (`C:\src`) and inline option values (`--rootfs=C:\src`)
+/// are handled; any other argument, and every argument outside WSL, is
+/// returned unchanged.
+///
+/// # Parameters
+/// - `arg`: A command-line argument.
+///
+/// # Returns
+/// The argument with the Windows path replaced by `/mnt/<drive>/...`.
+pub fn translate_path(arg: String) -> String {
+    if !is_wsl() {
+        return arg;
+    }
+
+    let inline = arg
+        .split_once('=')
+        .filter(|(opt, _)| opt.starts_with("--"))
+        .and_then(|(opt, value)| Some(format!("{opt}={}", windows_to_wsl(value)?)));
+
+    inline.or_else(|| windows_to_wsl(&arg)).unwrap_or(arg)
+}
+
+/// Converts a drive-letter path such as `C:\Users\me` to `/mnt/c/Users/me`.
+fn windows_to_wsl(value: &str) -> Option<String> {
+    let mut chars = value.chars();
+    let drive = chars.next().filter(char::is_ascii_alphabetic)?;
+
+    if chars.next() != Some(':') || !matches!(chars.next(), Some('\\') | Some('/')) {
+        return None;
+    }
+
+    Some(format!(
+        "/mnt/{}/{}",
+        drive.to_ascii_lowercase(),
+        value[3..].replace('\\', "/")
+    ))
+}